@@ -1,14 +1,15 @@
 #!/opt/homebrew/bin/zsh
 
+# shards are kept gzipped, `rplace convert --from csv data/csv <output>` reads them directly
+mkdir -p data/csv
+
 for i in {0..78}
 do
     n=$(printf %02d $i)
     csvfilename="2022_place_canvas_history-0000000000$n.csv"
     gzipfilename="$csvfilename.gzip"
-    gzfilename="$csvfilename.gz"
     url="https://placedata.reddit.com/data/canvas-history/$gzipfilename"
-    downloadfilename="data/$gzfilename"
+    downloadfilename="data/csv/$gzipfilename"
 
     curl $url -o $downloadfilename
-    gunzip $downloadfilename 
 done
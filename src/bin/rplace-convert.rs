@@ -0,0 +1,18 @@
+use rplace::cli;
+
+// same as `rplace convert ...`
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let convert_args = match cli::parse_convert_args(&args) {
+        Ok(convert_args) => convert_args,
+        Err(e) => {
+            println!("Error: {}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        },
+    };
+
+    if let Err(e) = cli::convert(&convert_args) {
+        println!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
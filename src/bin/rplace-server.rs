@@ -0,0 +1,9 @@
+use rocket::{launch, routes};
+use rplace::{index, game};
+
+#[launch]
+fn rocket() -> _ {
+    rocket::build()
+        .mount("/", routes![index])
+        .mount("/game", routes![game])
+}
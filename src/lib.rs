@@ -0,0 +1,37 @@
+//! Reader, writer and viewer for the r/place datasets.
+//!
+//! Raw data (the deephaven parquet file or Reddit's csv dump) is converted into a custom
+//! memory mapped format with [`DatasetWriter`]. [`SerializedDataset`] answers queries about
+//! pixel histories in that format and [`Canvas`] replays it for rendering.
+//!
+//! ```no_run
+//! use rplace::{SerializedDataset, PixelColor};
+//!
+//! let dataset = SerializedDataset::new("data/custom/output_white").unwrap();
+//! let (_, last_timestamp) = dataset.timestamp_range();
+//! let color: PixelColor = dataset.color_at(0, 0, last_timestamp);
+//! println!("(0, 0) ended up {:?} after {} edits", color, dataset.pixel_history(0, 0).len() - 1);
+//! ```
+//!
+//! The `rplace` binary (viewer and converter), `rplace-convert` and `rplace-server` are thin
+//! wrappers around this crate.
+
+mod rplace;
+
+pub use rplace::run_visualizer;
+
+pub use rplace::canvas::{Canvas, CanvasPixel};
+pub use rplace::data::{CanvasDimensions, RPlaceDatapoint, RPlaceDataReader};
+pub use rplace::dataset::RPlaceDatasetDatapoint;
+pub use rplace::error::{BadRecordPolicy, RPlaceError};
+pub use rplace::pixel::PixelColor;
+pub use rplace::reader::csv::{RPlaceCSVDataIterator, RPlaceCSVShardIterator};
+pub use rplace::reader::custom::{DatasetFormatError, DatasetHeader, DatasetLayout, SerializedDataset, SerializedDatapointHistory};
+pub use rplace::reader::parquet::{RPlaceParquetColumn, RPlaceParquetDataReader};
+pub use rplace::writer::DatasetWriter;
+
+// entry points of the binaries in src/main.rs and src/bin, not part of the library API
+#[doc(hidden)]
+pub use rplace::cli;
+#[doc(hidden)]
+pub use rplace::api::{game, index};
@@ -1,15 +1,17 @@
-// use rplace::api::{index, game};
-// use rocket::{launch, routes};
-// mod rplace;
+use rplace::cli;
 
-// #[launch]
-// fn rocket() -> _ {
-//     rocket::build()
-//         .mount("/", routes![index])
-//         .mount("/game", routes![game])
-// }
-
-mod rplace;
 fn main() {
-    rplace::main();
-}
\ No newline at end of file
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match cli::parse_args(&args) {
+        Ok(command) => command,
+        Err(e) => {
+            println!("Error: {}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        },
+    };
+
+    if let Err(e) = cli::run(&command) {
+        println!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -7,16 +7,27 @@ pub mod dataset;
 pub mod display;
 pub mod data;
 pub mod api;
+pub mod writer;
+pub mod cli;
+pub mod users;
+pub mod error;
+pub mod keyframes;
+pub mod tiles;
+pub mod framebuffer;
+pub mod render;
+pub mod timelapse;
+pub mod user_index;
+pub mod inspector;
+pub mod counter;
+pub mod heatmap;
 
 use speedy2d::Window;
 use window::RedditPlaceWindowHandler;
+use reader::custom::DatasetFormatError;
 
-pub fn run_visualizer(file_path: &str, size: usize) {
+// only returns if the dataset can't be loaded
+pub fn run_visualizer(file_path: &str) -> Result<(), DatasetFormatError> {
+    let handler = RedditPlaceWindowHandler::new(file_path)?;
     let window = Window::new_fullscreen_borderless("R/Place Renderer").unwrap();
-    window.run_loop(RedditPlaceWindowHandler::new(file_path, size));
-}
-
-pub fn main() {
-    let file_path = "data/custom/output_white";
-    run_visualizer(file_path, 2000);
+    window.run_loop(handler);
 }
@@ -22,7 +22,7 @@ impl GameDisplay {
         let timestamp = rand::thread_rng().gen_range(min..max);
         
         let file_path = "data/custom/output_white";
-        let mut canvas = Canvas::new_with_file_path(file_path);
+        let mut canvas = Canvas::new_with_file_path(file_path).unwrap();
         canvas.adjust_timestamp(timestamp as i64, 0, canvas.width() as usize, 0, canvas.height() as usize);
 
         let x_start = 0u16;
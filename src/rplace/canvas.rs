@@ -5,14 +5,31 @@ use std::sync::mpsc::{Sender, Receiver, self};
 use std::thread;
 use std::time::{Instant, Duration};
 
-use crate::rplace::data::{MAX_TIMESTAMP, DAY_1_START_TIMESTAMP, MIN_TIMESTAMP};
+use crate::rplace::data::{MAX_TIMESTAMP, MIN_TIMESTAMP, CanvasDimensions};
 
+use super::keyframes::{keyframes_file_path, Keyframes};
+use super::dataset::{RPlaceDatasetDatapoint, SENTINEL_COLOR};
 use super::pixel::PixelColor;
-use super::reader::custom::SerializedDataset;
+use super::reader::custom::{SerializedDataset, DatasetFormatError};
+use super::tiles::TilePyramid;
 use libm::log2;
 use min_max::{max, min};
 use speedy2d::dimen::Vector2;
 
+// Most edits the viewer reads from the edit log when stepping through the edits of the visible 
+// pixels. Small viewports can skip over many edits outside of them, past this it is faster to
+// search the visible pixel histories instead
+const EDIT_LOG_SCAN_LIMIT: usize = 16_000_000;
+
+// A history search reads about as many datapoints as replaying this many edits of the edit log.
+// adjust_timestamp uses it to pick the cheapest way to move a region
+const SEARCH_COST_PER_PIXEL: usize = 8;
+
+// history index of pixels that were set from a keyframe. Keyframes only store colors, so the
+// index is searched when it is needed, see Canvas::history_idx
+pub const UNRESOLVED_HISTORY_IDX: usize = usize::MAX;
+
+/// A pixel of the canvas at the current timestamp.
 #[derive(Debug, Clone, Copy)]
 pub struct CanvasPixel {
     pub color: PixelColor,
@@ -21,6 +38,7 @@ pub struct CanvasPixel {
 }
 
 impl CanvasPixel {
+    /// Pixel with the first datapoint of its history.
     pub fn new(color: PixelColor, timestamp: u64) -> CanvasPixel {
         CanvasPixel {
             color,
@@ -29,6 +47,7 @@ impl CanvasPixel {
         }
     }
 
+    /// Pixel with the first datapoint of its history and a timestamp of 0.
     pub fn new_with_color(color: PixelColor) -> CanvasPixel {
         CanvasPixel {
             color,
@@ -38,6 +57,110 @@ impl CanvasPixel {
     }
 }
 
+// Pixels covered by a run of dirty tiles, in the same x1..x2, y1..y2 form as the regions passed
+// to adjust_timestamp
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRegion {
+    pub x1: usize,
+    pub x2: usize,
+    pub y1: usize,
+    pub y2: usize,
+}
+
+impl DirtyRegion {
+    pub fn num_pixels(&self) -> usize {
+        (self.x2 - self.x1) * (self.y2 - self.y1)
+    }
+}
+
+// side length in pixels of the tiles that dirty pixels are tracked in
+pub const DIRTY_TILE_SIZE: usize = 32;
+
+// Tiles of the canvas that contain pixels whose current datapoint changed. Edits scattered over
+// the canvas only mark the tiles around them instead of one box spanning all of them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirtyTiles {
+    pub width: usize,
+    pub height: usize,
+    pub tiles_width: usize,
+    pub tiles_height: usize,
+    dirty: Vec<bool>,
+}
+
+impl DirtyTiles {
+    pub fn new(width: usize, height: usize) -> DirtyTiles {
+        let tiles_width = width.div_ceil(DIRTY_TILE_SIZE);
+        let tiles_height = height.div_ceil(DIRTY_TILE_SIZE);
+        DirtyTiles {
+            width,
+            height,
+            tiles_width,
+            tiles_height,
+            dirty: vec![false; tiles_width * tiles_height],
+        }
+    }
+
+    pub fn mark_pixel(&mut self, x: usize, y: usize) {
+        self.dirty[(y / DIRTY_TILE_SIZE) * self.tiles_width + x / DIRTY_TILE_SIZE] = true;
+    }
+
+    // both sides have to cover the same canvas
+    pub fn merge(&mut self, other: &DirtyTiles) {
+        for (tile, other_tile) in self.dirty.iter_mut().zip(other.dirty.iter()) {
+            *tile |= *other_tile;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.dirty.contains(&true)
+    }
+
+    pub fn num_tiles(&self) -> usize {
+        self.dirty.iter().filter(|tile| **tile).count()
+    }
+
+    // One region per run of dirty tiles in a row of tiles, clipped to the canvas
+    pub fn regions(&self) -> Vec<DirtyRegion> {
+        let mut regions = Vec::new();
+        for tile_y in 0..self.tiles_height {
+            let row = &self.dirty[tile_y * self.tiles_width..(tile_y + 1) * self.tiles_width];
+            let mut tile_x = 0;
+            while tile_x < self.tiles_width {
+                if !row[tile_x] {
+                    tile_x += 1;
+                    continue;
+                }
+
+                let run_start = tile_x;
+                while tile_x < self.tiles_width && row[tile_x] {
+                    tile_x += 1;
+                }
+                regions.push(DirtyRegion {
+                    x1: run_start * DIRTY_TILE_SIZE,
+                    x2: min!(tile_x * DIRTY_TILE_SIZE, self.width),
+                    y1: tile_y * DIRTY_TILE_SIZE,
+                    y2: min!((tile_y + 1) * DIRTY_TILE_SIZE, self.height),
+                });
+            }
+        }
+        regions
+    }
+}
+
+// How adjust_timestamp moves a region. from..to are positions in the edit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Seek {
+    // search the history of every pixel
+    Search,
+
+    // apply the edits between the current timestamp of the region and the new one
+    Replay { from: usize, to: usize },
+
+    // load the colors of the keyframe before the new timestamp and apply the edits after it
+    Keyframe { keyframe: usize, from: usize, to: usize },
+}
+
+/// The canvas of a dataset at a timestamp, along with the view used to draw it.
 #[derive(Debug)]
 pub struct Canvas {
     pub pixels: Vec<Vec<CanvasPixel>>,
@@ -45,6 +168,15 @@ pub struct Canvas {
     pub min_pixel_size: f32,
     pub top_left: Vector2<f32>,
     pub dataset: SerializedDataset,
+
+    // optional sidecar that far seeks start from, only used with datasets that have an edit log
+    pub keyframes: Option<Keyframes>,
+
+    // downsampled canvas state for zoomed out views, kept in sync by adjust_timestamp
+    pub tiles: TilePyramid,
+
+    // tiles changed by adjust_timestamp since the last take_dirty_tiles
+    pub dirty: DirtyTiles,
     pub min_timestamp: u64,
     pub max_timestamp: u64,
     pub timestamp: u64,
@@ -52,30 +184,53 @@ pub struct Canvas {
 
 // Initialization 
 impl Canvas {
-    pub fn new_with_file_path(file_path: &str) -> Canvas {
-        let dataset = SerializedDataset::new(file_path);
+    /// Opens the dataset at `file_path` and the keyframes next to it, if there are any. All pixels
+    /// start at the first timestamp of the dataset.
+    pub fn new_with_file_path(file_path: &str) -> Result<Canvas, DatasetFormatError> {
+        let dataset = SerializedDataset::new(file_path)?;
         let min_timestamp = dataset.metadata.min_timestamp;
         let max_timestamp = dataset.metadata.max_timestamp;
-        let default_pixel = CanvasPixel::new(PixelColor::White, min_timestamp);
+        let default_pixel = CanvasPixel::new(SENTINEL_COLOR, min_timestamp);
 
         println!("Creating canvas | min_timestamp {} | max_timestamp {} | default_pixel {:?}", 
             min_timestamp, max_timestamp, default_pixel);
 
-        Canvas {
-            // TODO: Replace this with the RPlaceDataset and add another matrix of current frame's indicies 
-            pixels: vec![vec![default_pixel; dataset.metadata.canvas_width as usize]; dataset.metadata.canvas_height as usize],
+        let keyframes_path = keyframes_file_path(file_path);
+        let keyframes = match Keyframes::open(&keyframes_path, &dataset) {
+            Ok(keyframes) => {
+                println!("Loaded {} keyframes from {}", keyframes.len(), keyframes_path);
+                Some(keyframes)
+            },
+            Err(DatasetFormatError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                println!("Ignoring keyframes {}: {}", keyframes_path, e);
+                None
+            },
+        };
+
+        // TODO: Replace this with the RPlaceDataset and add another matrix of current frame's indicies 
+        let pixels = vec![vec![default_pixel; dataset.metadata.canvas_width as usize]; dataset.metadata.canvas_height as usize];
+        let mut tiles = TilePyramid::new(dataset.metadata.canvas_width, dataset.metadata.canvas_height);
+        tiles.update(&pixels, 0, dataset.metadata.canvas_width as usize, 0, dataset.metadata.canvas_height as usize);
+
+        Ok(Canvas {
+            pixels,
             pixel_size: 1.0,
-            min_pixel_size: 0.5,
+            min_pixel_size: 1.0 / 16.0,
             top_left: Vector2::ZERO,
+            tiles,
+            dirty: DirtyTiles::new(dataset.metadata.canvas_width as usize, dataset.metadata.canvas_height as usize),
             dataset,
+            keyframes,
             min_timestamp,
             max_timestamp,
             timestamp: min_timestamp,
-        }
+        })
     }
 }
 
 impl Canvas {
+    /// Moves the region back to the nth distinct timestamp before the current one.
     pub fn prev_nth_pixel_change(&mut self, n: usize, x1: usize, x2: usize, y1: usize, y2: usize) {
         let start_time = Instant::now();
         let mut cache = Vec::new();
@@ -83,7 +238,7 @@ impl Canvas {
         for y in y1..y2 {
             for x in x1..x2 {
                 let current_timestamp = self.pixels[y][x].timestamp;
-                let mut prev_datapoint_history_idx = self.pixels[y][x].datapoint_history_idx as i32;
+                let mut prev_datapoint_history_idx = self.history_idx(x as u32, y as u32) as i32;
                 let mut timestamp = self.dataset.datapoint_timestamp_with_xy_and_idx(x as u32, y as u32, prev_datapoint_history_idx as u32);
 
                 // TODO: shoudn't be using current_timestamp but rather max(self.dataset.datapoint_timestamp_with_xy_and_idx(x as u32, y as u32, prev_datapoint_history_idx as u32)) 
@@ -122,17 +277,28 @@ impl Canvas {
         self.adjust_timestamp(prev_timestamp as i64, x1, x2, y1, y2);
     }
 
-    // TODO: draw only the pixels that changed
+    /// Same as `prev_nth_pixel_change` without caching the timestamps of the region.
     // TODO: use threading to speed up search
     pub fn prev_nth_pixel_change_low_mem(&mut self, n: usize, x1: usize, x2: usize, y1: usize, y2: usize) {
         let start_time = Instant::now();
+        if let Some(result) = self.prev_nth_edit_from_log(n, x1, x2, y1, y2) {
+            match result {
+                Some(prev_timestamp) => {
+                    println!("Found prev nth pixel in edit log: n={} timestamp={} | duration: {:?}", n, prev_timestamp, start_time.elapsed());
+                    self.adjust_timestamp(prev_timestamp as i64, x1, x2, y1, y2);
+                },
+                None => println!("Keeping timestamp the same"),
+            }
+            return;
+        }
+
         let mut total_added = 0;
         let mut cache = Vec::with_capacity(2*n);
         let mut prev_timestamp = MIN_TIMESTAMP;
         for y in y1..y2 {
             for x in x1..x2 {
                 let current_timestamp = self.pixels[y][x].timestamp;
-                let mut prev_datapoint_history_idx = self.pixels[y][x].datapoint_history_idx as i32;
+                let mut prev_datapoint_history_idx = self.history_idx(x as u32, y as u32) as i32;
                 let mut timestamp = self.dataset.datapoint_timestamp_with_xy_and_idx(x as u32, y as u32, prev_datapoint_history_idx as u32);
 
                 // TODO: shoudn't be using current_timestamp but rather max(self.dataset.datapoint_timestamp_with_xy_and_idx(x as u32, y as u32, prev_datapoint_history_idx as u32)) 
@@ -178,6 +344,7 @@ impl Canvas {
         self.adjust_timestamp(prev_timestamp as i64, x1, x2, y1, y2);
     }
 
+    /// Moves the region forward to the nth distinct timestamp after the current one.
     pub fn next_nth_pixel_change(&mut self, n: usize,  x1: usize, x2: usize, y1: usize, y2: usize) {
         let start_time = Instant::now();
         let mut cache = Vec::new();
@@ -185,7 +352,7 @@ impl Canvas {
         for y in y1..y2 {
             for x in x1..x2 {
                 let max_datapoint_history_idx = self.dataset.datapoint_history_len(x as u32, y as u32);
-                let next_datapoint_history_idx = self.pixels[y][x].datapoint_history_idx + 1;
+                let next_datapoint_history_idx = self.history_idx(x as u32, y as u32) + 1;
                 let next_nth_datapoint_history_idx = next_datapoint_history_idx + n;
                 for idx in next_datapoint_history_idx..min!(next_nth_datapoint_history_idx, max_datapoint_history_idx) {
                     let timestamp = self.dataset.datapoint_timestamp_with_xy_and_idx(x as u32, y as u32, idx as u32);
@@ -211,17 +378,28 @@ impl Canvas {
         self.adjust_timestamp(next_timestamp as i64, x1, x2, y1, y2);
     }
          
-    // TODO: draw only the pixels that changed
+    /// Same as `next_nth_pixel_change` without caching the timestamps of the region.
     // TODO: use threading to speed up search
     pub fn next_nth_pixel_change_low_mem(&mut self, n: usize,  x1: usize, x2: usize, y1: usize, y2: usize) {
         let start_time = Instant::now();
+        if let Some(result) = self.next_nth_edit_from_log(n, x1, x2, y1, y2) {
+            match result {
+                Some(next_timestamp) => {
+                    println!("Found next nth pixel in edit log: n={} timestamp={} | duration: {:?}", n, next_timestamp, start_time.elapsed());
+                    self.adjust_timestamp(next_timestamp as i64, x1, x2, y1, y2);
+                },
+                None => println!("Keeping timestamp the same: {}", self.timestamp),
+            }
+            return;
+        }
+
         let mut total_added = 0;
         let mut cache = Vec::with_capacity(2*n);
         let mut next_timestamp = MAX_TIMESTAMP; 
         for y in y1..y2 {
             for x in x1..x2 {
                 let max_datapoint_history_idx = self.dataset.datapoint_history_len(x as u32, y as u32);
-                let next_datapoint_history_idx = self.pixels[y][x].datapoint_history_idx + 1;
+                let next_datapoint_history_idx = self.history_idx(x as u32, y as u32) + 1;
                 let next_nth_datapoint_history_idx = next_datapoint_history_idx + n;
 
                 let start_length = cache.len();
@@ -255,6 +433,82 @@ impl Canvas {
         self.adjust_timestamp(next_timestamp as i64, x1, x2, y1, y2);
     }
 
+    fn is_visible(&self, x: u32, y: u32, x1: usize, x2: usize, y1: usize, y2: usize) -> bool {
+        (x1..x2).contains(&(x as usize)) && (y1..y2).contains(&(y as usize))
+    }
+
+    // Timestamp of the nth edit of a visible pixel after the current timestamp, or of the last one 
+    // if there are fewer. Returns None if the dataset has no edit log or the scan limit is reached, 
+    // and Some(None) if no visible pixel changes after the current timestamp
+    fn next_nth_edit_from_log(&self, n: usize, x1: usize, x2: usize, y1: usize, y2: usize) -> Option<Option<u64>> {
+        let edit_log_len = self.dataset.edit_log_len();
+        if edit_log_len == 0 {
+            return None;
+        }
+
+        let mut found = 0;
+        let mut last_timestamp = None;
+        let start = self.dataset.edit_index_after(self.timestamp);
+        for index in start..min!(edit_log_len, start + EDIT_LOG_SCAN_LIMIT) {
+            let edit = match self.dataset.edit(index) {
+                Some(edit) => edit,
+                None => break,
+            };
+            let (x, y) = edit.coordinate(self.width());
+            if !self.is_visible(x, y, x1, x2, y1, y2) {
+                continue;
+            }
+
+            found += 1;
+            last_timestamp = Some(edit.timestamp);
+            if found == n {
+                return Some(last_timestamp);
+            }
+        }
+
+        if start + EDIT_LOG_SCAN_LIMIT < edit_log_len {
+            return None;
+        }
+        Some(last_timestamp)
+    }
+
+    // Timestamp of the nth edit of a visible pixel before the current timestamp. The sentinels 
+    // count as the first edit of every pixel. Same return values as next_nth_edit_from_log
+    fn prev_nth_edit_from_log(&self, n: usize, x1: usize, x2: usize, y1: usize, y2: usize) -> Option<Option<u64>> {
+        let edit_log_len = self.dataset.edit_log_len();
+        if edit_log_len == 0 {
+            return None;
+        }
+
+        // first edit at the current timestamp
+        let end = if self.timestamp > 0 { self.dataset.edit_index_after(self.timestamp - 1) } else { 0 };
+        let mut found = 0;
+        for index in (end.saturating_sub(EDIT_LOG_SCAN_LIMIT)..end).rev() {
+            let edit = match self.dataset.edit(index) {
+                Some(edit) => edit,
+                None => continue,
+            };
+            let (x, y) = edit.coordinate(self.width());
+            if !self.is_visible(x, y, x1, x2, y1, y2) {
+                continue;
+            }
+
+            found += 1;
+            if found == n {
+                return Some(Some(edit.timestamp));
+            }
+        }
+
+        if end > EDIT_LOG_SCAN_LIMIT {
+            return None;
+        }
+
+        if self.timestamp > self.min_timestamp {
+            return Some(Some(self.min_timestamp));
+        }
+        Some(None)
+    }
+
     fn split_canvas_into_chunks(pixels: &mut Vec<Vec<CanvasPixel>>, n_threads: u32, x1: usize, x2: usize, y1: usize, y2: usize) -> (Box<Vec<Vec<&mut [CanvasPixel]>>>, usize) {
         let y_chunk_size = f32::ceil((y2 - y1) as f32 / n_threads as f32) as usize;
         let y_chunked_canvas: Vec<&mut [Vec<CanvasPixel>]> = pixels[y1..y2].chunks_mut(y_chunk_size).collect();
@@ -269,31 +523,169 @@ impl Canvas {
         (xy_sliced_canvas, y_chunk_size)
     }
 
-    pub fn adjust_timestamp(&mut self, timestamp: i64, x1: usize, x2: usize, y1: usize, y2: usize) {
+    /// Moves the pixels in the region to the timestamp and returns the tiles with pixels whose
+    /// current datapoint changed. The tiles are also added to `self.dirty`.
+    pub fn adjust_timestamp(&mut self, timestamp: i64, x1: usize, x2: usize, y1: usize, y2: usize) -> DirtyTiles {
         println!("Adjust timestamp between x={}..{} y={}..{} | t={}", x1, x2, y1, y2, timestamp);
+        let mut dirty = DirtyTiles::new(self.dirty.width, self.dirty.height);
         if x1 >= x2 || y1 >= y2 {
             println!("Skipping adjust timestamp");
-            return;
+            return dirty;
         }
 
         let start_time = Instant::now();
+        let seek = self.plan_seek(timestamp as u64, x1, x2, y1, y2);
+        match seek {
+            Seek::Search => self.search_pixels(timestamp, x1, x2, y1, y2, &mut dirty),
+            Seek::Replay { from, to } => self.replay_edits(timestamp as u64, from, to, x1, x2, y1, y2, &mut dirty),
+            Seek::Keyframe { keyframe, from, to } => {
+                self.load_keyframe(keyframe, x1, x2, y1, y2, &mut dirty);
+                self.replay_edits(timestamp as u64, from, to, x1, x2, y1, y2, &mut dirty);
+            },
+        }
+
+        // only the tiles over changed pixels need to be recomputed
+        for region in dirty.regions() {
+            self.tiles.update(&self.pixels, region.x1, region.x2, region.y1, region.y2);
+        }
+        self.dirty.merge(&dirty);
+        self.timestamp = timestamp as u64;
+        println!("adjust_timestamp duration: {:?} | {:?} | {} dirty tiles", start_time.elapsed(), seek, dirty.num_tiles());
+        dirty
+    }
+
+    // Picks the cheapest way to move the region to the timestamp. Replaying reads every edit of 
+    // the canvas between two timestamps, searching reads a few datapoints of every pixel of the region
+    fn plan_seek(&self, timestamp: u64, x1: usize, x2: usize, y1: usize, y2: usize) -> Seek {
+        if self.dataset.edit_log_len() == 0 {
+            return Seek::Search;
+        }
+
+        let num_pixels = (x2 - x1) * (y2 - y1);
+        let to = self.dataset.edit_index_after(timestamp);
+        let mut seek = Seek::Search;
+        let mut cost = num_pixels * SEARCH_COST_PER_PIXEL;
+
+        // edits can only be replayed from the current state if the whole region is at the same timestamp
+        if let Some(current_timestamp) = self.region_timestamp(x1, x2, y1, y2) {
+            let from = self.dataset.edit_index_after(current_timestamp);
+            if from.abs_diff(to) <= cost {
+                cost = from.abs_diff(to);
+                seek = Seek::Replay { from, to };
+            }
+        }
+
+        // loading a keyframe rewrites every pixel of the region before replaying
+        if let Some(keyframes) = &self.keyframes {
+            if let Some(keyframe) = keyframes.keyframe_before(timestamp) {
+                let from = self.dataset.edit_index_after(keyframes.timestamp(keyframe));
+                if num_pixels + (to - from) < cost {
+                    seek = Seek::Keyframe { keyframe, from, to };
+                }
+            }
+        }
+
+        seek
+    }
+
+    // timestamp of the pixels in the region if they are all at the same one
+    fn region_timestamp(&self, x1: usize, x2: usize, y1: usize, y2: usize) -> Option<u64> {
+        let timestamp = self.pixels[y1][x1].timestamp;
+        let uniform = self.pixels[y1..y2].iter().all(|row| row[x1..x2].iter().all(|pixel| pixel.timestamp == timestamp));
+        uniform.then_some(timestamp)
+    }
+
+    // Sets the pixels of the region to the colors of a keyframe. Their history indices are only
+    // searched when they are needed
+    fn load_keyframe(&mut self, keyframe: usize, x1: usize, x2: usize, y1: usize, y2: usize, dirty: &mut DirtyTiles) {
+        let keyframes = match &self.keyframes {
+            Some(keyframes) => keyframes,
+            None => return,
+        };
+
+        let keyframe_timestamp = keyframes.timestamp(keyframe);
+        for y in y1..y2 {
+            for x in x1..x2 {
+                let color = match keyframes.color(keyframe, x as u32, y as u32) {
+                    Some(color) => color,
+                    None => self.dataset.color_at(x as u32, y as u32, keyframe_timestamp),
+                };
+
+                // the datapoint of a resolved pixel may change even if its color stays the same
+                let pixel = &mut self.pixels[y][x];
+                if pixel.datapoint_history_idx != UNRESOLVED_HISTORY_IDX || pixel.color != color {
+                    dirty.mark_pixel(x, y);
+                }
+                pixel.color = color;
+                pixel.datapoint_history_idx = UNRESOLVED_HISTORY_IDX;
+                pixel.timestamp = keyframe_timestamp;
+            }
+        }
+    }
+
+    // Applies the edits from..to of the edit log to the pixels of the region and moves them to the 
+    // timestamp. Forward every edit sets its datapoint, backward (to < from) the edits are undone
+    // from the last one, so the pixel ends at the datapoint before its earliest edit in the range
+    #[allow(clippy::too_many_arguments)]
+    fn replay_edits(&mut self, timestamp: u64, from: usize, to: usize, x1: usize, x2: usize, y1: usize, y2: usize, dirty: &mut DirtyTiles) {
+        let forward = from <= to;
+        let indices: Box<dyn Iterator<Item = usize>> = if forward { Box::new(from..to) } else { Box::new((to..from).rev()) };
+        let width = self.width();
+        for index in indices {
+            let edit = match self.dataset.edit(index) {
+                Some(edit) => edit,
+                None => break,
+            };
+            let (x, y) = edit.coordinate(width);
+            if !self.is_visible(x, y, x1, x2, y1, y2) {
+                continue;
+            }
+
+            let history_idx = if forward { edit.history_index } else { edit.history_index - 1 };
+            let history_offset = self.dataset.datapoint_history_xy_offset(x, y);
+            let color = self.dataset.datapoint_with_history_offset(history_offset, history_idx).color;
+            let pixel = &mut self.pixels[y as usize][x as usize];
+            if pixel.datapoint_history_idx != history_idx as usize || pixel.color != color {
+                dirty.mark_pixel(x as usize, y as usize);
+            }
+            pixel.color = color;
+            pixel.datapoint_history_idx = history_idx as usize;
+        }
+
+        for row in self.pixels[y1..y2].iter_mut() {
+            for pixel in row[x1..x2].iter_mut() {
+                pixel.timestamp = timestamp;
+            }
+        }
+    }
+
+    // Searches the history of every pixel of the region for the datapoint at the timestamp
+    fn search_pixels(&mut self, timestamp: i64, x1: usize, x2: usize, y1: usize, y2: usize, dirty: &mut DirtyTiles) {
         let (mut xy_sliced_canvas, y_chunk_size) = Self::split_canvas_into_chunks(&mut self.pixels, 8, x1, x2, y1, y2);
 
-        type CanvasThreadOutput = (usize, Duration);
+        type CanvasThreadOutput = (usize, Duration, DirtyTiles);
         let (tx, rx): (Sender<CanvasThreadOutput>, Receiver<CanvasThreadOutput>) = mpsc::channel();
         let dataset = Arc::new(&self.dataset);
+        let (canvas_width, canvas_height) = (self.dirty.width, self.dirty.height);
         thread::scope(|scope| {
             for (n_th, slice) in xy_sliced_canvas.iter_mut().enumerate() {
                 let thread_tx = tx.clone();
                 let thread_dataset = dataset.clone();
                 scope.spawn(move || {
                     let thread_start_time = Instant::now();
+                    let mut thread_dirty = DirtyTiles::new(canvas_width, canvas_height);
                     for (row_idx, row) in slice.into_iter().enumerate() {
                         let y = n_th * y_chunk_size + row_idx + y1;
                         for (col_idx, pixel) in row.into_iter().enumerate() {
                             let x = col_idx + x1; 
                             let current_idx = pixel.datapoint_history_idx;
                             let (start_idx, end_idx) = match timestamp - (pixel.timestamp as i64) {
+                                0 => { 
+                                    continue
+                                },
+                                _ if current_idx == UNRESOLVED_HISTORY_IDX => {
+                                    (0, thread_dataset.datapoint_history_len(x as u32, y as u32))
+                                },
                                 1_i64..=i64::MAX => {
                                     let start_idx = current_idx;
                                     let end_idx = thread_dataset.datapoint_history_len(x as u32, y as u32);
@@ -304,15 +696,15 @@ impl Canvas {
                                     let end_idx = current_idx + 1;
                                     (start_idx, end_idx)
                                 },
-                                0 => { 
-                                    continue
-                                },
                             };
             
-                            let search_idx = thread_dataset.search(timestamp as u64, x, y, start_idx, end_idx, pixel);
+                            let search_idx = thread_dataset.search(timestamp as u64, x, y, start_idx, end_idx);
                             let history_offset = thread_dataset.datapoint_history_xy_offset(x as u32, y as u32);
                             let search_datapoint = thread_dataset.datapoint_with_history_offset(history_offset, search_idx as u32);
                             
+                            if pixel.datapoint_history_idx != search_idx || pixel.color != search_datapoint.color {
+                                thread_dirty.mark_pixel(x, y);
+                            }
                             pixel.color = search_datapoint.color;
                             pixel.datapoint_history_idx = search_idx;
                             pixel.timestamp = timestamp as u64;
@@ -321,21 +713,43 @@ impl Canvas {
 
                     thread_tx.send((
                         n_th, 
-                        thread_start_time.elapsed()
+                        thread_start_time.elapsed(),
+                        thread_dirty
                     )).unwrap();
                 });
             }
         });
 
         for _ in 0..xy_sliced_canvas.len() {
-            let (thread_idx, thread_duration) = rx.recv().unwrap();
-            //println!("Thread number: {:?} - Finished! - Duration {:?}", thread_idx, thread_duration);
+            let (_thread_idx, _thread_duration, thread_dirty) = rx.recv().unwrap();
+            //println!("Thread number: {:?} - Finished! - Duration {:?}", _thread_idx, _thread_duration);
+            dirty.merge(&thread_dirty);
         }
+    }
 
-        self.timestamp = timestamp as u64;
-        println!("adjust_timestamp duration: {:?}", start_time.elapsed());
+    /// Returns the tiles changed since the last call and starts tracking again.
+    pub fn take_dirty_tiles(&mut self) -> DirtyTiles {
+        let empty = DirtyTiles::new(self.dirty.width, self.dirty.height);
+        std::mem::replace(&mut self.dirty, empty)
+    }
+
+    /// History index of the current datapoint of (x, y), searched if the pixel was set from a keyframe.
+    pub fn history_idx(&self, x: u32, y: u32) -> usize {
+        let pixel = &self.pixels[y as usize][x as usize];
+        if pixel.datapoint_history_idx == UNRESOLVED_HISTORY_IDX {
+            return self.dataset.history_index_at(x, y, pixel.timestamp);
+        }
+
+        pixel.datapoint_history_idx
+    }
+
+    /// The datapoint that set the current color of (x, y).
+    pub fn current_datapoint(&self, x: u32, y: u32) -> RPlaceDatasetDatapoint {
+        let history_offset = self.dataset.datapoint_history_xy_offset(x, y);
+        self.dataset.datapoint_with_history_offset(history_offset, self.history_idx(x, y) as u32)
     }
 
+    /// Size of the canvas on the display at the current zoom.
     pub fn display_size(&self) -> Vector2<f32> {
         Vector2::new(
             self.width() as f32 * self.pixel_size, 
@@ -343,14 +757,27 @@ impl Canvas {
         )
     }
 
+    /// Height of the canvas in pixels.
     pub fn height(&self) -> u32 {
         return self.pixels.len() as u32;
     }
 
+    /// Width of the canvas in pixels.
     pub fn width(&self) -> u32 {
         return self.pixels[0].len() as u32;
     }
 
+    /// Size and r/place origin of the canvas.
+    pub fn dimensions(&self) -> CanvasDimensions {
+        self.dataset.header.dimensions()
+    }
+
+    /// The r/place coordinate of the canvas pixel at (x, y).
+    pub fn place_coordinates(&self, x: u32, y: u32) -> Vector2<i32> {
+        self.dimensions().place_coordinate(x, y)
+    }
+
+    /// Display position of the center of the canvas.
     pub fn center_coordinate(&self) -> Vector2<f32> {
         return Vector2::new(
             self.top_left.x + (self.pixel_size * self.width() as f32) / 2.0,    
@@ -358,6 +785,7 @@ impl Canvas {
         );
     }
 
+    /// Top left and bottom right display positions of the canvas pixel at (x, y).
     pub fn get_rect_bounds(&self, x: u32, y: u32) -> (Vector2<f32>, Vector2<f32>) {
         let top_left = Vector2::new(
             self.top_left.x + (x as f32 * self.pixel_size), 
@@ -372,6 +800,7 @@ impl Canvas {
         return (top_left, bottom_right);
     }
 
+    /// Canvas pixel under the display position (x, y), clamped to the canvas.
     pub fn get_canvas_coordinates(&self, x: f32, y: f32) -> Vector2<u32> {
         let canvas_x = max!(
             0,
@@ -389,7 +818,19 @@ impl Canvas {
         );
     }
 
-    // pixel_diff is positive on zoom in and negative on zoom out
+    /// Canvas pixel under the display position (x, y), None if the position is outside of the canvas.
+    pub fn canvas_pixel_at(&self, x: f32, y: f32) -> Option<Vector2<u32>> {
+        let canvas_x = ((x - self.top_left.x) / self.pixel_size).floor();
+        let canvas_y = ((y - self.top_left.y) / self.pixel_size).floor();
+        if canvas_x < 0.0 || canvas_y < 0.0 || canvas_x >= self.width() as f32 || canvas_y >= self.height() as f32 {
+            return None;
+        }
+
+        Some(Vector2::new(canvas_x as u32, canvas_y as u32))
+    }
+
+    /// Zooms in or out around the display position `location`. `pixel_size_diff` is positive on
+    /// zoom in and negative on zoom out.
     pub fn zoom(&mut self, pixel_size_diff: f32, location: Vector2<f32>) {
         // ensures the updated pixel size is always above self.min_pixel_size
         let mut new_pixel_size = self.pixel_size + pixel_size_diff;
@@ -423,4 +864,140 @@ impl Canvas {
         println!("Updated pixel_size input_size_diff={} modified_size_diff={} new_pixel_size={}", 
             pixel_size_diff, modified_pixel_size_diff, new_pixel_size); 
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use speedy2d::dimen::Vec2;
+
+    use super::*;
+    use crate::rplace::data::RPlaceDatapoint;
+    use crate::rplace::keyframes::write_keyframes;
+    use crate::rplace::writer::DatasetWriter;
+
+    const SIZE: usize = 8;
+    const NUM_EDITS: u64 = 600;
+    const KEYFRAME_INTERVAL: u64 = 500;
+
+    // edits every 10ns at pseudo random pixels of an 8x8 canvas
+    fn datapoints() -> Vec<RPlaceDatapoint> {
+        let mut state: u64 = 7;
+        (1..=NUM_EDITS).map(|i| {
+            state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            let value = state >> 33;
+            RPlaceDatapoint {
+                timestamp: i * 10,
+                user_id: (value % 5) as u32,
+                color: PixelColor::from_index((value % 16) as u8).unwrap(),
+                coordinate: Vec2::new(((value >> 8) % SIZE as u64) as f32, ((value >> 16) % SIZE as u64) as f32),
+                is_mod: false,
+            }
+        }).collect()
+    }
+
+    // the same dataset opened once with its keyframes and once without
+    fn canvases(name: &str) -> (Canvas, Canvas) {
+        let file_path = std::env::temp_dir().join(format!("rplace-canvas-{}-{}", std::process::id(), name)).to_string_lossy().to_string();
+        let datapoints = datapoints();
+        DatasetWriter::new(CanvasDimensions::new(SIZE as u32, SIZE as u32))
+            .write(&file_path, || Ok(datapoints.iter().copied().map(Ok)))
+            .unwrap();
+        write_keyframes(&SerializedDataset::new(&file_path).unwrap(), &keyframes_file_path(&file_path), KEYFRAME_INTERVAL).unwrap();
+
+        let keyframed = Canvas::new_with_file_path(&file_path).unwrap();
+        let mut plain = Canvas::new_with_file_path(&file_path).unwrap();
+        plain.keyframes = None;
+        std::fs::remove_file(&file_path).unwrap();
+        std::fs::remove_file(keyframes_file_path(&file_path)).unwrap();
+        (keyframed, plain)
+    }
+
+    fn assert_same_pixels(keyframed: &Canvas, plain: &Canvas, timestamp: i64) {
+        for y in 0..SIZE as u32 {
+            for x in 0..SIZE as u32 {
+                let pixel = keyframed.pixels[y as usize][x as usize];
+                assert_eq!(pixel.color, plain.pixels[y as usize][x as usize].color, "({}, {}) after seeking to {}", x, y, timestamp);
+                assert_eq!(pixel.color, keyframed.dataset.color_at(x, y, pixel.timestamp), "({}, {}) after seeking to {}", x, y, timestamp);
+                assert_eq!(keyframed.history_idx(x, y), plain.history_idx(x, y), "({}, {}) after seeking to {}", x, y, timestamp);
+            }
+        }
+    }
+
+    #[test]
+    fn dirty_tile_regions() {
+        // 4x3 tiles, the last column and row of tiles are clipped to the canvas
+        let mut dirty = DirtyTiles::new(100, 70);
+        assert!(dirty.is_empty());
+        assert_eq!(dirty.regions(), vec![]);
+
+        for (x, y) in [(0, 0), (40, 5), (63, 31), (0, 33), (70, 33), (99, 69)] {
+            dirty.mark_pixel(x, y);
+        }
+        let regions = dirty.regions();
+        assert_eq!(dirty.num_tiles(), 5);
+        assert_eq!(regions, vec![
+            DirtyRegion { x1: 0, x2: 64, y1: 0, y2: 32 },
+            DirtyRegion { x1: 0, x2: 32, y1: 32, y2: 64 },
+            DirtyRegion { x1: 64, x2: 96, y1: 32, y2: 64 },
+            DirtyRegion { x1: 96, x2: 100, y1: 64, y2: 70 },
+        ]);
+        assert_eq!(regions[3].num_pixels(), 4 * 6);
+    }
+
+    #[test]
+    fn merge_dirty_tiles() {
+        let mut dirty = DirtyTiles::new(100, 70);
+        let mut other = DirtyTiles::new(100, 70);
+        dirty.mark_pixel(10, 10);
+        other.mark_pixel(40, 10);
+        other.mark_pixel(10, 10);
+
+        dirty.merge(&other);
+        assert_eq!(dirty.num_tiles(), 2);
+        assert_eq!(dirty.regions(), vec![DirtyRegion { x1: 0, x2: 64, y1: 0, y2: 32 }]);
+        assert_eq!(other.num_tiles(), 2);
+    }
+
+    #[test]
+    fn adjust_timestamp_marks_changed_pixels() {
+        let (mut canvas, _) = canvases("dirty");
+        assert!(!canvas.adjust_timestamp(3_000, 0, SIZE, 0, SIZE).is_empty());
+        assert!(canvas.take_dirty_tiles().num_tiles() > 0);
+        assert!(canvas.take_dirty_tiles().is_empty());
+
+        // nothing changes when the region is already at the timestamp
+        assert!(canvas.adjust_timestamp(3_000, 0, SIZE, 0, SIZE).is_empty());
+        assert!(canvas.take_dirty_tiles().is_empty());
+    }
+
+    #[test]
+    fn keyframe_seek_matches_plain_seek() {
+        let (mut keyframed, mut plain) = canvases("keyframes");
+        assert!(keyframed.keyframes.is_some());
+
+        // far seeks start from a keyframe, the canvas without keyframes searches or replays
+        assert!(matches!(keyframed.plan_seek(5_555, 0, SIZE, 0, SIZE), Seek::Keyframe { .. }));
+        assert!(!matches!(plain.plan_seek(5_555, 0, SIZE, 0, SIZE), Seek::Keyframe { .. }));
+
+        let seeks = [
+            (5_555, 0, SIZE, 0, SIZE),
+            (2_500, 0, SIZE, 0, SIZE),
+            (2_510, 0, SIZE, 0, SIZE),
+            (4_000, 2, 5, 1, 7),
+            (9, 0, SIZE, 0, SIZE),
+            (3_333, 0, 4, 0, SIZE),
+            (6_000, 0, SIZE, 0, SIZE),
+            (1_234, 0, SIZE, 0, SIZE),
+            (10_000, 0, SIZE, 0, SIZE),
+        ];
+        for (i, (timestamp, x1, x2, y1, y2)) in seeks.into_iter().enumerate() {
+            keyframed.adjust_timestamp(timestamp, x1, x2, y1, y2);
+            plain.adjust_timestamp(timestamp, x1, x2, y1, y2);
+            assert_same_pixels(&keyframed, &plain, timestamp);
+
+            // pixels that were not edited since the keyframe are left for history_idx to resolve
+            if i == 0 {
+                assert!(keyframed.pixels.iter().flatten().any(|pixel| pixel.datapoint_history_idx == UNRESOLVED_HISTORY_IDX));
+            }
+        }
+    }
+}
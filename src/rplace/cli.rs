@@ -0,0 +1,943 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::thread;
+use std::time::Instant;
+
+use time::format_description::well_known::Rfc3339;
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+use super::data::{CanvasDimensions, RPlaceDataReader};
+use super::error::BadRecordPolicy;
+use super::heatmap::{ColorRamp, HeatmapScale, HeatmapStyle};
+use super::inspector::PixelInspection;
+use super::keyframes::{keyframes_file_path, write_keyframes, DEFAULT_KEYFRAME_INTERVAL};
+use super::reader::csv::RPlaceCSVShardIterator;
+use super::reader::custom::{DatasetLayout, SerializedDataset};
+use super::render::{CanvasRegion, HeadlessRenderer};
+use super::timelapse::{FrameStep, Timelapse, TimelapseOutput};
+use super::user_index::{user_index_file_path, write_user_index, UserEdit, UserIndex};
+use super::users::{user_table_file_path, UserHashTable, UserIdDictionary};
+use super::writer::DatasetWriter;
+
+pub const DEFAULT_DATASET_FILE_PATH: &str = "data/custom/output_white";
+
+pub const USAGE: &str = "Usage:
+    rplace [view] [dataset]
+        Opens the viewer. Defaults to data/custom/output_white
+
+    rplace convert --from <parquet|csv> --to <custom> [options] <input> <output>
+        Converts a dataset into the custom format. For csv the input can be a single
+        file or a directory of (optionally gzipped) shards. Csv user hashes are replaced
+        by sequential ids, the mapping is written to <output>.users
+        --limit <n>               only convert the first n datapoints
+        --canvas <2017|2022|2023|WIDTHxHEIGHT>
+                                  canvas size, defaults to 2022
+        --origin <x,y>            r/place coordinate of the top left pixel
+        --print-frequency <n>     print progress every n datapoints
+        --threads <n>             threads that decode parquet row groups, defaults to the
+                                  number of cores
+        --bad-records <skip|count|fail>
+                                  what to do with datapoints that have an unknown color,
+                                  are outside of the canvas or can't be parsed. defaults to count
+        --layout <records|columns|compressed>
+                                  how datapoints are stored. columns keeps the timestamps of
+                                  every pixel history together, compressed delta and varint
+                                  encodes every history. defaults to records
+        --keyframe-interval <minutes>
+                                  write a full canvas keyframe every n minutes of data to
+                                  <output>.keyframes for faster seeking. 0 disables keyframes,
+                                  defaults to 60
+        --no-user-index           do not write the index of the edits of every user to
+                                  <output>.user_index. writing it needs 8 bytes of memory
+                                  per user id, about 80 MB for the 2022 dataset
+
+    rplace render --at <time> --out <file.png> [options] [dataset]
+        Renders the canvas at a point in time to a png without opening a window
+        --at <time>               nanosecond unix timestamp, or a UTC time like
+                                  2022-04-03T12:00:00Z or \"2022-04-03 12:00:00\"
+        --region <x1,y1,x2,y2>    canvas pixels x1..x2, y1..y2 to render (x2 and y2 are
+                                  excluded). defaults to the whole canvas
+        --scale <n>               draw every canvas pixel as n x n pixels, defaults to 1
+        --out <file.png>          where to write the image
+
+    rplace timelapse --start <time> --end <time> <--interval <seconds>|--edits <n>> --out <output> [options] [dataset]
+        Renders one frame per step from the start to the end time. Times are the same as for render
+        --interval <seconds>      seconds of data between two frames
+        --edits <n>               edits inside the region between two frames
+        --region <x1,y1,x2,y2>    same as for render, defaults to the whole canvas
+        --scale <n>               same as for render, defaults to 1
+        --delay <ms>              how long every frame of a gif is shown, defaults to 100
+        --out <output>            a .gif file, or a directory that frame_00000.png, ... are written to.
+                                  apng is not supported, convert the png frames with an external tool
+
+    rplace heatmap --out <file.png> [options] [dataset]
+        Renders how often every pixel was edited within a time window. The most edited pixel of
+        the region gets the last color of the ramp
+        --start <time>            edits after this time are counted, defaults to the start of the
+                                  dataset. times are the same as for render
+        --end <time>              edits at or before this time are counted, defaults to the end
+                                  of the dataset
+        --ramp <ramp>             heat, viridis, gray, blue or comma separated hex colors like
+                                  #000000,#ff0000,#ffffff. defaults to heat
+        --color-scale <log|linear>
+                                  how counts are spread over the ramp, defaults to log
+        --region <x1,y1,x2,y2>    same as for render, defaults to the whole canvas
+        --scale <n>               same as for render, defaults to 1
+        --top <n>                 also print the n most edited pixels, defaults to 10
+        --out <file.png>          where to write the image
+
+    rplace user <user id|user hash> [options] [dataset]
+        Lists every edit of a user in timestamp order. Needs <dataset>.user_index, and
+        <dataset>.users to look up user hashes
+        --limit <n>               only list the first n edits
+        --overlay <file.png>      also render the user's edits over the dimmed canvas
+        --at <time>               time of the overlay, defaults to the user's last edit
+        --region <x1,y1,x2,y2>    same as for render, defaults to the whole canvas
+        --scale <n>               same as for render, defaults to 1
+
+    rplace inspect <x,y> [options] [dataset]
+        Lists every edit of a canvas pixel with its timestamp, user, color and mod flag, and
+        marks the edit that is current at a point in time. Uses <dataset>.users for user hashes
+        --at <time>               same as for render, defaults to the end of the dataset
+
+    rplace verify [options] [dataset]
+        Reads every datapoint once and reports the ones that can't be decoded. Opening a
+        dataset only checks its header, so this is worth running once on new datasets
+        --bad-records <skip|count|fail>
+                                  what to do with datapoints that have an unknown color,
+                                  defaults to count. history blocks that can't be decoded
+                                  always stop the check
+
+    rplace help
+        Prints this message";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Parquet,
+    Csv,
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "parquet" => Ok(InputFormat::Parquet),
+            "csv" => Ok(InputFormat::Csv),
+            _ => Err(format!("unknown input format '{}', expected parquet or csv", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Custom,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "custom" => Ok(OutputFormat::Custom),
+            _ => Err(format!("unknown output format '{}', expected custom", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConvertArgs {
+    pub from: InputFormat,
+    pub to: OutputFormat,
+    pub input: String,
+    pub output: String,
+    pub limit: Option<usize>,
+    pub dimensions: CanvasDimensions,
+    pub print_frequency: u64,
+
+    // threads that decode parquet row groups
+    pub threads: usize,
+    pub bad_record_policy: BadRecordPolicy,
+    pub layout: DatasetLayout,
+
+    // nanoseconds between keyframes, None to not write keyframes
+    pub keyframe_interval: Option<u64>,
+    pub user_index: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderArgs {
+    pub dataset: String,
+    pub timestamp: u64,
+
+    // None renders the whole canvas
+    pub region: Option<CanvasRegion>,
+    pub scale: u32,
+    pub output: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct TimelapseArgs {
+    pub dataset: String,
+    pub start_timestamp: u64,
+    pub end_timestamp: u64,
+    pub step: FrameStep,
+
+    // None renders the whole canvas
+    pub region: Option<CanvasRegion>,
+    pub scale: u32,
+    pub frame_delay_ms: u32,
+    pub output: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct HeatmapArgs {
+    pub dataset: String,
+
+    // None counts from the start or until the end of the dataset
+    pub start_timestamp: Option<u64>,
+    pub end_timestamp: Option<u64>,
+    pub style: HeatmapStyle,
+
+    // None renders the whole canvas
+    pub region: Option<CanvasRegion>,
+    pub scale: u32,
+    pub top: usize,
+    pub output: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct UserArgs {
+    pub dataset: String,
+
+    // numeric user id or the hash from the csv dump
+    pub user: String,
+    pub limit: Option<usize>,
+    pub overlay: Option<String>,
+    pub timestamp: Option<u64>,
+    pub region: Option<CanvasRegion>,
+    pub scale: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct InspectArgs {
+    pub dataset: String,
+    pub x: u32,
+    pub y: u32,
+
+    // None marks the edit that is current at the end of the dataset
+    pub timestamp: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifyArgs {
+    pub dataset: String,
+    pub bad_record_policy: BadRecordPolicy,
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    View { dataset: String },
+    Convert(ConvertArgs),
+    Render(RenderArgs),
+    Timelapse(TimelapseArgs),
+    Heatmap(HeatmapArgs),
+    User(UserArgs),
+    Inspect(InspectArgs),
+    Verify(VerifyArgs),
+    Help,
+}
+
+#[derive(Debug)]
+pub struct CliError(pub String);
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<String> for CliError {
+    fn from(item: String) -> Self {
+        CliError(item)
+    }
+}
+
+// Walks over the arguments of a subcommand, separating --flag value pairs from positional arguments
+struct ArgParser {
+    args: Vec<String>,
+    position: usize,
+}
+
+impl ArgParser {
+    fn new(args: &[String]) -> ArgParser {
+        ArgParser {
+            args: args.to_vec(),
+            position: 0,
+        }
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let arg = self.args.get(self.position).cloned();
+        self.position += 1;
+        arg
+    }
+
+    fn value(&mut self, flag: &str) -> Result<String, CliError> {
+        match self.next() {
+            Some(value) => Ok(value),
+            None => Err(CliError(format!("missing value for {}", flag))),
+        }
+    }
+
+    fn parsed<T: FromStr>(&mut self, flag: &str) -> Result<T, CliError> {
+        let value = self.value(flag)?;
+        match value.parse::<T>() {
+            Ok(parsed) => Ok(parsed),
+            Err(_) => Err(CliError(format!("invalid value for {}: {}", flag, value))),
+        }
+    }
+}
+
+pub fn parse_canvas_dimensions(s: &str) -> Result<CanvasDimensions, CliError> {
+    match s {
+        "2017" => return Ok(CanvasDimensions::r_place_2017()),
+        "2022" => return Ok(CanvasDimensions::r_place_2022()),
+        "2023" => return Ok(CanvasDimensions::r_place_2023()),
+        _ => (),
+    }
+
+    let values: Vec<&str> = s.split('x').collect();
+    if values.len() == 2 {
+        if let (Ok(width), Ok(height)) = (values[0].parse::<u32>(), values[1].parse::<u32>()) {
+            if width > 0 && height > 0 {
+                return Ok(CanvasDimensions::new(width, height));
+            }
+        }
+    }
+
+    Err(CliError(format!("invalid canvas '{}', expected 2017, 2022, 2023 or WIDTHxHEIGHT", s)))
+}
+
+pub fn parse_pair<T: FromStr>(s: &str, name: &str) -> Result<(T, T), CliError> {
+    let values: Vec<&str> = s.split(',').collect();
+    if values.len() == 2 {
+        if let (Ok(first), Ok(second)) = (values[0].trim().parse::<T>(), values[1].trim().parse::<T>()) {
+            return Ok((first, second));
+        }
+    }
+
+    Err(CliError(format!("invalid {} '{}', expected two comma separated values", name, s)))
+}
+
+// Nanosecond unix timestamps or UTC times, with or without an offset
+pub fn parse_timestamp(s: &str) -> Result<u64, CliError> {
+    if let Ok(timestamp) = s.parse::<u64>() {
+        return Ok(timestamp);
+    }
+
+    if let Ok(datetime) = OffsetDateTime::parse(s, &Rfc3339) {
+        return Ok(datetime.unix_timestamp_nanos() as u64);
+    }
+
+    let format1 = time::macros::format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
+    let format2 = time::macros::format_description!("[year]-[month]-[day]T[hour]:[minute]:[second]");
+    for format in [format1, format2] {
+        if let Ok(datetime) = PrimitiveDateTime::parse(s, &format) {
+            return Ok(datetime.assume_utc().unix_timestamp_nanos() as u64);
+        }
+    }
+
+    Err(CliError(format!("invalid time '{}', expected a nanosecond timestamp or a time like 2022-04-03T12:00:00Z", s)))
+}
+
+pub fn parse_region(s: &str) -> Result<CanvasRegion, CliError> {
+    let values: Vec<Option<u32>> = s.split(',').map(|value| value.trim().parse::<u32>().ok()).collect();
+    if let [Some(x1), Some(y1), Some(x2), Some(y2)] = values[..] {
+        if x1 < x2 && y1 < y2 {
+            return Ok(CanvasRegion { x1, y1, x2, y2 });
+        }
+    }
+
+    Err(CliError(format!("invalid region '{}', expected x1,y1,x2,y2 with x1 < x2 and y1 < y2", s)))
+}
+
+pub fn parse_args(args: &[String]) -> Result<Command, CliError> {
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => return Ok(Command::View { dataset: DEFAULT_DATASET_FILE_PATH.to_string() }),
+    };
+
+    match command {
+        "view" => parse_view_args(rest),
+        "convert" => Ok(Command::Convert(parse_convert_args(rest)?)),
+        "render" => Ok(Command::Render(parse_render_args(rest)?)),
+        "timelapse" => Ok(Command::Timelapse(parse_timelapse_args(rest)?)),
+        "heatmap" => Ok(Command::Heatmap(parse_heatmap_args(rest)?)),
+        "user" => Ok(Command::User(parse_user_args(rest)?)),
+        "inspect" => Ok(Command::Inspect(parse_inspect_args(rest)?)),
+        "verify" => Ok(Command::Verify(parse_verify_args(rest)?)),
+        "help" | "--help" | "-h" => Ok(Command::Help),
+
+        // a bare path opens the viewer
+        _ if !command.starts_with('-') => parse_view_args(args),
+        _ => Err(CliError(format!("unknown command '{}'", command))),
+    }
+}
+
+fn parse_view_args(args: &[String]) -> Result<Command, CliError> {
+    match args {
+        [] => Ok(Command::View { dataset: DEFAULT_DATASET_FILE_PATH.to_string() }),
+        [dataset] => Ok(Command::View { dataset: dataset.clone() }),
+        _ => Err(CliError(format!("view takes at most one dataset, got {:?}", args))),
+    }
+}
+
+pub fn parse_convert_args(args: &[String]) -> Result<ConvertArgs, CliError> {
+    let mut parser = ArgParser::new(args);
+    let mut from = None;
+    let mut to = OutputFormat::Custom;
+    let mut limit = None;
+    let mut dimensions = CanvasDimensions::r_place_2022();
+    let mut origin = None;
+    let mut print_frequency = 1_000_000;
+    let mut threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+    let mut bad_record_policy = BadRecordPolicy::default();
+    let mut layout = DatasetLayout::default();
+    let mut keyframe_interval = Some(DEFAULT_KEYFRAME_INTERVAL);
+    let mut user_index = true;
+    let mut positional = Vec::new();
+
+    while let Some(arg) = parser.next() {
+        match arg.as_str() {
+            "--from" => from = Some(parser.parsed::<InputFormat>("--from")?),
+            "--to" => to = parser.parsed::<OutputFormat>("--to")?,
+            "--limit" => limit = Some(parser.parsed::<usize>("--limit")?),
+            "--canvas" => dimensions = parse_canvas_dimensions(&parser.value("--canvas")?)?,
+            "--origin" => origin = Some(parse_pair::<i32>(&parser.value("--origin")?, "origin")?),
+            "--print-frequency" => print_frequency = parser.parsed::<u64>("--print-frequency")?,
+            "--threads" => threads = parser.parsed::<usize>("--threads")?,
+            "--bad-records" => bad_record_policy = parser.parsed::<BadRecordPolicy>("--bad-records")?,
+            "--layout" => layout = parser.parsed::<DatasetLayout>("--layout")?,
+            "--keyframe-interval" => keyframe_interval = match parser.parsed::<u64>("--keyframe-interval")? {
+                0 => None,
+                minutes => Some(minutes * 60 * 1_000_000_000),
+            },
+            "--no-user-index" => user_index = false,
+            flag if flag.starts_with("--") => return Err(CliError(format!("unknown flag '{}'", flag))),
+            _ => positional.push(arg),
+        }
+    }
+
+    let from = match from {
+        Some(from) => from,
+        None => return Err(CliError("convert requires --from".to_string())),
+    };
+
+    if let Some((origin_x, origin_y)) = origin {
+        dimensions.origin_x = origin_x;
+        dimensions.origin_y = origin_y;
+    }
+
+    if positional.len() != 2 {
+        return Err(CliError(format!("convert requires <input> and <output>, got {:?}", positional)));
+    }
+
+    Ok(ConvertArgs {
+        from,
+        to,
+        input: positional[0].clone(),
+        output: positional[1].clone(),
+        limit,
+        dimensions,
+        print_frequency: print_frequency.max(1),
+        threads: threads.max(1),
+        bad_record_policy,
+        layout,
+        keyframe_interval,
+        user_index,
+    })
+}
+
+pub fn parse_render_args(args: &[String]) -> Result<RenderArgs, CliError> {
+    let mut parser = ArgParser::new(args);
+    let mut timestamp = None;
+    let mut region = None;
+    let mut scale = 1;
+    let mut output = None;
+    let mut positional = Vec::new();
+
+    while let Some(arg) = parser.next() {
+        match arg.as_str() {
+            "--at" => timestamp = Some(parse_timestamp(&parser.value("--at")?)?),
+            "--region" => region = Some(parse_region(&parser.value("--region")?)?),
+            "--scale" => scale = parser.parsed::<u32>("--scale")?,
+            "--out" => output = Some(parser.value("--out")?),
+            flag if flag.starts_with("--") => return Err(CliError(format!("unknown flag '{}'", flag))),
+            _ => positional.push(arg),
+        }
+    }
+
+    let (timestamp, output) = match (timestamp, output) {
+        (Some(timestamp), Some(output)) => (timestamp, output),
+        _ => return Err(CliError("render requires --at and --out".to_string())),
+    };
+
+    if scale == 0 {
+        return Err(CliError("--scale has to be at least 1".to_string()));
+    }
+
+    let dataset = parse_dataset_arg(&positional, "render")?;
+    Ok(RenderArgs {
+        dataset,
+        timestamp,
+        region,
+        scale,
+        output,
+    })
+}
+
+pub fn parse_timelapse_args(args: &[String]) -> Result<TimelapseArgs, CliError> {
+    let mut parser = ArgParser::new(args);
+    let mut start_timestamp = None;
+    let mut end_timestamp = None;
+    let mut step = None;
+    let mut region = None;
+    let mut scale = 1;
+    let mut frame_delay_ms = 100;
+    let mut output = None;
+    let mut positional = Vec::new();
+
+    while let Some(arg) = parser.next() {
+        match arg.as_str() {
+            "--start" => start_timestamp = Some(parse_timestamp(&parser.value("--start")?)?),
+            "--end" => end_timestamp = Some(parse_timestamp(&parser.value("--end")?)?),
+            "--interval" => step = Some(FrameStep::Interval(parser.parsed::<u64>("--interval")? * 1_000_000_000)),
+            "--edits" => step = Some(FrameStep::Edits(parser.parsed::<usize>("--edits")?)),
+            "--region" => region = Some(parse_region(&parser.value("--region")?)?),
+            "--scale" => scale = parser.parsed::<u32>("--scale")?,
+            "--delay" => frame_delay_ms = parser.parsed::<u32>("--delay")?,
+            "--out" => output = Some(parser.value("--out")?),
+            flag if flag.starts_with("--") => return Err(CliError(format!("unknown flag '{}'", flag))),
+            _ => positional.push(arg),
+        }
+    }
+
+    let (start_timestamp, end_timestamp, step, output) = match (start_timestamp, end_timestamp, step, output) {
+        (Some(start_timestamp), Some(end_timestamp), Some(step), Some(output)) => (start_timestamp, end_timestamp, step, output),
+        _ => return Err(CliError("timelapse requires --start, --end, --out and either --interval or --edits".to_string())),
+    };
+
+    if start_timestamp > end_timestamp {
+        return Err(CliError(format!("--start {} is after --end {}", start_timestamp, end_timestamp)));
+    }
+
+    if step == FrameStep::Interval(0) || step == FrameStep::Edits(0) {
+        return Err(CliError("--interval and --edits have to be at least 1".to_string()));
+    }
+
+    if scale == 0 {
+        return Err(CliError("--scale has to be at least 1".to_string()));
+    }
+
+    // would otherwise silently become a directory of png frames
+    let lowercase_output = output.to_lowercase();
+    if lowercase_output.ends_with(".png") || lowercase_output.ends_with(".apng") {
+        return Err(CliError(format!("--out {} looks like an apng, which is not supported. Use a .gif file or a directory for png frames", output)));
+    }
+
+    let dataset = parse_dataset_arg(&positional, "timelapse")?;
+    Ok(TimelapseArgs {
+        dataset,
+        start_timestamp,
+        end_timestamp,
+        step,
+        region,
+        scale,
+        frame_delay_ms,
+        output,
+    })
+}
+
+pub fn parse_heatmap_args(args: &[String]) -> Result<HeatmapArgs, CliError> {
+    let mut parser = ArgParser::new(args);
+    let mut start_timestamp = None;
+    let mut end_timestamp = None;
+    let mut style = HeatmapStyle::default();
+    let mut region = None;
+    let mut scale = 1;
+    let mut top = 10;
+    let mut output = None;
+    let mut positional = Vec::new();
+
+    while let Some(arg) = parser.next() {
+        match arg.as_str() {
+            "--start" => start_timestamp = Some(parse_timestamp(&parser.value("--start")?)?),
+            "--end" => end_timestamp = Some(parse_timestamp(&parser.value("--end")?)?),
+            "--ramp" => style.ramp = parser.value("--ramp")?.parse::<ColorRamp>()?,
+            "--color-scale" => style.scale = parser.value("--color-scale")?.parse::<HeatmapScale>()?,
+            "--region" => region = Some(parse_region(&parser.value("--region")?)?),
+            "--scale" => scale = parser.parsed::<u32>("--scale")?,
+            "--top" => top = parser.parsed::<usize>("--top")?,
+            "--out" => output = Some(parser.value("--out")?),
+            flag if flag.starts_with("--") => return Err(CliError(format!("unknown flag '{}'", flag))),
+            _ => positional.push(arg),
+        }
+    }
+
+    let output = match output {
+        Some(output) => output,
+        None => return Err(CliError("heatmap requires --out".to_string())),
+    };
+
+    if let (Some(start_timestamp), Some(end_timestamp)) = (start_timestamp, end_timestamp) {
+        if start_timestamp > end_timestamp {
+            return Err(CliError(format!("--start {} is after --end {}", start_timestamp, end_timestamp)));
+        }
+    }
+
+    if scale == 0 {
+        return Err(CliError("--scale has to be at least 1".to_string()));
+    }
+
+    let dataset = parse_dataset_arg(&positional, "heatmap")?;
+    Ok(HeatmapArgs {
+        dataset,
+        start_timestamp,
+        end_timestamp,
+        style,
+        region,
+        scale,
+        top,
+        output,
+    })
+}
+
+pub fn parse_user_args(args: &[String]) -> Result<UserArgs, CliError> {
+    let mut parser = ArgParser::new(args);
+    let mut limit = None;
+    let mut overlay = None;
+    let mut timestamp = None;
+    let mut region = None;
+    let mut scale = 1;
+    let mut positional = Vec::new();
+
+    while let Some(arg) = parser.next() {
+        match arg.as_str() {
+            "--limit" => limit = Some(parser.parsed::<usize>("--limit")?),
+            "--overlay" => overlay = Some(parser.value("--overlay")?),
+            "--at" => timestamp = Some(parse_timestamp(&parser.value("--at")?)?),
+            "--region" => region = Some(parse_region(&parser.value("--region")?)?),
+            "--scale" => scale = parser.parsed::<u32>("--scale")?,
+            flag if flag.starts_with("--") => return Err(CliError(format!("unknown flag '{}'", flag))),
+            _ => positional.push(arg),
+        }
+    }
+
+    if scale == 0 {
+        return Err(CliError("--scale has to be at least 1".to_string()));
+    }
+
+    let (user, dataset) = match positional.split_first() {
+        Some((user, rest)) => (user.clone(), parse_dataset_arg(rest, "user")?),
+        None => return Err(CliError("user requires a user id or user hash".to_string())),
+    };
+
+    Ok(UserArgs {
+        dataset,
+        user,
+        limit,
+        overlay,
+        timestamp,
+        region,
+        scale,
+    })
+}
+
+pub fn parse_inspect_args(args: &[String]) -> Result<InspectArgs, CliError> {
+    let mut parser = ArgParser::new(args);
+    let mut timestamp = None;
+    let mut positional = Vec::new();
+
+    while let Some(arg) = parser.next() {
+        match arg.as_str() {
+            "--at" => timestamp = Some(parse_timestamp(&parser.value("--at")?)?),
+            flag if flag.starts_with("--") => return Err(CliError(format!("unknown flag '{}'", flag))),
+            _ => positional.push(arg),
+        }
+    }
+
+    let ((x, y), dataset) = match positional.split_first() {
+        Some((coordinate, rest)) => (parse_pair::<u32>(coordinate, "pixel")?, parse_dataset_arg(rest, "inspect")?),
+        None => return Err(CliError("inspect requires a canvas pixel x,y".to_string())),
+    };
+
+    Ok(InspectArgs {
+        dataset,
+        x,
+        y,
+        timestamp,
+    })
+}
+
+pub fn parse_verify_args(args: &[String]) -> Result<VerifyArgs, CliError> {
+    let mut parser = ArgParser::new(args);
+    let mut bad_record_policy = BadRecordPolicy::default();
+    let mut positional = Vec::new();
+
+    while let Some(arg) = parser.next() {
+        match arg.as_str() {
+            "--bad-records" => bad_record_policy = parser.parsed::<BadRecordPolicy>("--bad-records")?,
+            flag if flag.starts_with("--") => return Err(CliError(format!("unknown flag '{}'", flag))),
+            _ => positional.push(arg),
+        }
+    }
+
+    Ok(VerifyArgs {
+        dataset: parse_dataset_arg(&positional, "verify")?,
+        bad_record_policy,
+    })
+}
+
+// optional dataset after the flags of a command, defaults to the same dataset as the viewer
+fn parse_dataset_arg(positional: &[String], command: &str) -> Result<String, CliError> {
+    match positional {
+        [] => Ok(DEFAULT_DATASET_FILE_PATH.to_string()),
+        [dataset] => Ok(dataset.clone()),
+        _ => Err(CliError(format!("{} takes at most one dataset, got {:?}", command, positional))),
+    }
+}
+
+pub fn run(command: &Command) -> Result<(), CliError> {
+    match command {
+        Command::View { dataset } => match super::run_visualizer(dataset) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(CliError(format!("failed to load dataset {}: {}", dataset, e))),
+        },
+        Command::Convert(convert_args) => convert(convert_args),
+        Command::Render(render_args) => render(render_args),
+        Command::Timelapse(timelapse_args) => timelapse(timelapse_args),
+        Command::Heatmap(heatmap_args) => heatmap(heatmap_args),
+        Command::User(user_args) => user(user_args),
+        Command::Inspect(inspect_args) => inspect(inspect_args),
+        Command::Verify(verify_args) => verify(verify_args),
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(())
+        },
+    }
+}
+
+pub fn convert(args: &ConvertArgs) -> Result<(), CliError> {
+    println!("Converting {} ({:?}) into {} ({:?}) | canvas {:?} | limit {:?} | threads {}", args.input, args.from, args.output, args.to, args.dimensions, args.limit, args.threads);
+
+    let limit = args.limit.unwrap_or(usize::MAX);
+    let writer = DatasetWriter::new(args.dimensions)
+        .with_print_frequency(args.print_frequency)
+        .with_bad_record_policy(args.bad_record_policy)
+        .with_layout(args.layout);
+    let user_ids = Rc::new(RefCell::new(UserIdDictionary::new()));
+    let result = match args.from {
+        InputFormat::Parquet => writer.write(&args.output, || {
+            Ok(RPlaceDataReader::new(&args.input)?.with_threads(args.threads).into_iter().take(limit))
+        }),
+        InputFormat::Csv => {
+            // both passes share the dictionary so the second pass sees the ids assigned by the first
+            let result = writer.write(&args.output, || {
+                Ok(RPlaceCSVShardIterator::from_path_with_user_ids(&args.input, Rc::clone(&user_ids))?.take(limit))
+            });
+
+            if result.is_ok() {
+                let user_table_path = user_table_file_path(&args.output);
+                if let Err(e) = user_ids.borrow().write_to_file(&user_table_path) {
+                    return Err(CliError(format!("failed to write user table {}: {}", user_table_path, e)));
+                }
+                println!("Wrote {} user ids to {}", user_ids.borrow().len(), user_table_path);
+            }
+
+            result
+        },
+    };
+
+    let header = match result {
+        Ok(header) => header,
+        Err(e) => return Err(CliError(format!("failed to convert {}: {}", args.input, e))),
+    };
+    println!("Converted {} datapoints into {}", header.num_datapoints, args.output);
+
+    if let Some(interval) = args.keyframe_interval {
+        let keyframes_path = keyframes_file_path(&args.output);
+        let result = SerializedDataset::new(&args.output)
+            .map_err(|e| e.into())
+            .and_then(|dataset| write_keyframes(&dataset, &keyframes_path, interval));
+        if let Err(e) = result {
+            return Err(CliError(format!("failed to write keyframes {}: {}", keyframes_path, e)));
+        }
+    }
+
+    if args.user_index {
+        let user_index_path = user_index_file_path(&args.output);
+        let result = SerializedDataset::new(&args.output)
+            .map_err(|e| e.into())
+            .and_then(|dataset| write_user_index(&dataset, &user_index_path));
+        if let Err(e) = result {
+            return Err(CliError(format!("failed to write user index {}: {}", user_index_path, e)));
+        }
+    }
+
+    Ok(())
+}
+
+// Loads the dataset and checks the region against its canvas
+fn open_renderer(dataset: &str, region: Option<CanvasRegion>) -> Result<(HeadlessRenderer, CanvasRegion), CliError> {
+    let renderer = match HeadlessRenderer::new_with_file_path(dataset) {
+        Ok(renderer) => renderer,
+        Err(e) => return Err(CliError(format!("failed to load dataset {}: {}", dataset, e))),
+    };
+
+    let region = region.unwrap_or_else(|| CanvasRegion::new_with_canvas(&renderer.canvas));
+    if !region.is_within(&renderer.canvas) {
+        return Err(CliError(format!("region {:?} is outside of the {}x{} canvas", region, renderer.canvas.width(), renderer.canvas.height())));
+    }
+
+    Ok((renderer, region))
+}
+
+pub fn render(args: &RenderArgs) -> Result<(), CliError> {
+    let (mut renderer, region) = open_renderer(&args.dataset, args.region)?;
+    let image = renderer.render(args.timestamp, region, args.scale);
+    if let Err(e) = image.save(&args.output) {
+        return Err(CliError(format!("failed to write {}: {}", args.output, e)));
+    }
+
+    println!("Wrote {}x{} image to {}", image.width(), image.height(), args.output);
+    Ok(())
+}
+
+pub fn timelapse(args: &TimelapseArgs) -> Result<(), CliError> {
+    let (mut renderer, region) = open_renderer(&args.dataset, args.region)?;
+    let mut output = match TimelapseOutput::new_with_path(&args.output, args.frame_delay_ms) {
+        Ok(output) => output,
+        Err(e) => return Err(CliError(format!("failed to create {}: {}", args.output, e))),
+    };
+
+    let timelapse = Timelapse {
+        start_timestamp: args.start_timestamp,
+        end_timestamp: args.end_timestamp,
+        step: args.step,
+        region,
+        scale: args.scale,
+    };
+    match renderer.render_timelapse(&timelapse, &mut output) {
+        Ok(num_frames) => {
+            println!("Wrote {} frames to {}", num_frames, args.output);
+            Ok(())
+        },
+        Err(e) => Err(CliError(format!("failed to write timelapse {}: {}", args.output, e))),
+    }
+}
+
+pub fn heatmap(args: &HeatmapArgs) -> Result<(), CliError> {
+    let (renderer, region) = open_renderer(&args.dataset, args.region)?;
+    let start_timestamp = args.start_timestamp.unwrap_or(renderer.canvas.min_timestamp);
+    let end_timestamp = args.end_timestamp.unwrap_or(renderer.canvas.max_timestamp);
+    let (heatmap, image) = renderer.render_heatmap(start_timestamp, end_timestamp, args.style.clone(), region, args.scale);
+    if let Err(e) = image.save(&args.output) {
+        return Err(CliError(format!("failed to write {}: {}", args.output, e)));
+    }
+    println!("Wrote {}x{} heatmap to {} | max {} edits per pixel", image.width(), image.height(), args.output, heatmap.max_count);
+
+    if args.top > 0 {
+        println!("x,y,edits");
+        for (x, y, count) in heatmap.counts.top_pixels(args.top) {
+            println!("{},{},{}", x, y, count);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn user(args: &UserArgs) -> Result<(), CliError> {
+    let (mut renderer, region) = open_renderer(&args.dataset, args.region)?;
+    let dataset = &renderer.canvas.dataset;
+
+    let user_index_path = user_index_file_path(&args.dataset);
+    let user_index = match UserIndex::open(&user_index_path, dataset) {
+        Ok(user_index) => user_index,
+        Err(e) => return Err(CliError(format!("failed to open user index {}, convert the dataset again to write it: {}", user_index_path, e))),
+    };
+
+    // the hash table is optional, parquet datasets don't have one
+    let user_table_path = user_table_file_path(&args.dataset);
+    let user_table = UserHashTable::read_from_file(&user_table_path).ok();
+    let user_id = match args.user.parse::<u32>() {
+        Ok(user_id) => user_id,
+        Err(_) => match user_table.as_ref().and_then(|user_table| user_table.find(&args.user)) {
+            Some(user_id) => user_id,
+            None => return Err(CliError(format!("unknown user '{}', expected a user id or a user hash from {}", args.user, user_table_path))),
+        },
+    };
+
+    let edits: Vec<UserEdit> = user_index.edits(dataset, user_id).collect();
+    let user_hash = user_table.as_ref().and_then(|user_table| user_table.get(user_id)).unwrap_or("-");
+    println!("User {} ({}) placed {} pixels", user_id, user_hash, edits.len());
+    println!("timestamp,x,y,color,is_mod");
+    for edit in edits.iter().take(args.limit.unwrap_or(usize::MAX)) {
+        println!("{},{},{},{:?},{}", edit.timestamp, edit.x, edit.y, edit.color, edit.is_mod);
+    }
+
+    if let Some(overlay_path) = &args.overlay {
+        let timestamp = match (args.timestamp, edits.last()) {
+            (Some(timestamp), _) => timestamp,
+            (None, Some(edit)) => edit.timestamp,
+            (None, None) => renderer.canvas.max_timestamp,
+        };
+
+        let image = renderer.render_edits_overlay(&edits, timestamp, region, args.scale);
+        if let Err(e) = image.save(overlay_path) {
+            return Err(CliError(format!("failed to write {}: {}", overlay_path, e)));
+        }
+        println!("Wrote overlay to {}", overlay_path);
+    }
+
+    Ok(())
+}
+
+pub fn inspect(args: &InspectArgs) -> Result<(), CliError> {
+    let dataset = match SerializedDataset::new(&args.dataset) {
+        Ok(dataset) => dataset,
+        Err(e) => return Err(CliError(format!("failed to load dataset {}: {}", args.dataset, e))),
+    };
+
+    let (width, height) = (dataset.header.canvas_width, dataset.header.canvas_height);
+    if args.x >= width || args.y >= height {
+        return Err(CliError(format!("pixel {},{} is outside of the {}x{} canvas", args.x, args.y, width, height)));
+    }
+
+    let user_table = UserHashTable::read_from_file(&user_table_file_path(&args.dataset)).ok();
+    let inspection = PixelInspection::new(&dataset, args.x, args.y);
+    let timestamp = args.timestamp.unwrap_or(dataset.metadata.max_timestamp);
+    if let Err(e) = inspection.write_table(&mut std::io::stdout(), timestamp, user_table.as_ref()) {
+        return Err(CliError(format!("failed to print pixel {},{}: {}", args.x, args.y, e)));
+    }
+
+    Ok(())
+}
+
+pub fn verify(args: &VerifyArgs) -> Result<(), CliError> {
+    let start_time = Instant::now();
+    let dataset = match SerializedDataset::new(&args.dataset) {
+        Ok(dataset) => dataset,
+        Err(e) => return Err(CliError(format!("failed to load dataset {}: {}", args.dataset, e))),
+    };
+
+    match dataset.verify(args.bad_record_policy) {
+        Ok(bad_records) => {
+            println!("Verified {} datapoints of {} | {} | duration {:?}", dataset.num_datapoints(), args.dataset, bad_records, start_time.elapsed());
+            Ok(())
+        },
+        Err(e) => Err(CliError(format!("dataset {} is corrupt: {}", args.dataset, e))),
+    }
+}
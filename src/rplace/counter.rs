@@ -1,73 +1,57 @@
 use std::time::Instant;
-use rplace::{window::RedditPlaceWindowHandler, data::RPlaceDataReader, reader::custom::{write_data_to_file, read_data_from_compressed_file}};
 
+use super::reader::custom::SerializedDataset;
+use super::render::CanvasRegion;
+
+// Number of edits of every canvas pixel within a time window
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RPlaceDataCounter {
     pub counter: Vec<Vec<u32>>,
 }
 
 impl RPlaceDataCounter {
-    pub fn empty(size: usize) -> RPlaceDataCounter {
-        let mut counter = Vec::new();
-        for y in 0..size {
-            let mut row = Vec::new();
-            for x in 0..size {
-                row.push(0);
-            }
-            counter.push(row);
+    pub fn empty(width: u32, height: u32) -> RPlaceDataCounter {
+        RPlaceDataCounter {
+            counter: vec![vec![0; width as usize]; height as usize],
         }
+    }
 
-        RPlaceDataCounter { 
-            counter,
+    // Counts the edits placed after start_timestamp and at or before end_timestamp from the
+    // history lengths at both timestamps. Pixels outside of the region stay at 0
+    pub fn new_with_dataset(dataset: &SerializedDataset, region: CanvasRegion, start_timestamp: u64, end_timestamp: u64) -> RPlaceDataCounter {
+        let start_time = Instant::now();
+        let mut counter = RPlaceDataCounter::empty(dataset.header.canvas_width, dataset.header.canvas_height);
+        for y in region.y1..region.y2 {
+            for x in region.x1..region.x2 {
+                counter.counter[y as usize][x as usize] = dataset.num_edits_between(x, y, start_timestamp, end_timestamp) as u32;
+            }
         }
+
+        println!("Counted edits of x={}..{} y={}..{} between t={} and t={} | max {} | duration {:?}",
+            region.x1, region.x2, region.y1, region.y2, start_timestamp, end_timestamp, counter.max(), start_time.elapsed());
+        counter
     }
 
     pub fn increment(&mut self, x: usize, y: usize) {
         self.counter[y][x] += 1;
     }
-}
 
-fn iterate_data(file_path: &str) {
-    println!("Iterating data");
-
-    let size = 2000;
-    let mut history = 0;
-    let limit = 170_000_000;
-    let mut start_time = Instant::now();
-    let mut found_day2 = false;
-    let mut found_day3 = false;
-    //let mut dataset = RPlaceDataset::empty(size);
-    let mut counter = RPlaceDataCounter::empty(size);
-    if let Some(reader) = RPlaceDataReader::new(file_path) {
-        let iterator = reader.into_iter();
-        for datapoint in iterator.take(limit) {
-            if history % 1000000 == 0 {
-                let duration = start_time.elapsed();
-                println!("Start | At {} lines | Duration - {:?}", history, duration);
-                start_time = Instant::now();
-            }
-
-            if !found_day2 && datapoint.coordinate.x >= 1000.0 {
-                found_day2 = true;
-                println!("Day 2 | At {} lines | {:?}", history, datapoint);
-            }
-
-            if !found_day3 && datapoint.coordinate.y >= 1000.0 {
-                found_day3 = true;
-                println!("Day 3 | At {} lines | {:?}", history, datapoint);
-            }
-
-            //dataset.add(datapoint, datapoint.coordinate.x as usize, datapoint.coordinate.y as usize);
-            counter.increment(datapoint.coordinate.x as usize, datapoint.coordinate.y as usize);
-            history += 1;
-        }
+    pub fn get(&self, x: usize, y: usize) -> u32 {
+        self.counter[y][x]
     }
 
-    let mut all_counts: Vec<&u32> = counter.counter.iter().flatten().collect::<Vec<&u32>>();
-    all_counts.sort_by(|&&a, &&b| b.cmp(&a));
+    pub fn max(&self) -> u32 {
+        self.counter.iter().flatten().copied().max().unwrap_or(0)
+    }
 
-    for (i, count) in all_counts.iter().enumerate().take(100) {
-        println!("Most counts: {} {}", i, count);
+    // (x, y, count) of the n most edited pixels, most edits first
+    pub fn top_pixels(&self, n: usize) -> Vec<(u32, u32, u32)> {
+        let mut all_counts: Vec<(u32, u32, u32)> = self.counter.iter()
+            .enumerate()
+            .flat_map(|(y, row)| row.iter().enumerate().map(move |(x, count)| (x as u32, y as u32, *count)))
+            .collect();
+        all_counts.sort_by_key(|(_, _, count)| std::cmp::Reverse(*count));
+        all_counts.truncate(n);
+        all_counts
     }
-    //println!("Sleeping - History size: {}", history);
-    //thread::sleep(Duration::from_secs(30));
 }
@@ -1,7 +1,8 @@
 use std::fmt;
 use speedy2d::dimen::{Vec2, Vector2};
-use super::{pixel::PixelColor, reader::parquet::{RPlaceParquetDatapoint, RPlaceParquetDataReader, RPlaceParquetDataIterator}};
+use super::{error::RPlaceError, pixel::PixelColor, reader::parquet::{RPlaceParquetDatapoint, RPlaceParquetDataReader, RPlaceParquetDataIterator, NULL_COORDINATE}};
 
+// all timestamps are nanoseconds since the unix epoch, regardless of which reader produced them
 pub const DAY_1_START_TIMESTAMP: u64 = 1648817050315000000;
 pub const DAY_2_START_TIMESTAMP: u64 = 1648916696239000000;
 pub const DAY_3_START_TIMESTAMP: u64 = 1649012633356000000;
@@ -13,10 +14,74 @@ pub const DAY_2_START_LINE: u64 = 28_201_610;
 pub const DAY_3_START_LINE: u64 = 71_784_347;
 pub const TOTAL_LINES: u64 = 160_808_191;
 
+/// Size of a canvas and the r/place coordinate of its top left pixel. Coordinates in the raw
+/// datasets are converted to canvas indices by subtracting the origin.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CanvasDimensions {
+    pub width: u32,
+    pub height: u32,
+    pub origin_x: i32,
+    pub origin_y: i32,
+}
+
+impl CanvasDimensions {
+    /// Canvas with its top left pixel at r/place coordinate (0, 0).
+    pub fn new(width: u32, height: u32) -> CanvasDimensions {
+        CanvasDimensions::new_with_origin(width, height, 0, 0)
+    }
+
+    /// Canvas with its top left pixel at r/place coordinate (origin_x, origin_y).
+    pub fn new_with_origin(width: u32, height: u32, origin_x: i32, origin_y: i32) -> CanvasDimensions {
+        CanvasDimensions { 
+            width, 
+            height, 
+            origin_x, 
+            origin_y,
+        }
+    }
+
+    /// The 1000x1000 canvas of r/place 2017.
+    pub fn r_place_2017() -> CanvasDimensions {
+        CanvasDimensions::new(1000, 1000)
+    }
+
+    /// The 2000x2000 canvas of r/place 2022.
+    pub fn r_place_2022() -> CanvasDimensions {
+        CanvasDimensions::new(2000, 2000)
+    }
+
+    /// The 2023 canvas is centered on (0, 0) and ends at (-1500, -1000)..(1499, 999).
+    pub fn r_place_2023() -> CanvasDimensions {
+        CanvasDimensions::new_with_origin(3000, 2000, -1500, -1000)
+    }
+
+    /// Number of pixels on the canvas.
+    pub fn num_pixels(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    /// Converts an r/place coordinate into a canvas index, or None if it is outside of the canvas.
+    pub fn canvas_index(&self, coordinate: &RPlaceCoordinate) -> Option<Vector2<usize>> {
+        let x = coordinate.x as i64 - self.origin_x as i64;
+        let y = coordinate.y as i64 - self.origin_y as i64;
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return None;
+        }
+
+        Some(Vector2::new(x as usize, y as usize))
+    }
+
+    /// Inverse of `canvas_index`, the r/place coordinate of the canvas pixel at (x, y).
+    pub fn place_coordinate(&self, x: u32, y: u32) -> Vector2<i32> {
+        Vector2::new(x as i32 + self.origin_x, y as i32 + self.origin_y)
+    }
+}
+
 // TODO: need to change this to custom type? maybe use u16 for size of coordinate but that can be confusing when doing math. 
 // if we use u16, then we always have to make sure we dont overflow
 pub type RPlaceCoordinate = Vec2;
 
+/// A single pixel placement read from one of the raw datasets.
 #[derive(Copy, Clone, Debug)]
 pub struct RPlaceDatapoint {
     pub timestamp: u64,
@@ -43,27 +108,25 @@ impl Default for RPlaceDatapoint {
 }
 
 impl TryFrom<&RPlaceParquetDatapoint> for RPlaceDatapoint {
-    type Error = ();
+    type Error = RPlaceError;
 
     // Note: This will always create a pixel at coordinate (x1, y1) 
     fn try_from(item: &RPlaceParquetDatapoint) -> Result<Self, Self::Error> {
-        let is_mod = (item.x2, item.y2) != (i16::MIN, i16::MIN);
-
-        match PixelColor::try_from(item.rgb) {
-            Ok(color) => Ok(RPlaceDatapoint {
-                timestamp: item.timestamp as u64,
-                user_id: item.user_id as u32,
-                color,
-                coordinate: Vec2::new(item.x1 as f32, item.y1 as f32),
-                is_mod: is_mod,
-            }),
-            _ => Err(())
-        }
+        let is_mod = (item.x2, item.y2) != (NULL_COORDINATE, NULL_COORDINATE);
+        let color = PixelColor::try_from(item.rgb)?;
+
+        Ok(RPlaceDatapoint {
+            timestamp: item.timestamp as u64,
+            user_id: item.user_id as u32,
+            color,
+            coordinate: Vec2::new(item.x1 as f32, item.y1 as f32),
+            is_mod,
+        })
     }
 }
 
 impl TryFrom<RPlaceParquetDatapoint> for RPlaceDatapoint {
-    type Error = ();
+    type Error = RPlaceError;
 
     fn try_from(item: RPlaceParquetDatapoint) -> Result<Self, Self::Error> {
         (&item).try_into()
@@ -71,21 +134,19 @@ impl TryFrom<RPlaceParquetDatapoint> for RPlaceDatapoint {
 }
 
 impl TryFrom<&RPlaceParquetDatapoint> for Vec<RPlaceDatapoint> {
-    type Error = ();
+    type Error = RPlaceError;
     fn try_from(item: &RPlaceParquetDatapoint) -> Result<Self, Self::Error> {
+        let color = PixelColor::try_from(item.rgb)?;
         let mut vector = Vec::new();
         for y in item.y1..item.y2+1 {
             for x in item.x1..item.x2+1 {
-                match PixelColor::try_from(item.rgb) {
-                    Ok(color) => vector.push(RPlaceDatapoint {
-                        timestamp: item.timestamp as u64,
-                        user_id: item.user_id as u32,
-                        color,
-                        coordinate: Vec2::new(x as f32, y as f32),
-                        is_mod: false,
-                    }),
-                    _ => return Err(()),
-                }
+                vector.push(RPlaceDatapoint {
+                    timestamp: item.timestamp as u64,
+                    user_id: item.user_id as u32,
+                    color,
+                    coordinate: Vec2::new(x as f32, y as f32),
+                    is_mod: false,
+                });
             }
         }
 
@@ -94,12 +155,13 @@ impl TryFrom<&RPlaceParquetDatapoint> for Vec<RPlaceDatapoint> {
 }
 
 impl TryFrom<RPlaceParquetDatapoint> for Vec<RPlaceDatapoint> {
-    type Error = ();
+    type Error = RPlaceError;
     fn try_from(item: RPlaceParquetDatapoint) -> Result<Self, Self::Error> {
         (&item).try_into()
     }
 }
 
+/// Reads the deephaven parquet dataset as an iterator of `RPlaceDatapoint`s.
 pub struct RPlaceDataReader {
     file_path: String,
     reader: RPlaceParquetDataReader,
@@ -114,79 +176,148 @@ impl fmt::Debug for RPlaceDataReader {
 }
 
 impl RPlaceDataReader {
-    pub fn new(file_path: &str) -> Option<RPlaceDataReader> {
-        match RPlaceParquetDataReader::new(file_path) {
-            Some(reader) => Some(RPlaceDataReader{ 
-                file_path: file_path.to_string(), 
-                reader 
-            }),
-            None => None,
+    /// Opens the parquet file at `file_path`.
+    pub fn new(file_path: &str) -> Result<RPlaceDataReader, RPlaceError> {
+        let reader = RPlaceParquetDataReader::new(file_path)?;
+        Ok(RPlaceDataReader { 
+            file_path: file_path.to_string(), 
+            reader 
+        })
+    }
+
+    /// Row groups are decoded on num_threads threads, the datapoints keep their order.
+    pub fn with_threads(mut self, num_threads: usize) -> RPlaceDataReader {
+        self.reader = self.reader.with_threads(num_threads);
+        self
+    }
+}
+
+// Expands a moderator rectangle edit into one datapoint per pixel, row by row.
+// Both corners are inclusive.
+#[derive(Debug, Clone, Copy)]
+pub struct RPlaceRectangleIterator {
+    datapoint: RPlaceDatapoint,
+    top_left: Vector2<i32>,
+    bottom_right: Vector2<i32>,
+    position: Option<Vector2<i32>>,
+}
+
+impl RPlaceRectangleIterator {
+    pub fn new(datapoint: RPlaceDatapoint, top_left: Vector2<i32>, bottom_right: Vector2<i32>) -> RPlaceRectangleIterator {
+        let position = if top_left.x <= bottom_right.x && top_left.y <= bottom_right.y {
+            Some(top_left)
+        } else {
+            None
+        };
+
+        RPlaceRectangleIterator { 
+            datapoint, 
+            top_left, 
+            bottom_right, 
+            position,
         }
     }
 }
 
+impl Iterator for RPlaceRectangleIterator {
+    type Item = RPlaceDatapoint;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let position = self.position?;
+
+        self.position = if position.x < self.bottom_right.x {
+            Some(Vector2::new(position.x + 1, position.y))
+        } else if position.y < self.bottom_right.y {
+            Some(Vector2::new(self.top_left.x, position.y + 1))
+        } else {
+            None
+        };
+
+        let mut datapoint = self.datapoint;
+        datapoint.coordinate = Vec2::new(position.x as f32, position.y as f32);
+        datapoint.is_mod = true;
+        Some(datapoint)
+    }
+}
+
 #[derive(Debug)]
-pub struct RPlaceDataIterator<'a> {
-    iter: RPlaceParquetDataIterator<'a>,
-    position: Option<Vector2<usize>>,
+pub struct RPlaceDataIterator {
+    iter: RPlaceParquetDataIterator,
 
-    // all values should be less than the x,y limit
-    limit: Option<Vector2<usize>>,
-    cached_datapoint: Option<RPlaceDatapoint>,
+    // remaining pixels of the moderator rectangle that is being expanded
+    rectangle: Option<RPlaceRectangleIterator>,
 }
 
-impl<'a> Iterator for RPlaceDataIterator<'a> {
-    type Item = RPlaceDatapoint;
+// Bad records are returned as errors and iteration continues after them
+impl Iterator for RPlaceDataIterator {
+    type Item = Result<RPlaceDatapoint, RPlaceError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let (Some(position), Some(limit), Some(mut cached_datapoint)) = (self.position, self.limit, self.cached_datapoint) {
-            let x = position.x;
-            let y = position.y;
-            let x_end = limit.x as usize;
-            let y_end = limit.y as usize;
-            
-            cached_datapoint.coordinate = Vec2::new(x as f32, y as f32);
-
-            if x + 1 < x_end {
-                self.position = Some(Vector2::new(x + 1, y));
-            } else if y + 1 < y_end {
-                self.position = Some(Vector2::new(0, y + 1));
-            } else {
-                self.position = None;
-                self.cached_datapoint = None;
+        if let Some(rectangle) = self.rectangle.as_mut() {
+            if let Some(datapoint) = rectangle.next() {
+                return Some(Ok(datapoint));
             }
-
-            return Some(cached_datapoint);
+            self.rectangle = None;
         }
 
-        if let Some(parquet_datapoint) = self.iter.next() {
-            if let Ok(datapoint) = RPlaceDatapoint::try_from(parquet_datapoint) {
-                if datapoint.is_mod {
-                    self.position = Some(Vector2::new(parquet_datapoint.x1 as usize, parquet_datapoint.y1 as usize));
-                    self.limit = Some(Vector2::new(parquet_datapoint.x2 as usize + 1, parquet_datapoint.y2 as usize + 1));
-                    self.cached_datapoint = Some(datapoint);
-                    return self.next();
-                }
+        let parquet_datapoint = match self.iter.next()? {
+            Ok(parquet_datapoint) => parquet_datapoint,
+            Err(e) => return Some(Err(e)),
+        };
 
-                return Some(datapoint);
-            }
+        let datapoint = match RPlaceDatapoint::try_from(parquet_datapoint) {
+            Ok(datapoint) => datapoint,
+            Err(e) => return Some(Err(e)),
+        };
+
+        if datapoint.is_mod {
+            self.rectangle = Some(RPlaceRectangleIterator::new(
+                datapoint,
+                Vector2::new(parquet_datapoint.x1 as i32, parquet_datapoint.y1 as i32),
+                Vector2::new(parquet_datapoint.x2 as i32, parquet_datapoint.y2 as i32),
+            ));
+            return self.next();
         }
 
-        return None;
+        Some(Ok(datapoint))
     }
 }
 
 impl IntoIterator for RPlaceDataReader {
-    type Item = RPlaceDatapoint;
+    type Item = Result<RPlaceDatapoint, RPlaceError>;
 
-    type IntoIter = RPlaceDataIterator<'static>;
+    type IntoIter = RPlaceDataIterator;
 
     fn into_iter(self) -> Self::IntoIter {
         RPlaceDataIterator {
             iter: self.reader.into_iter(),
-            position: None,
-            limit: None,
-            cached_datapoint: None,
+            rectangle: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coordinates<I: Iterator<Item = RPlaceDatapoint>>(iter: I) -> Vec<(i32, i32)> {
+        iter.map(|datapoint| (datapoint.coordinate.x as i32, datapoint.coordinate.y as i32)).collect()
+    }
+
+    #[test]
+    fn rectangle_expansion() {
+        let datapoint = RPlaceDatapoint { timestamp: 5, user_id: 3, color: PixelColor::Red, ..Default::default() };
+        let rectangle: Vec<RPlaceDatapoint> = RPlaceRectangleIterator::new(datapoint, Vector2::new(-1, 4), Vector2::new(1, 5)).collect();
+
+        assert_eq!(coordinates(rectangle.iter().copied()), vec![(-1, 4), (0, 4), (1, 4), (-1, 5), (0, 5), (1, 5)]);
+        assert!(rectangle.iter().all(|pixel| pixel.timestamp == 5 && pixel.user_id == 3 && pixel.color == PixelColor::Red && pixel.is_mod));
+    }
+
+    #[test]
+    fn rectangle_corners() {
+        let datapoint = RPlaceDatapoint::default();
+        assert_eq!(coordinates(RPlaceRectangleIterator::new(datapoint, Vector2::new(2, 3), Vector2::new(2, 3))), vec![(2, 3)]);
+        assert_eq!(coordinates(RPlaceRectangleIterator::new(datapoint, Vector2::new(2, 3), Vector2::new(1, 3))), vec![]);
+        assert_eq!(coordinates(RPlaceRectangleIterator::new(datapoint, Vector2::new(2, 3), Vector2::new(2, 2))), vec![]);
+    }
+}
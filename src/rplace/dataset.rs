@@ -1,6 +1,10 @@
+use std::mem::{align_of, size_of};
+
 use serde::{Deserialize, Serialize};
-use super::{data::RPlaceDatapoint, pixel::PixelColor};
+use super::{data::RPlaceDatapoint, pixel::PixelColor, reader::custom::DatasetFormatError, users::NO_USER_ID};
 
+/// A datapoint of a pixel history in the custom format. The coordinate is implied by the
+/// history it is in.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RPlaceDatasetDatapoint {
     pub timestamp: u64,
@@ -24,30 +28,133 @@ impl From<RPlaceDatapoint> for RPlaceDatasetDatapoint {
     }
 }
 
+// color of every pixel before its first edit. The writer stores it in the sentinels and the canvas
+// starts with it, so seeking back to a sentinel shows the same color as a freshly loaded canvas
+pub const SENTINEL_COLOR: PixelColor = PixelColor::White;
+
 impl RPlaceDatasetDatapoint {
+    /// The sentinel that starts every pixel history.
     pub fn start() -> Self {
         RPlaceDatasetDatapoint { 
             timestamp: 0, 
-            user_id: 0, 
-            color: PixelColor::Black, 
+            user_id: NO_USER_ID, 
+            color: SENTINEL_COLOR, 
             is_mod: false, 
         }
     }
 
+    /// Bincode encoding used by datasets written before format version 3.
     pub fn to_bytes(&self) -> Vec<u8> {
         bincode::serialize(&self).unwrap()
     }
 
+    /// Inverse of `to_bytes`.
     pub fn from_bytes(bytes: &[u8]) -> Self {
         bincode::deserialize(bytes).unwrap()
     }
 
+    /// Length of the bincode encoding in bytes.
     pub fn compressed_size() -> u8 {
         let datapoint = RPlaceDatasetDatapoint::start();
         datapoint.to_bytes().len() as u8
     }
 }
 
+// On-disk datapoint of dataset format version 3 and later. Unlike the bincode encoding of
+// RPlaceDatasetDatapoint (14 bytes) the layout is fixed and 16 byte aligned, so records can be read
+// in place from the mmap. All fields are little endian.
+//
+// bytes 0..8   timestamp
+// bytes 8..12  user id
+// byte  12     color (PixelColor as u8)
+// byte  13     is_mod (0 or 1)
+// bytes 14..16 unused
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DatasetRecord {
+    pub timestamp: u64,
+    pub user_id: u32,
+    pub color: u8,
+    pub is_mod: u8,
+    pub padding: [u8; 2],
+}
+
+const _: () = assert!(size_of::<DatasetRecord>() == DatasetRecord::SIZE);
+
+impl DatasetRecord {
+    pub const SIZE: usize = 16;
+
+    // position of the color byte, also in bincode serialized RPlaceDatasetDatapoints
+    pub const COLOR_OFFSET: usize = 12;
+
+    pub fn to_bytes(&self) -> [u8; DatasetRecord::SIZE] {
+        let mut bytes = [0u8; DatasetRecord::SIZE];
+        bytes[0..8].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.user_id.to_le_bytes());
+        bytes[DatasetRecord::COLOR_OFFSET] = self.color;
+        bytes[13] = self.is_mod;
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> DatasetRecord {
+        DatasetRecord { 
+            timestamp: DatasetRecord::timestamp_from_bytes(bytes), 
+            user_id: u32::from_le_bytes(bytes[8..12].try_into().unwrap()), 
+            color: bytes[DatasetRecord::COLOR_OFFSET], 
+            is_mod: bytes[13], 
+            padding: [0; 2],
+        }
+    }
+
+    // the timestamp is the first field in both the record and the bincode layout
+    pub fn timestamp_from_bytes(bytes: &[u8]) -> u64 {
+        u64::from_le_bytes(bytes[0..8].try_into().unwrap())
+    }
+
+    // Reinterprets bytes as records without copying. Returns None if the bytes are not aligned
+    // or the target is big endian, callers then fall back to from_bytes
+    pub fn slice_from_bytes(bytes: &[u8]) -> Option<&[DatasetRecord]> {
+        if cfg!(target_endian = "big") || !bytes.len().is_multiple_of(DatasetRecord::SIZE) || !(bytes.as_ptr() as usize).is_multiple_of(align_of::<DatasetRecord>()) {
+            return None;
+        }
+
+        // safe because DatasetRecord is repr(C) without implicit padding and every bit pattern 
+        // is a valid value for each of its fields
+        Some(unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const DatasetRecord, bytes.len() / DatasetRecord::SIZE) })
+    }
+
+    // fails if the color byte is not a palette index
+    pub fn datapoint(&self) -> Result<RPlaceDatasetDatapoint, DatasetFormatError> {
+        let color = PixelColor::from_index(self.color)
+            .ok_or_else(|| DatasetFormatError::CorruptData(format!("invalid color index {}", self.color)))?;
+
+        Ok(RPlaceDatasetDatapoint { 
+            timestamp: self.timestamp, 
+            user_id: self.user_id, 
+            color, 
+            is_mod: self.is_mod != 0, 
+        })
+    }
+}
+
+impl From<&RPlaceDatasetDatapoint> for DatasetRecord {
+    fn from(item: &RPlaceDatasetDatapoint) -> Self {
+        DatasetRecord { 
+            timestamp: item.timestamp, 
+            user_id: item.user_id, 
+            color: item.color as u8, 
+            is_mod: item.is_mod as u8, 
+            padding: [0; 2],
+        }
+    }
+}
+
+impl From<RPlaceDatapoint> for DatasetRecord {
+    fn from(item: RPlaceDatapoint) -> Self {
+        DatasetRecord::from(&RPlaceDatasetDatapoint::from(item))
+    }
+}
+
 // Data is 2d matrix. Each element is a sorted array of edits for that pixel location
 #[derive(Debug)]
 pub struct RPlaceDataset {
@@ -56,11 +163,11 @@ pub struct RPlaceDataset {
 
 impl RPlaceDataset {
     // creates a dataset where the first datapoint per pixel is sentinel
-    pub fn new_with_initial_datapoint(size: usize) -> RPlaceDataset {
+    pub fn new_with_initial_datapoint(width: usize, height: usize) -> RPlaceDataset {
         let mut data = Vec::new();
-        for y in 0..size {
+        for _ in 0..height {
             let mut row = Vec::new();
-            for x in 0..size {
+            for _ in 0..width {
                 let mut vector = Vec::new();
                 let datapoint = RPlaceDatasetDatapoint::start();
                 vector.push(datapoint);
@@ -89,4 +196,141 @@ impl RPlaceDataset {
             Err(value) => return value - 1,
         }
     }
-}
\ No newline at end of file
+}
+
+// One entry of the global edit log, which lists every datapoint except the sentinels in timestamp
+// order. Edits with the same timestamp are ordered by pixel. The pixel is y * width + x and the 
+// history index is the position of the datapoint in that pixel's history.
+//
+// bytes 0..8   timestamp
+// bytes 8..12  pixel
+// bytes 12..16 history index
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EditLogEntry {
+    pub timestamp: u64,
+    pub pixel: u32,
+    pub history_index: u32,
+}
+
+const _: () = assert!(size_of::<EditLogEntry>() == EditLogEntry::SIZE);
+
+impl EditLogEntry {
+    pub const SIZE: usize = 16;
+
+    pub fn to_bytes(&self) -> [u8; EditLogEntry::SIZE] {
+        let mut bytes = [0u8; EditLogEntry::SIZE];
+        bytes[0..8].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.pixel.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.history_index.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> EditLogEntry {
+        EditLogEntry { 
+            timestamp: u64::from_le_bytes(bytes[0..8].try_into().unwrap()), 
+            pixel: u32::from_le_bytes(bytes[8..12].try_into().unwrap()), 
+            history_index: u32::from_le_bytes(bytes[12..16].try_into().unwrap()), 
+        }
+    }
+
+    // Reinterprets bytes as entries without copying, see DatasetRecord::slice_from_bytes
+    pub fn slice_from_bytes(bytes: &[u8]) -> Option<&[EditLogEntry]> {
+        if cfg!(target_endian = "big") || !bytes.len().is_multiple_of(EditLogEntry::SIZE) || !(bytes.as_ptr() as usize).is_multiple_of(align_of::<EditLogEntry>()) {
+            return None;
+        }
+
+        // safe because EditLogEntry is repr(C) without padding and every bit pattern is valid
+        Some(unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const EditLogEntry, bytes.len() / EditLogEntry::SIZE) })
+    }
+
+    pub fn coordinate(&self, canvas_width: u32) -> (u32, u32) {
+        (self.pixel % canvas_width, self.pixel / canvas_width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use strum::EnumCount;
+
+    use super::*;
+
+    fn records() -> Vec<DatasetRecord> {
+        (0..4).map(|i| DatasetRecord {
+            timestamp: 1_000_000_000_000 + i,
+            user_id: 70_000 + i as u32,
+            color: i as u8,
+            is_mod: (i % 2) as u8,
+            padding: [0; 2],
+        }).collect()
+    }
+
+    // writes the records into `buffer` at the first aligned offset plus `shift` bytes and returns
+    // that offset
+    fn encode_aligned(records: &[DatasetRecord], buffer: &mut Vec<u8>, shift: usize) -> usize {
+        buffer.resize(records.len() * DatasetRecord::SIZE + align_of::<DatasetRecord>() + shift, 0);
+        let offset = buffer.as_ptr().align_offset(align_of::<DatasetRecord>()) + shift;
+        for (i, record) in records.iter().enumerate() {
+            let start = offset + i * DatasetRecord::SIZE;
+            buffer[start..start + DatasetRecord::SIZE].copy_from_slice(&record.to_bytes());
+        }
+        offset
+    }
+
+    #[test]
+    fn record_round_trip() {
+        for record in records() {
+            let bytes = record.to_bytes();
+            assert_eq!(DatasetRecord::from_bytes(&bytes), record);
+            assert_eq!(DatasetRecord::timestamp_from_bytes(&bytes), record.timestamp);
+        }
+    }
+
+    #[test]
+    fn record_matches_bincode_offsets() {
+        let datapoint = RPlaceDatasetDatapoint { timestamp: 123_456_789, user_id: 42, color: PixelColor::Blue, is_mod: true };
+        let bincode_bytes = datapoint.to_bytes();
+        let record = DatasetRecord::from(&datapoint);
+
+        assert_eq!(DatasetRecord::timestamp_from_bytes(&bincode_bytes), 123_456_789);
+        assert_eq!(bincode_bytes[DatasetRecord::COLOR_OFFSET], PixelColor::Blue as u8);
+        assert_eq!(DatasetRecord::from_bytes(&bincode_bytes[..]).user_id, 42);
+        assert_eq!(record.to_bytes()[..14], bincode_bytes[..]);
+    }
+
+    #[test]
+    fn records_in_place() {
+        let records = records();
+        let length = records.len() * DatasetRecord::SIZE;
+
+        let mut buffer = Vec::new();
+        let offset = encode_aligned(&records, &mut buffer, 0);
+        let slice = DatasetRecord::slice_from_bytes(&buffer[offset..offset + length]);
+        if cfg!(target_endian = "little") {
+            assert_eq!(slice.unwrap(), &records[..]);
+        } else {
+            assert!(slice.is_none());
+        }
+        assert!(DatasetRecord::slice_from_bytes(&buffer[offset..offset + length - 1]).is_none());
+
+        let mut buffer = Vec::new();
+        let offset = encode_aligned(&records, &mut buffer, 1);
+        assert!(DatasetRecord::slice_from_bytes(&buffer[offset..offset + length]).is_none());
+    }
+
+    #[test]
+    fn edit_log_entry_round_trip() {
+        let entry = EditLogEntry { timestamp: 1_000_000_000_123, pixel: 2_001, history_index: 7 };
+        assert_eq!(EditLogEntry::from_bytes(&entry.to_bytes()), entry);
+        assert_eq!(entry.coordinate(1_000), (1, 2));
+    }
+
+    #[test]
+    fn invalid_record_color() {
+        let record = DatasetRecord { color: PixelColor::COUNT as u8, ..Default::default() };
+        assert!(matches!(record.datapoint(), Err(DatasetFormatError::CorruptData(_))));
+
+        let datapoint = DatasetRecord { color: PixelColor::Red as u8, is_mod: 1, ..Default::default() }.datapoint().unwrap();
+        assert_eq!((datapoint.color, datapoint.is_mod), (PixelColor::Red, true));
+    }
+}
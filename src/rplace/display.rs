@@ -137,6 +137,11 @@ impl GraphicsHelper {
         return (x1, x2, y1, y2);
     }
 
+    // tile pyramid level to draw at the current zoom, 0 draws the canvas pixels directly
+    pub fn tile_level(&self) -> usize {
+        self.canvas.tiles.level_for_pixel_size(self.canvas.pixel_size)
+    }
+
     pub fn num_rectangles_to_redraw(&self) -> u32 {
         let (x1, x2, y1, y2) = self.pixel_index_bounds_2d();
         return ((x2 - x1) * (y2 - y1)) as u32;
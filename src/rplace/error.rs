@@ -0,0 +1,171 @@
+use std::fmt;
+use std::str::FromStr;
+
+use image::ImageError;
+use parquet::errors::ParquetError;
+
+use super::reader::custom::DatasetFormatError;
+
+/// Errors of reading the raw datasets and converting them.
+#[derive(Debug)]
+pub enum RPlaceError {
+    Io(std::io::Error),
+    Format(DatasetFormatError),
+    Parquet(ParquetError),
+    Csv(csv::Error),
+    Image(ImageError),
+
+    // the color as it appeared in the source, e.g. 0x123456 or #123456
+    UnknownColor(String),
+
+    // coordinate (in r/place coordinates) that is not on the canvas
+    OutOfBounds { x: i32, y: i32 },
+}
+
+impl RPlaceError {
+    /// Bad records only affect a single datapoint and are handled by the BadRecordPolicy.
+    /// All other errors mean the source can't be read any further.
+    pub fn is_bad_record(&self) -> bool {
+        match self {
+            RPlaceError::UnknownColor(_) | RPlaceError::OutOfBounds { .. } => true,
+            RPlaceError::Csv(e) => !e.is_io_error(),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for RPlaceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RPlaceError::Io(e) => write!(f, "io error: {}", e),
+            RPlaceError::Format(e) => write!(f, "{}", e),
+            RPlaceError::Parquet(e) => write!(f, "failed to read parquet: {}", e),
+            RPlaceError::Csv(e) => write!(f, "failed to read csv: {}", e),
+            RPlaceError::Image(e) => write!(f, "failed to write image: {}", e),
+            RPlaceError::UnknownColor(color) => write!(f, "unknown pixel color {}", color),
+            RPlaceError::OutOfBounds { x, y } => write!(f, "coordinate ({}, {}) is outside of the canvas", x, y),
+        }
+    }
+}
+
+impl std::error::Error for RPlaceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RPlaceError::Io(e) => Some(e),
+            RPlaceError::Format(e) => Some(e),
+            RPlaceError::Parquet(e) => Some(e),
+            RPlaceError::Csv(e) => Some(e),
+            RPlaceError::Image(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for RPlaceError {
+    fn from(item: std::io::Error) -> Self {
+        RPlaceError::Io(item)
+    }
+}
+
+impl From<DatasetFormatError> for RPlaceError {
+    fn from(item: DatasetFormatError) -> Self {
+        RPlaceError::Format(item)
+    }
+}
+
+impl From<bincode::Error> for RPlaceError {
+    fn from(item: bincode::Error) -> Self {
+        RPlaceError::Format(DatasetFormatError::Decode(item))
+    }
+}
+
+impl From<ParquetError> for RPlaceError {
+    fn from(item: ParquetError) -> Self {
+        RPlaceError::Parquet(item)
+    }
+}
+
+impl From<csv::Error> for RPlaceError {
+    fn from(item: csv::Error) -> Self {
+        RPlaceError::Csv(item)
+    }
+}
+
+impl From<ImageError> for RPlaceError {
+    fn from(item: ImageError) -> Self {
+        RPlaceError::Image(item)
+    }
+}
+
+/// What to do with a datapoint that can't be converted (unknown color, coordinate outside of
+/// the canvas, malformed csv row).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BadRecordPolicy {
+    // drop the datapoint
+    Skip,
+
+    // drop the datapoint and count it by reason
+    #[default]
+    Count,
+
+    // stop reading and return the error
+    Fail,
+}
+
+impl BadRecordPolicy {
+    /// Returns the error back if reading should stop.
+    pub fn handle(&self, error: RPlaceError, counts: &mut BadRecordCounts) -> Result<(), RPlaceError> {
+        if !error.is_bad_record() {
+            return Err(error);
+        }
+
+        match self {
+            BadRecordPolicy::Skip => Ok(()),
+            BadRecordPolicy::Count => {
+                counts.add(&error);
+                Ok(())
+            },
+            BadRecordPolicy::Fail => Err(error),
+        }
+    }
+}
+
+impl FromStr for BadRecordPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(BadRecordPolicy::Skip),
+            "count" => Ok(BadRecordPolicy::Count),
+            "fail" => Ok(BadRecordPolicy::Fail),
+            _ => Err(format!("unknown bad record policy '{}', expected skip, count or fail", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BadRecordCounts {
+    pub unknown_color: u64,
+    pub out_of_bounds: u64,
+    pub malformed: u64,
+}
+
+impl BadRecordCounts {
+    pub fn add(&mut self, error: &RPlaceError) {
+        match error {
+            RPlaceError::UnknownColor(_) => self.unknown_color += 1,
+            RPlaceError::OutOfBounds { .. } => self.out_of_bounds += 1,
+            _ => self.malformed += 1,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.unknown_color + self.out_of_bounds + self.malformed
+    }
+}
+
+impl fmt::Display for BadRecordCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} bad records (unknown color: {}, out of bounds: {}, malformed: {})", self.total(), self.unknown_color, self.out_of_bounds, self.malformed)
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashSet;
+use std::time::Instant;
+
+use image::RgbImage;
+
+use super::canvas::{Canvas, DirtyRegion};
+use super::heatmap::{Heatmap, HeatmapStyle};
+use super::tiles::{pixel_rgb, TileLevel};
+
+// color of pixels that are not highlighted by an overlay
+pub fn dimmed_rgb(rgb: [u8; 3]) -> [u8; 3] {
+    rgb.map(|value| value / 4)
+}
+
+// What every canvas pixel is drawn as
+#[derive(Debug, Clone, Default)]
+pub enum RenderMode {
+    // the current color
+    #[default]
+    Colors,
+
+    // the current color for pixels whose current datapoint was placed by one of the users,
+    // everything else dimmed
+    UserOverlay(HashSet<u32>),
+
+    // the edit count of the heatmap's time window, independent of the canvas timestamp
+    Heatmap(Heatmap),
+}
+
+// RGB copy of a canvas that regions are drawn from. Only the dirty tiles reported by the canvas
+// are rewritten, and regions are copied out row by row into a scratch buffer so they can be
+// uploaded as a single image. Nothing here needs a window, so headless renders use it too.
+#[derive(Debug)]
+pub struct FrameBuffer {
+    pub width: u32,
+    pub height: u32,
+
+    // rgb bytes of every canvas pixel, row by row
+    canvas_rgb: Vec<u8>,
+
+    // rgb bytes of the last region, sized to fit the whole canvas
+    region_rgb: Vec<u8>,
+
+    mode: RenderMode,
+
+    // set when the mode changes, every pixel is rewritten on the next update
+    stale: bool,
+}
+
+impl FrameBuffer {
+    pub fn new(canvas: &Canvas) -> FrameBuffer {
+        let canvas_rgb: Vec<u8> = canvas.pixels.iter().flatten().flat_map(pixel_rgb).collect();
+        FrameBuffer {
+            width: canvas.width(),
+            height: canvas.height(),
+            region_rgb: vec![0; canvas_rgb.len()],
+            canvas_rgb,
+            mode: RenderMode::Colors,
+            stale: false,
+        }
+    }
+
+    pub fn mode(&self) -> &RenderMode {
+        &self.mode
+    }
+
+    // Only switching from colors to colors keeps the pixels, comparing the users or heatmap counts
+    // of two modes would cost about as much as redrawing
+    pub fn set_mode(&mut self, mode: RenderMode) {
+        if !matches!((&self.mode, &mode), (RenderMode::Colors, RenderMode::Colors)) {
+            self.stale = true;
+        }
+        self.mode = mode;
+    }
+
+    // Changes the style of a shown heatmap in place, the counts are kept
+    pub fn set_heatmap_style(&mut self, style: &HeatmapStyle) {
+        if let RenderMode::Heatmap(heatmap) = &mut self.mode {
+            if heatmap.style != *style {
+                heatmap.set_style(style.clone());
+                self.stale = true;
+            }
+        }
+    }
+
+    // Tile pyramid levels only hold the current colors, other modes have to be drawn per pixel
+    pub fn supports_tiles(&self) -> bool {
+        matches!(self.mode, RenderMode::Colors)
+    }
+
+    fn rgb(&self, canvas: &Canvas, x: usize, y: usize) -> [u8; 3] {
+        let rgb = pixel_rgb(&canvas.pixels[y][x]);
+        match &self.mode {
+            RenderMode::Colors => rgb,
+            RenderMode::UserOverlay(users) => {
+                if users.contains(&canvas.current_datapoint(x as u32, y as u32).user_id) {
+                    rgb
+                } else {
+                    dimmed_rgb(rgb)
+                }
+            },
+            RenderMode::Heatmap(heatmap) => heatmap.rgb(x, y),
+        }
+    }
+
+    // Rewrites the pixels that changed since the last update
+    pub fn update(&mut self, canvas: &mut Canvas) {
+        assert_eq!((self.width, self.height), (canvas.width(), canvas.height()), "frame buffer was created for a different canvas");
+        let dirty = canvas.take_dirty_tiles();
+        let regions = if self.stale {
+            self.stale = false;
+            vec![DirtyRegion { x1: 0, x2: self.width as usize, y1: 0, y2: self.height as usize }]
+        } else if dirty.is_empty() {
+            return;
+        } else {
+            dirty.regions()
+        };
+
+        let start_time = Instant::now();
+        let width = self.width as usize;
+        for region in regions.iter() {
+            for y in region.y1..region.y2 {
+                for x in region.x1..region.x2 {
+                    let idx = (y * width + x) * 3;
+                    let rgb = self.rgb(canvas, x, y);
+                    self.canvas_rgb[idx..idx + 3].copy_from_slice(&rgb);
+                }
+            }
+        }
+        let num_pixels: usize = regions.iter().map(|region| region.num_pixels()).sum();
+        println!("Updated {} dirty pixels in {} regions | duration {:?}", num_pixels, regions.len(), start_time.elapsed());
+    }
+
+    // rgb bytes of the canvas pixels x1..x2, y1..y2, row by row
+    pub fn region(&mut self, canvas: &mut Canvas, x1: usize, x2: usize, y1: usize, y2: usize) -> &[u8] {
+        self.update(canvas);
+
+        let width = self.width as usize;
+        let x_width = x2 - x1;
+        let y_height = y2 - y1;
+        let region_bytes = &mut self.region_rgb[0..(x_width * y_height * 3)];
+        for (region_y, canvas_y) in (y1..y2).enumerate() {
+            let canvas_start = (canvas_y * width + x1) * 3;
+            let region_start = region_y * x_width * 3;
+            region_bytes[region_start..region_start + x_width * 3].copy_from_slice(&self.canvas_rgb[canvas_start..canvas_start + x_width * 3]);
+        }
+
+        region_bytes
+    }
+
+    // rgb bytes of the tiles tx1..tx2, ty1..ty2 of a tile pyramid level, row by row
+    pub fn tile_region(&mut self, tiles: &TileLevel, tx1: u32, tx2: u32, ty1: u32, ty2: u32) -> &[u8] {
+        let x_width = (tx2 - tx1) as usize;
+        let y_height = (ty2 - ty1) as usize;
+        let region_bytes = &mut self.region_rgb[0..(x_width * y_height * 3)];
+        for (region_y, tile_y) in (ty1..ty2).enumerate() {
+            for (region_x, tile_x) in (tx1..tx2).enumerate() {
+                let idx = ((region_y * x_width) + region_x) * 3;
+                region_bytes[idx..idx + 3].copy_from_slice(&tiles.rgb(tile_x, tile_y));
+            }
+        }
+
+        region_bytes
+    }
+
+    pub fn region_image(&mut self, canvas: &mut Canvas, x1: usize, x2: usize, y1: usize, y2: usize) -> RgbImage {
+        let bytes = self.region(canvas, x1, x2, y1, y2).to_vec();
+        RgbImage::from_raw((x2 - x1) as u32, (y2 - y1) as u32, bytes).unwrap()
+    }
+}
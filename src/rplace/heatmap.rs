@@ -0,0 +1,182 @@
+use std::str::FromStr;
+use std::time::Instant;
+
+use image::RgbImage;
+
+use super::counter::RPlaceDataCounter;
+use super::reader::custom::SerializedDataset;
+use super::render::{scale_image, CanvasRegion, HeadlessRenderer};
+
+// ramps that can be picked by name, the first one is the default
+pub const NAMED_COLOR_RAMPS: [&str; 4] = ["heat", "viridis", "gray", "blue"];
+
+// Colors that edit counts are mapped to. The lowest count gets the first stop, the highest
+// count the last one and everything in between is interpolated between the two closest stops
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorRamp {
+    pub name: String,
+    pub stops: Vec<[u8; 3]>,
+}
+
+impl ColorRamp {
+    pub fn named(name: &str) -> Option<ColorRamp> {
+        let stops = match name {
+            "heat" => vec![[0, 0, 0], [128, 0, 0], [255, 64, 0], [255, 200, 0], [255, 255, 255]],
+            "viridis" => vec![[68, 1, 84], [59, 82, 139], [33, 145, 140], [94, 201, 98], [253, 231, 37]],
+            "gray" => vec![[0, 0, 0], [255, 255, 255]],
+            "blue" => vec![[8, 16, 48], [0, 100, 200], [120, 220, 255]],
+            _ => return None,
+        };
+
+        Some(ColorRamp {
+            name: name.to_string(),
+            stops,
+        })
+    }
+
+    // the ramp after this one in NAMED_COLOR_RAMPS, custom ramps continue with the first one
+    pub fn next_named(&self) -> ColorRamp {
+        let position = NAMED_COLOR_RAMPS.iter().position(|name| *name == self.name);
+        let next = position.map_or(0, |position| (position + 1) % NAMED_COLOR_RAMPS.len());
+        ColorRamp::named(NAMED_COLOR_RAMPS[next]).unwrap()
+    }
+
+    // t is clamped to 0..=1
+    pub fn rgb(&self, t: f32) -> [u8; 3] {
+        if self.stops.len() == 1 {
+            return self.stops[0];
+        }
+
+        let position = t.clamp(0.0, 1.0) * (self.stops.len() - 1) as f32;
+        let idx = (position.floor() as usize).min(self.stops.len() - 2);
+        let fraction = position - idx as f32;
+        let (from, to) = (self.stops[idx], self.stops[idx + 1]);
+        [0, 1, 2].map(|channel| (from[channel] as f32 + (to[channel] as f32 - from[channel] as f32) * fraction).round() as u8)
+    }
+}
+
+impl Default for ColorRamp {
+    fn default() -> Self {
+        ColorRamp::named(NAMED_COLOR_RAMPS[0]).unwrap()
+    }
+}
+
+fn parse_hex_color(s: &str) -> Option<[u8; 3]> {
+    let hex = s.trim().trim_start_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+// A ramp name, or at least two comma separated hex colors like #000000,#ff0000,#ffffff
+impl FromStr for ColorRamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(ramp) = ColorRamp::named(s) {
+            return Ok(ramp);
+        }
+
+        let stops: Option<Vec<[u8; 3]>> = s.split(',').map(parse_hex_color).collect();
+        match stops {
+            Some(stops) if stops.len() >= 2 => Ok(ColorRamp {
+                name: s.to_string(),
+                stops,
+            }),
+            _ => Err(format!("unknown color ramp '{}', expected one of {} or comma separated colors like #000000,#ff0000", s, NAMED_COLOR_RAMPS.join(", "))),
+        }
+    }
+}
+
+// How edit counts are spread over the color ramp
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HeatmapScale {
+    Linear,
+
+    // a few heavily edited pixels would otherwise push everything else to the bottom of the ramp
+    #[default]
+    Log,
+}
+
+impl FromStr for HeatmapScale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(HeatmapScale::Linear),
+            "log" => Ok(HeatmapScale::Log),
+            _ => Err(format!("unknown heatmap scale '{}', expected linear or log", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeatmapStyle {
+    pub ramp: ColorRamp,
+    pub scale: HeatmapScale,
+}
+
+// Edit counts of a time window with the colors they are drawn in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heatmap {
+    pub start_timestamp: u64,
+    pub end_timestamp: u64,
+    pub counts: RPlaceDataCounter,
+
+    // count that gets the last color of the ramp
+    pub max_count: u32,
+    pub style: HeatmapStyle,
+}
+
+impl Heatmap {
+    // Counts the edits in the region after start_timestamp and at or before end_timestamp
+    pub fn new(dataset: &SerializedDataset, region: CanvasRegion, start_timestamp: u64, end_timestamp: u64, style: HeatmapStyle) -> Heatmap {
+        let counts = RPlaceDataCounter::new_with_dataset(dataset, region, start_timestamp, end_timestamp);
+        Heatmap {
+            start_timestamp,
+            end_timestamp,
+            max_count: counts.max(),
+            counts,
+            style,
+        }
+    }
+
+    pub fn set_style(&mut self, style: HeatmapStyle) {
+        self.style = style;
+    }
+
+    // position of a count on the color ramp
+    pub fn intensity(&self, count: u32) -> f32 {
+        if self.max_count == 0 {
+            return 0.0;
+        }
+
+        match self.style.scale {
+            HeatmapScale::Linear => count as f32 / self.max_count as f32,
+            HeatmapScale::Log => (count as f32).ln_1p() / (self.max_count as f32).ln_1p(),
+        }
+    }
+
+    pub fn rgb(&self, x: usize, y: usize) -> [u8; 3] {
+        self.style.ramp.rgb(self.intensity(self.counts.get(x, y)))
+    }
+}
+
+impl HeadlessRenderer {
+    // Draws the edit counts of the region between the timestamps, the most edited pixel of the
+    // region gets the last color of the ramp
+    pub fn render_heatmap(&self, start_timestamp: u64, end_timestamp: u64, style: HeatmapStyle, region: CanvasRegion, scale: u32) -> (Heatmap, RgbImage) {
+        let start_time = Instant::now();
+        let heatmap = Heatmap::new(&self.canvas.dataset, region, start_timestamp, end_timestamp, style);
+        let image = RgbImage::from_fn(region.width(), region.height(), |x, y| {
+            image::Rgb(heatmap.rgb((region.x1 + x) as usize, (region.y1 + y) as usize))
+        });
+
+        println!("Rendered heatmap of x={}..{} y={}..{} between t={} and t={} | ramp {} | {:?} scale | duration {:?}",
+            region.x1, region.x2, region.y1, region.y2, start_timestamp, end_timestamp, heatmap.style.ramp.name, heatmap.style.scale, start_time.elapsed());
+        (heatmap, scale_image(image, scale))
+    }
+}
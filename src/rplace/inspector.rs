@@ -0,0 +1,67 @@
+use std::io::{self, Write};
+
+use super::pixel::PixelColor;
+use super::reader::custom::SerializedDataset;
+use super::users::UserHashTable;
+
+// A single edit of a pixel
+#[derive(Debug, Clone, Copy)]
+pub struct PixelEdit {
+    pub timestamp: u64,
+    pub user_id: u32,
+    pub color: PixelColor,
+    pub is_mod: bool,
+}
+
+// Every edit of a pixel in timestamp order
+#[derive(Debug, Clone)]
+pub struct PixelInspection {
+    pub x: u32,
+    pub y: u32,
+    pub edits: Vec<PixelEdit>,
+}
+
+impl PixelInspection {
+    pub fn new(dataset: &SerializedDataset, x: u32, y: u32) -> PixelInspection {
+        // the sentinel at index 0 of the history is not an edit
+        let edits = dataset.pixel_history(x, y).iter()
+            .skip(1)
+            .map(|datapoint| PixelEdit {
+                timestamp: datapoint.timestamp,
+                user_id: datapoint.user_id,
+                color: datapoint.color,
+                is_mod: datapoint.is_mod,
+            })
+            .collect();
+
+        PixelInspection {
+            x,
+            y,
+            edits,
+        }
+    }
+
+    // Position in self.edits of the edit that sets the color at the timestamp, None before the first edit
+    pub fn current_edit(&self, timestamp: u64) -> Option<usize> {
+        self.edits.partition_point(|edit| edit.timestamp <= timestamp).checked_sub(1)
+    }
+
+    // Writes one line per edit and marks the current edit with a *
+    pub fn write_table<W: Write>(&self, writer: &mut W, timestamp: u64, user_table: Option<&UserHashTable>) -> io::Result<()> {
+        let current_edit = self.current_edit(timestamp);
+        match current_edit {
+            Some(current_edit) => writeln!(writer, "Pixel ({}, {}) | {} edits | edit {} is current at t={}", self.x, self.y, self.edits.len(), current_edit + 1, timestamp)?,
+            None => writeln!(writer, "Pixel ({}, {}) | {} edits | not edited yet at t={}", self.x, self.y, self.edits.len(), timestamp)?,
+        }
+
+        writeln!(writer, "  {:>6} {:>20} {:>10} {:<16} {:<5} user_hash", "edit", "timestamp", "user_id", "color", "mod")?;
+        for (i, edit) in self.edits.iter().enumerate() {
+            let marker = if Some(i) == current_edit { '*' } else { ' ' };
+            let user_hash = user_table.and_then(|user_table| user_table.get(edit.user_id)).unwrap_or("-");
+            writeln!(writer, "{} {:>6} {:>20} {:>10} {:<16} {:<5} {}",
+                marker, i + 1, edit.timestamp, edit.user_id, format!("{:?}", edit.color), edit.is_mod, user_hash)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::time::Instant;
+
+use memmap::{Mmap, MmapMut};
+use serde::{Serialize, Deserialize};
+
+use super::error::RPlaceError;
+use super::pixel::PixelColor;
+use super::reader::custom::{DatasetFormatError, SerializedDataset};
+
+pub const KEYFRAMES_MAGIC: [u8; 8] = *b"RPLACEKF";
+// version 1 also stored the history index of every pixel, those files are not read anymore
+pub const KEYFRAMES_FORMAT_VERSION: u32 = 2;
+
+// one keyframe every hour of data. a keyframe of the 2022 canvas is 4 MB, about 340 MB for the
+// whole dataset
+pub const DEFAULT_KEYFRAME_INTERVAL: u64 = 60 * 60 * 1_000_000_000;
+
+// Keyframes are full canvas snapshots stored next to a dataset. Every keyframe holds the color
+// of each pixel at its timestamp, so seeking far only has to apply the edits of the edit log 
+// between the keyframe before the target timestamp and the target.
+//
+// header (bincode, fixed size)
+// keyframe k at data_offset + k * keyframe_length
+//     - color u8 * width*height
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyframeHeader {
+    pub magic: [u8; 8],
+    pub version: u32,
+    pub canvas_width: u32,
+    pub canvas_height: u32,
+
+    // used to detect a sidecar that was written for a different dataset
+    pub dataset_num_datapoints: u64,
+
+    // keyframe k is at first_timestamp + k * interval
+    pub first_timestamp: u64,
+    pub interval: u64,
+    pub num_keyframes: u64,
+    pub data_offset: u64,
+}
+
+impl KeyframeHeader {
+    pub fn new(dataset: &SerializedDataset, interval: u64) -> KeyframeHeader {
+        let (min_timestamp, max_timestamp) = dataset.timestamp_range();
+        let mut header = KeyframeHeader {
+            magic: KEYFRAMES_MAGIC,
+            version: KEYFRAMES_FORMAT_VERSION,
+            canvas_width: dataset.header.canvas_width,
+            canvas_height: dataset.header.canvas_height,
+            dataset_num_datapoints: dataset.num_datapoints(),
+            first_timestamp: min_timestamp,
+            interval,
+            num_keyframes: (max_timestamp - min_timestamp) / interval + 1,
+            data_offset: 0,
+        };
+        header.data_offset = header.to_bytes().len() as u64;
+        header
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    pub fn num_pixels(&self) -> u64 {
+        self.canvas_width as u64 * self.canvas_height as u64
+    }
+
+    pub fn keyframe_length(&self) -> u64 {
+        self.num_pixels()
+    }
+
+    pub fn file_length(&self) -> u64 {
+        self.data_offset + self.num_keyframes * self.keyframe_length()
+    }
+
+    // checks the header against the dataset it is used with
+    pub fn validate(&self, dataset: &SerializedDataset, file_length: u64) -> Result<(), DatasetFormatError> {
+        if self.magic != KEYFRAMES_MAGIC {
+            return Err(DatasetFormatError::InvalidHeader("keyframes file is missing the magic bytes".to_string()));
+        }
+
+        if self.version != KEYFRAMES_FORMAT_VERSION {
+            return Err(DatasetFormatError::UnsupportedVersion(self.version));
+        }
+
+        if (self.canvas_width, self.canvas_height) != (dataset.header.canvas_width, dataset.header.canvas_height)
+            || self.dataset_num_datapoints != dataset.num_datapoints() {
+            return Err(DatasetFormatError::InvalidHeader("keyframes were written for a different dataset".to_string()));
+        }
+
+        if self.interval == 0 || self.num_keyframes == 0 {
+            return Err(DatasetFormatError::InvalidHeader(format!("invalid keyframe interval {} or count {}", self.interval, self.num_keyframes)));
+        }
+
+        if self.file_length() > file_length {
+            return Err(DatasetFormatError::Truncated { section: "keyframes", expected: self.file_length(), actual: file_length });
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Keyframes {
+    mmap: Mmap,
+    pub header: KeyframeHeader,
+}
+
+impl fmt::Debug for Keyframes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Keyframes")
+         .field("header", &self.header)
+         .finish()
+    }
+}
+
+impl Keyframes {
+    pub fn open(file_path: &str, dataset: &SerializedDataset) -> Result<Keyframes, DatasetFormatError> {
+        let file = File::open(file_path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        let header: KeyframeHeader = bincode::deserialize(&mmap)?;
+        header.validate(dataset, mmap.len() as u64)?;
+
+        Ok(Keyframes {
+            mmap,
+            header,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.header.num_keyframes as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.header.num_keyframes == 0
+    }
+
+    pub fn timestamp(&self, keyframe: usize) -> u64 {
+        self.header.first_timestamp + keyframe as u64 * self.header.interval
+    }
+
+    // the last keyframe at or before the timestamp
+    pub fn keyframe_before(&self, timestamp: u64) -> Option<usize> {
+        if timestamp < self.header.first_timestamp {
+            return None;
+        }
+
+        let keyframe = (timestamp - self.header.first_timestamp) / self.header.interval;
+        Some(keyframe.min(self.header.num_keyframes - 1) as usize)
+    }
+
+    fn pixel_idx(&self, x: u32, y: u32) -> u64 {
+        y as u64 * self.header.canvas_width as u64 + x as u64
+    }
+
+    // color of (x, y) at the keyframe, None if the color byte is not a palette index
+    pub fn color(&self, keyframe: usize, x: u32, y: u32) -> Option<PixelColor> {
+        let keyframe_start = self.header.data_offset + keyframe as u64 * self.header.keyframe_length();
+        PixelColor::from_index(self.mmap[(keyframe_start + self.pixel_idx(x, y)) as usize])
+    }
+}
+
+// side table that is written next to a dataset
+pub fn keyframes_file_path(dataset_file_path: &str) -> String {
+    format!("{}.keyframes", dataset_file_path)
+}
+
+// Walks every pixel history once and records its color at each keyframe timestamp
+pub fn write_keyframes(dataset: &SerializedDataset, file_path: &str, interval: u64) -> Result<KeyframeHeader, RPlaceError> {
+    let start_time = Instant::now();
+    let header = KeyframeHeader::new(dataset, interval);
+
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(file_path)?;
+    file.set_len(header.file_length())?;
+    let mut mmap = unsafe { MmapMut::map_mut(&file)? };
+    let header_bytes = header.to_bytes();
+    mmap[0..header_bytes.len()].copy_from_slice(&header_bytes);
+
+    for y in 0..header.canvas_height {
+        for x in 0..header.canvas_width {
+            let pixel_idx = y as u64 * header.canvas_width as u64 + x as u64;
+            let history: Vec<_> = dataset.pixel_history(x, y).iter().collect();
+
+            let mut idx = 0;
+            for keyframe in 0..header.num_keyframes {
+                let timestamp = header.first_timestamp + keyframe * interval;
+                while idx + 1 < history.len() && history[idx + 1].timestamp <= timestamp {
+                    idx += 1;
+                }
+
+                let keyframe_start = header.data_offset + keyframe * header.keyframe_length();
+                mmap[(keyframe_start + pixel_idx) as usize] = history[idx].color as u8;
+            }
+        }
+    }
+    mmap.flush()?;
+
+    println!("Wrote {} keyframes to {} | interval {}s | {} bytes | duration {:?}",
+        header.num_keyframes, file_path, interval / 1_000_000_000, header.file_length(), start_time.elapsed());
+    Ok(header)
+}
@@ -3,7 +3,9 @@ use strum::{IntoEnumIterator, EnumCount};
 use strum_macros::{EnumIter, EnumCount};
 
 use super::canvas::CanvasPixel;
+use super::error::RPlaceError;
 
+/// The colors of the r/place palettes. The discriminant is the palette index stored in datasets.
 #[derive(Clone, Copy, Debug, EnumIter, EnumCount, PartialEq, Eq, Serialize_repr, Deserialize_repr,)]
 #[repr(u8)]
 pub enum PixelColor {
@@ -48,8 +50,20 @@ impl Default for PixelColor {
     }
 }
 
+impl PixelColor {
+    /// Inverse of `color as u8`, None if `index` is not in the palette.
+    pub fn from_index(index: u8) -> Option<PixelColor> {
+        if (index as usize) < PixelColor::COUNT {
+            // safe because PixelColor is repr(u8) with discriminants 0..COUNT
+            return Some(unsafe { std::mem::transmute::<u8, PixelColor>(index) });
+        }
+
+        None
+    }
+}
+
 impl TryFrom<&String> for PixelColor {
-    type Error = ();
+    type Error = RPlaceError;
 
     fn try_from(item: &String) -> Result<Self, Self::Error> {
         let no_prefix_item = (*item).trim_start_matches("#");
@@ -59,10 +73,10 @@ impl TryFrom<&String> for PixelColor {
             Ok(value) => {
                 match PixelColor::try_from(value) {
                     Ok(color) => Ok(color),
-                    Err(_)    => Err(()),
+                    Err(_)    => Err(RPlaceError::UnknownColor(item.clone())),
                 }
             },
-            _ => Err(()),
+            _ => Err(RPlaceError::UnknownColor(item.clone())),
         }
     }
 }
@@ -113,7 +127,7 @@ impl From<PixelColor> for u64 {
 }
 
 impl TryFrom<u32> for PixelColor {
-    type Error = ();
+    type Error = RPlaceError;
 
     fn try_from(item: u32) -> Result<Self, Self::Error> {
         for color in PixelColor::iter() {
@@ -122,12 +136,12 @@ impl TryFrom<u32> for PixelColor {
             }
         }
 
-        return Err(());
+        Err(RPlaceError::UnknownColor(format!("{:#08x}", item)))
     }
 }
 
 impl TryFrom<u64> for PixelColor {
-    type Error = ();
+    type Error = RPlaceError;
 
     fn try_from(item: u64) -> Result<Self, Self::Error> {
         for color in PixelColor::iter() {
@@ -136,8 +150,7 @@ impl TryFrom<u64> for PixelColor {
             }
         }
 
-        println!("No color match! Input=0x{:#08x}", item);
-        return Err(());
+        Err(RPlaceError::UnknownColor(format!("{:#08x}", item)))
     }
 }
 
@@ -1,3 +1,4 @@
 pub mod csv;
 pub mod parquet;
-pub mod custom;
\ No newline at end of file
+pub mod custom;
+pub mod compressed;
\ No newline at end of file
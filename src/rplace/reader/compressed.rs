@@ -0,0 +1,384 @@
+use std::mem::size_of;
+
+use strum::EnumCount;
+
+use super::custom::DatasetFormatError;
+use crate::rplace::{dataset::{DatasetRecord, RPlaceDatasetDatapoint}, pixel::PixelColor};
+
+// Encoding of a single pixel history in the compressed layout.
+//
+// block
+//     - length    u32 (number of datapoints in the history)
+//     - skip index, one entry per chunk of HISTORY_CHUNK_SIZE datapoints
+//         - first timestamp u64 (absolute)
+//         - chunk offset    u32 (bytes from the start of the payload)
+//     - payload, chunks back to back
+//
+// datapoint in a chunk
+//     - timestamp delta varint (to the previous datapoint, omitted for the first datapoint of a chunk)
+//     - color     u8 (palette index in bits 0..5, is_mod in bit 5)
+//     - user      varint
+//
+// Lookups binary search the skip index and decode at most one chunk, so they stay logarithmic
+// in the history length. Decoding never reads past the given bytes, corrupt or truncated blocks
+// are reported as DatasetFormatErrors.
+pub const HISTORY_CHUNK_SIZE: usize = 64;
+
+const SKIP_ENTRY_SIZE: usize = size_of::<u64>() + size_of::<u32>();
+const COLOR_BITS: u8 = 5;
+const COLOR_MASK: u8 = (1 << COLOR_BITS) - 1;
+const MOD_FLAG: u8 = 1 << COLOR_BITS;
+
+// a u64 takes at most 10 varint bytes
+const MAX_VARINT_SIZE: usize = 10;
+
+// every palette index has to fit in the color bits
+const _: () = assert!(PixelColor::COUNT <= 1 << COLOR_BITS);
+
+pub fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+// returns the value and the number of bytes it took
+pub fn read_varint(bytes: &[u8]) -> Result<(u64, usize), DatasetFormatError> {
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().take(MAX_VARINT_SIZE).enumerate() {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+
+    Err(DatasetFormatError::CorruptData(format!("varint is not terminated after {} bytes", bytes.len().min(MAX_VARINT_SIZE))))
+}
+
+// Encodes a pixel history into a block. The records have to be in timestamp order
+pub fn encode_history(records: &[DatasetRecord]) -> Vec<u8> {
+    let num_chunks = records.len().div_ceil(HISTORY_CHUNK_SIZE);
+    let mut skip_index = Vec::with_capacity(num_chunks * SKIP_ENTRY_SIZE);
+    let mut payload = Vec::with_capacity(records.len() * 4);
+
+    for chunk in records.chunks(HISTORY_CHUNK_SIZE) {
+        skip_index.extend_from_slice(&chunk[0].timestamp.to_le_bytes());
+        skip_index.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+
+        let mut previous_timestamp = chunk[0].timestamp;
+        for (i, record) in chunk.iter().enumerate() {
+            if i > 0 {
+                assert!(record.timestamp >= previous_timestamp, "history is not in timestamp order");
+                write_varint(&mut payload, record.timestamp - previous_timestamp);
+            }
+            previous_timestamp = record.timestamp;
+
+            let mod_flag = if record.is_mod != 0 { MOD_FLAG } else { 0 };
+            payload.push((record.color & COLOR_MASK) | mod_flag);
+            write_varint(&mut payload, record.user_id as u64);
+        }
+    }
+
+    let mut block = Vec::with_capacity(size_of::<u32>() + skip_index.len() + payload.len());
+    block.extend_from_slice(&(records.len() as u32).to_le_bytes());
+    block.extend_from_slice(&skip_index);
+    block.extend_from_slice(&payload);
+    block
+}
+
+// A pixel history block read in place. The slice may extend past the end of the block
+#[derive(Debug, Clone, Copy)]
+pub struct CompressedHistory<'a> {
+    skip_index: &'a [u8],
+    payload: &'a [u8],
+    length: usize,
+}
+
+impl<'a> CompressedHistory<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<CompressedHistory<'a>, DatasetFormatError> {
+        let length_bytes = bytes.get(0..size_of::<u32>()).ok_or(DatasetFormatError::Truncated {
+            section: "history block",
+            expected: size_of::<u32>() as u64,
+            actual: bytes.len() as u64,
+        })?;
+        let length = u32::from_le_bytes(length_bytes.try_into().unwrap()) as usize;
+
+        let skip_index_end = size_of::<u32>() + length.div_ceil(HISTORY_CHUNK_SIZE) * SKIP_ENTRY_SIZE;
+        let skip_index = bytes.get(size_of::<u32>()..skip_index_end).ok_or(DatasetFormatError::Truncated {
+            section: "history block",
+            expected: skip_index_end as u64,
+            actual: bytes.len() as u64,
+        })?;
+
+        Ok(CompressedHistory {
+            skip_index,
+            payload: &bytes[skip_index_end..],
+            length,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    fn num_chunks(&self) -> usize {
+        self.skip_index.len() / SKIP_ENTRY_SIZE
+    }
+
+    fn chunk_timestamp(&self, chunk: usize) -> u64 {
+        let start = chunk * SKIP_ENTRY_SIZE;
+        u64::from_le_bytes(self.skip_index[start..start + 8].try_into().unwrap())
+    }
+
+    fn chunk_offset(&self, chunk: usize) -> usize {
+        let start = chunk * SKIP_ENTRY_SIZE + 8;
+        u32::from_le_bytes(self.skip_index[start..start + 4].try_into().unwrap()) as usize
+    }
+
+    // payload bytes starting at `position`, empty if the position is past the end
+    fn payload_from(&self, position: usize) -> &'a [u8] {
+        self.payload.get(position..).unwrap_or_default()
+    }
+
+    // Reads the timestamp delta at `position` and adds it to the record. Returns the number of bytes read
+    fn decode_timestamp(&self, position: usize, record: &mut DatasetRecord) -> Result<usize, DatasetFormatError> {
+        let (delta, size) = read_varint(self.payload_from(position))?;
+        record.timestamp = record.timestamp.checked_add(delta)
+            .ok_or_else(|| DatasetFormatError::CorruptData(format!("timestamp delta {} overflows timestamp {}", delta, record.timestamp)))?;
+        Ok(size)
+    }
+
+    // Reads the color byte and user id at `position` into the record. Returns the number of bytes read
+    fn decode_color_and_user(&self, position: usize, record: &mut DatasetRecord) -> Result<usize, DatasetFormatError> {
+        let color = *self.payload.get(position).ok_or(DatasetFormatError::Truncated {
+            section: "history payload",
+            expected: position as u64 + 1,
+            actual: self.payload.len() as u64,
+        })?;
+        if color & !(COLOR_MASK | MOD_FLAG) != 0 {
+            return Err(DatasetFormatError::CorruptData(format!("invalid color byte {:#04x}", color)));
+        }
+        record.color = color & COLOR_MASK;
+        record.is_mod = (color & MOD_FLAG != 0) as u8;
+
+        let (user_id, size) = read_varint(self.payload_from(position + 1))?;
+        record.user_id = u32::try_from(user_id)
+            .map_err(|_| DatasetFormatError::CorruptData(format!("user id {} does not fit in 32 bits", user_id)))?;
+        Ok(1 + size)
+    }
+
+    // Decodes the chunk up to and including `index`, stopping early once a timestamp is past
+    // `stop_after`. Returns the last decoded record and its index
+    fn decode_chunk(&self, chunk: usize, index: usize, stop_after: u64) -> Result<(usize, DatasetRecord), DatasetFormatError> {
+        let mut position = self.chunk_offset(chunk);
+        let mut record = DatasetRecord {
+            timestamp: self.chunk_timestamp(chunk),
+            ..DatasetRecord::default()
+        };
+
+        let first = chunk * HISTORY_CHUNK_SIZE;
+        for i in first..=index {
+            if i > first {
+                let previous = record;
+                let size = self.decode_timestamp(position, &mut record)?;
+                if record.timestamp > stop_after {
+                    return Ok((i - 1, previous));
+                }
+                position += size;
+            }
+
+            position += self.decode_color_and_user(position, &mut record)?;
+        }
+
+        Ok((index, record))
+    }
+
+    pub fn record(&self, index: usize) -> Result<DatasetRecord, DatasetFormatError> {
+        assert!(index < self.length, "index {} out of bounds for history of length {}", index, self.length);
+        Ok(self.decode_chunk(index / HISTORY_CHUNK_SIZE, index, u64::MAX)?.1)
+    }
+
+    pub fn get(&self, index: usize) -> Result<RPlaceDatasetDatapoint, DatasetFormatError> {
+        self.record(index)?.datapoint()
+    }
+
+    pub fn timestamp(&self, index: usize) -> Result<u64, DatasetFormatError> {
+        Ok(self.record(index)?.timestamp)
+    }
+
+    // Index of the last datapoint at or before `timestamp`, or None if every datapoint is after it
+    pub fn index_at(&self, timestamp: u64) -> Result<Option<usize>, DatasetFormatError> {
+        // number of chunks that start at or before the timestamp
+        let mut low = 0;
+        let mut high = self.num_chunks();
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.chunk_timestamp(mid) <= timestamp {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        if low == 0 {
+            return Ok(None);
+        }
+
+        let chunk = low - 1;
+        let last = ((chunk + 1) * HISTORY_CHUNK_SIZE).min(self.length) - 1;
+        Ok(Some(self.decode_chunk(chunk, last, timestamp)?.0))
+    }
+
+    pub fn iter(&self) -> CompressedHistoryIter<'a> {
+        CompressedHistoryIter {
+            history: *self,
+            index: 0,
+            position: 0,
+            record: DatasetRecord::default(),
+        }
+    }
+}
+
+// Decodes a history front to back. Keeps the position in the payload and the last record, so
+// every datapoint is only decoded once. Stops after the first error
+pub struct CompressedHistoryIter<'a> {
+    history: CompressedHistory<'a>,
+    index: usize,
+    position: usize,
+    record: DatasetRecord,
+}
+
+impl<'a> CompressedHistoryIter<'a> {
+    fn decode_next(&mut self) -> Result<DatasetRecord, DatasetFormatError> {
+        if self.index.is_multiple_of(HISTORY_CHUNK_SIZE) {
+            let chunk = self.index / HISTORY_CHUNK_SIZE;
+            self.position = self.history.chunk_offset(chunk);
+            self.record.timestamp = self.history.chunk_timestamp(chunk);
+        } else {
+            self.position += self.history.decode_timestamp(self.position, &mut self.record)?;
+        }
+
+        self.position += self.history.decode_color_and_user(self.position, &mut self.record)?;
+        Ok(self.record)
+    }
+}
+
+impl<'a> Iterator for CompressedHistoryIter<'a> {
+    type Item = Result<DatasetRecord, DatasetFormatError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.history.length {
+            return None;
+        }
+
+        let result = self.decode_next();
+        self.index = if result.is_ok() { self.index + 1 } else { self.history.length };
+        Some(result)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.history.length - self.index;
+        (0, Some(remaining))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(n: usize) -> Vec<DatasetRecord> {
+        (0..n).map(|i| DatasetRecord {
+            timestamp: 100 + 10 * i as u64,
+            user_id: (i * 1000) as u32,
+            color: (i % PixelColor::COUNT) as u8,
+            is_mod: (i % 7 == 0) as u8,
+            padding: [0; 2],
+        }).collect()
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        for (value, size) in [(0, 1), (127, 1), (128, 2), (u64::MAX, MAX_VARINT_SIZE)] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, value);
+            assert_eq!(bytes.len(), size);
+            assert_eq!(read_varint(&bytes).unwrap(), (value, size));
+        }
+    }
+
+    #[test]
+    fn truncated_varint_is_an_error() {
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, 128);
+        assert!(read_varint(&bytes[..1]).is_err());
+        assert!(read_varint(&[]).is_err());
+        assert!(read_varint(&[0xff; 11]).is_err());
+    }
+
+    #[test]
+    fn history_round_trip() {
+        for n in [1, 64, 65, 130] {
+            let records = records(n);
+            let block = encode_history(&records);
+            let history = CompressedHistory::new(&block).unwrap();
+            assert_eq!(history.len(), n);
+
+            for (index, record) in records.iter().enumerate() {
+                assert_eq!(history.record(index).unwrap(), *record, "history of {} records, index {}", n, index);
+            }
+
+            let decoded: Vec<DatasetRecord> = history.iter().collect::<Result<_, _>>().unwrap();
+            assert_eq!(decoded, records, "history of {} records", n);
+        }
+    }
+
+    #[test]
+    fn index_at_chunk_boundaries() {
+        for n in [1, 64, 65, 130] {
+            let records = records(n);
+            let block = encode_history(&records);
+            let history = CompressedHistory::new(&block).unwrap();
+
+            assert_eq!(history.index_at(0).unwrap(), None);
+            assert_eq!(history.index_at(records[0].timestamp - 1).unwrap(), None);
+            for index in [0, 62, 63, 64, 65, 127, 128, 129].into_iter().filter(|index| *index < n) {
+                let timestamp = records[index].timestamp;
+                assert_eq!(history.index_at(timestamp).unwrap(), Some(index), "history of {} records, at index {}", n, index);
+                assert_eq!(history.index_at(timestamp + 5).unwrap(), Some(index), "history of {} records, after index {}", n, index);
+            }
+            assert_eq!(history.index_at(u64::MAX).unwrap(), Some(n - 1));
+        }
+    }
+
+    #[test]
+    fn index_at_equal_timestamps_across_chunks() {
+        let mut records = records(130);
+        for record in &mut records[60..70] {
+            record.timestamp = 1000;
+        }
+        for record in &mut records[70..] {
+            record.timestamp += 1000;
+        }
+
+        let block = encode_history(&records);
+        let history = CompressedHistory::new(&block).unwrap();
+        assert_eq!(history.index_at(999).unwrap(), Some(59));
+        assert_eq!(history.index_at(1000).unwrap(), Some(69));
+    }
+
+    #[test]
+    fn truncated_block_is_an_error() {
+        let block = encode_history(&records(65));
+        assert!(CompressedHistory::new(&block[..2]).is_err());
+        assert!(CompressedHistory::new(&block[..10]).is_err());
+
+        let history = CompressedHistory::new(&block[..block.len() - 1]).unwrap();
+        assert!(history.record(64).is_err());
+        assert!(history.iter().any(|record| record.is_err()));
+    }
+}
@@ -1,18 +1,33 @@
 use csv::DeserializeRecordsIntoIter;
+use flate2::read::MultiGzDecoder;
 use serde::{Deserialize, Deserializer};
-use std::collections::hash_map::DefaultHasher;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::cell::RefCell;
 use std::fmt;
-use std::fs::File;
-use std::hash::{Hash, Hasher};
+use std::fs::{self, File};
+use std::io::{BufReader, Error as IoError, ErrorKind, Read};
+use std::path::Path;
+use std::rc::Rc;
 use speedy2d::dimen::{Vector2, Vec2};
 use time::PrimitiveDateTime;
-use crate::rplace::data::RPlaceDatapoint;
+use crate::rplace::data::{RPlaceDatapoint, RPlaceRectangleIterator};
+use crate::rplace::error::RPlaceError;
+use crate::rplace::users::UserIdDictionary;
 use super::super::pixel::PixelColor;
 use serde::de::Error;
 
+/// Reads a single file of Reddit's csv dump. Moderator rectangles are expanded into one
+/// datapoint per pixel.
 pub struct RPlaceCSVDataIterator {
     file_path: String,
-    iter: DeserializeRecordsIntoIter<File, RPlaceCSVDatapoint>,
+    iter: DeserializeRecordsIntoIter<Box<dyn Read>, RPlaceCSVDatapoint>,
+
+    // remaining pixels of the moderator rectangle that is being expanded
+    rectangle: Option<RPlaceRectangleIterator>,
+
+    // shared between shards (and between the passes of the writer) so every user gets one id
+    user_ids: Rc<RefCell<UserIdDictionary>>,
 }
 
 impl fmt::Debug for RPlaceCSVDataIterator {
@@ -24,21 +39,31 @@ impl fmt::Debug for RPlaceCSVDataIterator {
 }
 
 impl RPlaceCSVDataIterator {
-    pub fn new(file_path: &str) -> Option<RPlaceCSVDataIterator> {
-        match File::open(file_path) {
-            Ok(file) => {
-                let reader = csv::Reader::from_reader(file);
-                let iter = reader.into_deserialize();
-                Some(RPlaceCSVDataIterator {
-                    file_path: file_path.to_string(),
-                    iter,
-                })
-            },
-            _ => None
-        }
+    /// Gzipped files (the official dump uses the .csv.gzip extension) are decompressed while reading.
+    pub fn new(file_path: &str) -> Result<RPlaceCSVDataIterator, RPlaceError> {
+        RPlaceCSVDataIterator::new_with_user_ids(file_path, Rc::new(RefCell::new(UserIdDictionary::new())))
+    }
+
+    /// Same as `new`, with user ids shared with other readers so they stay unique across files.
+    pub fn new_with_user_ids(file_path: &str, user_ids: Rc<RefCell<UserIdDictionary>>) -> Result<RPlaceCSVDataIterator, RPlaceError> {
+        let file = File::open(file_path)?;
+        let input: Box<dyn Read> = if is_gzip_file_path(file_path) {
+            Box::new(MultiGzDecoder::new(BufReader::new(file)))
+        } else {
+            Box::new(file)
+        };
+
+        let reader = csv::Reader::from_reader(input);
+        let iter = reader.into_deserialize();
+        Ok(RPlaceCSVDataIterator {
+            file_path: file_path.to_string(),
+            iter,
+            rectangle: None,
+            user_ids,
+        })
     }
 
-    fn print(n: u32, file_path: &str) -> Result<(), std::io::Error> {
+    fn print(n: u32, file_path: &str) -> Result<(), RPlaceError> {
         let f = File::open(file_path)?;
         let mut rdr = csv::Reader::from_reader(f);
         for result in rdr.deserialize().take(n as usize) {
@@ -51,20 +76,163 @@ impl RPlaceCSVDataIterator {
 }
 
 
-// does not iterate over bad values
-impl Iterator for RPlaceCSVDataIterator {
-    type Item = RPlaceDatapoint;
+fn is_gzip_file_path(file_path: &str) -> bool {
+    file_path.ends_with(".gz") || file_path.ends_with(".gzip")
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some(value) = self.iter.next() {
-            match value {
+fn is_csv_file_path(file_path: &str) -> bool {
+    file_path.ends_with(".csv") || file_path.ends_with(".csv.gz") || file_path.ends_with(".csv.gzip")
+}
+
+/// Reads several csv shards (such as the 79 files of Reddit's official dump) as one stream.
+/// Every shard is read in order and the shards are merged by timestamp, so the output is in
+/// timestamp order as long as each shard is.
+pub struct RPlaceCSVShardIterator {
+    file_paths: Vec<String>,
+    shards: Vec<RPlaceCSVDataIterator>,
+
+    // next timestamp of each shard that has datapoints left, with the datapoint itself in `pending`
+    heap: BinaryHeap<Reverse<(u64, usize)>>,
+    pending: Vec<Option<RPlaceDatapoint>>,
+
+    // errors hit while reading ahead, returned before the next datapoint
+    errors: VecDeque<RPlaceError>,
+}
+
+impl fmt::Debug for RPlaceCSVShardIterator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RPlaceCSVShardIterator")
+         .field("file_paths", &self.file_paths)
+         .finish()
+    }
+}
+
+impl RPlaceCSVShardIterator {
+    /// Reads the shards at `file_paths`.
+    pub fn new(file_paths: &[String]) -> Result<RPlaceCSVShardIterator, RPlaceError> {
+        RPlaceCSVShardIterator::new_with_user_ids(file_paths, Rc::new(RefCell::new(UserIdDictionary::new())))
+    }
+
+    /// Same as `new`, with user ids shared with other readers.
+    pub fn new_with_user_ids(file_paths: &[String], user_ids: Rc<RefCell<UserIdDictionary>>) -> Result<RPlaceCSVShardIterator, RPlaceError> {
+        let mut shards = Vec::new();
+        for file_path in file_paths.iter() {
+            shards.push(RPlaceCSVDataIterator::new_with_user_ids(file_path, Rc::clone(&user_ids))?);
+        }
+
+        let mut iter = RPlaceCSVShardIterator {
+            file_paths: file_paths.to_vec(),
+            pending: vec![None; shards.len()],
+            shards,
+            heap: BinaryHeap::new(),
+            errors: VecDeque::new(),
+        };
+
+        for idx in 0..iter.shards.len() {
+            iter.advance(idx);
+        }
+
+        Ok(iter)
+    }
+
+    /// Accepts a single csv file or a directory of csv shards.
+    pub fn from_path(path: &str) -> Result<RPlaceCSVShardIterator, RPlaceError> {
+        RPlaceCSVShardIterator::from_path_with_user_ids(path, Rc::new(RefCell::new(UserIdDictionary::new())))
+    }
+
+    /// Same as `from_path`, with user ids shared with other readers.
+    pub fn from_path_with_user_ids(path: &str, user_ids: Rc<RefCell<UserIdDictionary>>) -> Result<RPlaceCSVShardIterator, RPlaceError> {
+        let file_paths = RPlaceCSVShardIterator::shard_file_paths(path)?;
+        if file_paths.is_empty() {
+            return Err(IoError::new(ErrorKind::NotFound, format!("no csv files found in {}", path)).into());
+        }
+
+        println!("Reading {} csv shards from {}", file_paths.len(), path);
+        RPlaceCSVShardIterator::new_with_user_ids(&file_paths, user_ids)
+    }
+
+    /// The csv files in the directory at `path` in name order, or `path` itself if it is a file.
+    pub fn shard_file_paths(path: &str) -> Result<Vec<String>, RPlaceError> {
+        if !Path::new(path).is_dir() {
+            return Ok(vec![path.to_string()]);
+        }
+
+        let mut file_paths: Vec<String> = fs::read_dir(path)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path().to_string_lossy().to_string())
+            .filter(|file_path| is_csv_file_path(file_path))
+            .collect();
+        file_paths.sort();
+        Ok(file_paths)
+    }
+
+    // reads the next datapoint of a shard into `pending`. errors are queued, and a shard that 
+    // hits an error other than a bad record is not read any further
+    fn advance(&mut self, idx: usize) {
+        self.pending[idx] = None;
+        for result in self.shards[idx].by_ref() {
+            match result {
                 Ok(datapoint) => {
-                    return Some(RPlaceDatapoint::from(datapoint));
+                    self.heap.push(Reverse((datapoint.timestamp, idx)));
+                    self.pending[idx] = Some(datapoint);
+                    return;
+                },
+                Err(e) => {
+                    let is_bad_record = e.is_bad_record();
+                    self.errors.push_back(e);
+                    if !is_bad_record {
+                        return;
+                    }
                 },
-                Err(error) => println!("Error: failed to parse datapoint. Skipping to next. Error: {:?}", error),
             }
         }
-        return None;
+    }
+}
+
+impl Iterator for RPlaceCSVShardIterator {
+    type Item = Result<RPlaceDatapoint, RPlaceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.errors.pop_front() {
+            return Some(Err(e));
+        }
+
+        let Reverse((_, idx)) = self.heap.pop()?;
+        let datapoint = self.pending[idx].take();
+        self.advance(idx);
+
+        datapoint.map(Ok)
+    }
+}
+
+// Bad records are returned as errors and iteration continues after them
+impl Iterator for RPlaceCSVDataIterator {
+    type Item = Result<RPlaceDatapoint, RPlaceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(rectangle) = self.rectangle.as_mut() {
+            if let Some(datapoint) = rectangle.next() {
+                return Some(Ok(datapoint));
+            }
+            self.rectangle = None;
+        }
+
+        let csv_datapoint = match self.iter.next()? {
+            Ok(csv_datapoint) => csv_datapoint,
+            Err(e) => return Some(Err(e.into())),
+        };
+
+        let datapoint = match csv_datapoint.to_datapoint(&mut self.user_ids.borrow_mut()) {
+            Ok(datapoint) => datapoint,
+            Err(e) => return Some(Err(e)),
+        };
+
+        if let RPlaceCSVCoordinate::Rectangle(top_left, bottom_right) = csv_datapoint.coordinate {
+            self.rectangle = Some(RPlaceRectangleIterator::new(datapoint, top_left, bottom_right));
+            return self.next();
+        }
+
+        Some(Ok(datapoint))
     }
 }
 
@@ -73,29 +241,47 @@ pub struct RPlaceCSVDatapoint {
     #[serde(deserialize_with = "primitive_date_time_from_str")]
     pub timestamp: PrimitiveDateTime,
     pub user_id: String, 
-    #[serde(deserialize_with = "pixel_color_from_str")]
-    pub pixel_color: PixelColor,
-    #[serde(deserialize_with = "vector2_from_str")]
-    pub coordinate: Vector2<u32>,
-}
-
-impl From<&RPlaceCSVDatapoint> for RPlaceDatapoint {
-    fn from(item: &RPlaceCSVDatapoint) -> Self {
-        let mut hasher = DefaultHasher::new();
-        item.user_id.hash(&mut hasher);
-        return RPlaceDatapoint { 
-            timestamp: item.timestamp.assume_utc().unix_timestamp() as u64, 
-            user_id: hasher.finish() as u32, 
-            color: item.pixel_color, 
-            coordinate: Vec2::new(item.coordinate.x as f32, item.coordinate.y as f32), 
-            is_mod: false, 
-        };
+
+    // kept as the hex string so unknown colors are reported as RPlaceError::UnknownColor
+    pub pixel_color: String,
+    #[serde(deserialize_with = "coordinate_from_str")]
+    pub coordinate: RPlaceCSVCoordinate,
+}
+
+// The official csv stores a single pixel as "x,y" and a moderator rectangle wipe as "x1,y1,x2,y2"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RPlaceCSVCoordinate {
+    Pixel(Vector2<i32>),
+
+    // top left and bottom right corners, both inclusive
+    Rectangle(Vector2<i32>, Vector2<i32>),
+}
+
+impl RPlaceCSVCoordinate {
+    pub fn top_left(&self) -> Vector2<i32> {
+        match self {
+            RPlaceCSVCoordinate::Pixel(coordinate) => *coordinate,
+            RPlaceCSVCoordinate::Rectangle(top_left, _) => *top_left,
+        }
+    }
+
+    pub fn is_rectangle(&self) -> bool {
+        matches!(self, RPlaceCSVCoordinate::Rectangle(_, _))
     }
 }
 
-impl From<RPlaceCSVDatapoint> for RPlaceDatapoint {
-    fn from(item: RPlaceCSVDatapoint) -> Self {
-        (&item).into()
+impl RPlaceCSVDatapoint {
+    // Note: This will always create a pixel at the top left coordinate. Rectangles are expanded by RPlaceCSVDataIterator
+    pub fn to_datapoint(&self, user_ids: &mut UserIdDictionary) -> Result<RPlaceDatapoint, RPlaceError> {
+        let color = PixelColor::try_from(&self.pixel_color)?;
+        let coordinate = self.coordinate.top_left();
+        Ok(RPlaceDatapoint { 
+            timestamp: self.timestamp.assume_utc().unix_timestamp_nanos() as u64, 
+            user_id: user_ids.get_or_insert(&self.user_id), 
+            color, 
+            coordinate: Vec2::new(coordinate.x as f32, coordinate.y as f32), 
+            is_mod: self.coordinate.is_rectangle(), 
+        })
     }
 }
 
@@ -104,6 +290,7 @@ fn primitive_date_time_from_str<'de, D: Deserializer<'de>>(d: D) -> Result<Primi
     match s {
         Some(datetime) => {
             // 2022-04-04 00:53:51.577 UTC
+            // the subsecond part is kept, it orders pixels placed within the same second
             let format1 = time::macros::format_description!(
                 "[year]-[month]-[day] [hour]:[minute]:[second].[subsecond] UTC"
             );
@@ -129,41 +316,164 @@ fn primitive_date_time_from_str<'de, D: Deserializer<'de>>(d: D) -> Result<Primi
     }
 }
 
-fn pixel_color_from_str<'de, D: Deserializer<'de>>(d: D) -> Result<PixelColor, D::Error> {
+fn coordinate_from_str<'de, D: Deserializer<'de>>(d: D) -> Result<RPlaceCSVCoordinate, D::Error> {
     let s: Option<String> = Deserialize::deserialize(d)?;
+    
     match s {
-        Some(hex_string) => {
-            match PixelColor::try_from(&hex_string) {
-                Ok(value) => Ok(value),
-                Err(_) => {
-                    Err(D::Error::custom(format!("Failed to match hex with pixel color: {hex_string}")))
+        Some(some) => {
+            let values: Result<Vec<i32>, _> = some.split(',').map(|v| v.trim().parse::<i32>()).collect();
+            match values.as_deref() {
+                Ok([x, y]) => return Ok(RPlaceCSVCoordinate::Pixel(Vector2::new(*x, *y))),
+                Ok([x1, y1, x2, y2]) => {
+                    let top_left = Vector2::new(*x1.min(x2), *y1.min(y2));
+                    let bottom_right = Vector2::new(*x1.max(x2), *y1.max(y2));
+                    return Ok(RPlaceCSVCoordinate::Rectangle(top_left, bottom_right));
                 },
+                _ => (),
             }
+            Err(D::Error::custom(format!("Failed to parse coordinate {some}")))
         },
         None => {
-            Err(D::Error::custom("Failed to deserialize hex color string"))
-        },
+            Err(D::Error::custom("Failed to deserialize coordinate string"))
+        }
     }
 }
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::PathBuf;
 
-fn vector2_from_str<'de, D: Deserializer<'de>>(d: D) -> Result<Vector2<u32>, D::Error> {
-    let s: Option<String> = Deserialize::deserialize(d)?;
-    
-    match s {
-        Some(some) => {
-            let v: Vec<&str> = some.split(',').collect();
-            if v.len() == 2 {
-                let v0_option = v[0].parse::<f32>();
-                let v1_option = v[1].parse::<f32>();
-        
-                if let (Ok(v0), Ok(v1)) = (v0_option, v1_option) {
-                    return Ok(Vector2::new(v0, v1).into_u32());
-                }
-            }
-            Err(D::Error::custom(format!("Failed to parse vector {some}")))
-        },
-        None => {
-            Err(D::Error::custom("Failed to deserialize vector2 string"))
-        }
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+
+    const CSV_HEADER: &str = "timestamp,user_id,pixel_color,coordinate";
+
+    // unix timestamp of 2022-04-01 12:00:00 UTC
+    const START_SECONDS: u64 = 1_648_814_400;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rplace-csv-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // gzipped shards get one gzip member per line, the way concatenated dumps look
+    fn write_shard(dir: &Path, name: &str, rows: &[&str]) -> String {
+        let lines: Vec<String> = std::iter::once(CSV_HEADER).chain(rows.iter().copied()).map(|line| format!("{}\n", line)).collect();
+        let bytes = if is_gzip_file_path(name) {
+            lines.iter().flat_map(|line| {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(line.as_bytes()).unwrap();
+                encoder.finish().unwrap()
+            }).collect()
+        } else {
+            lines.concat().into_bytes()
+        };
+
+        let file_path = dir.join(name);
+        fs::write(&file_path, bytes).unwrap();
+        file_path.to_string_lossy().to_string()
+    }
+
+    // seconds after START_SECONDS and coordinate of every datapoint
+    fn seconds_and_coordinates<I: Iterator<Item = Result<RPlaceDatapoint, RPlaceError>>>(iter: I) -> Vec<(u64, i32, i32)> {
+        iter.map(|datapoint| {
+            let datapoint = datapoint.unwrap();
+            (datapoint.timestamp / 1_000_000_000 - START_SECONDS, datapoint.coordinate.x as i32, datapoint.coordinate.y as i32)
+        }).collect()
+    }
+
+    #[test]
+    fn gzip_shard() {
+        let dir = temp_dir("gzip");
+        let file_path = write_shard(&dir, "a.csv.gzip", &[
+            "2022-04-01 12:00:01 UTC,u1,#FF4500,\"0,0\"",
+            "2022-04-01 12:00:02 UTC,u2,#FFFFFF,\"1,0\"",
+        ]);
+        let datapoints = seconds_and_coordinates(RPlaceCSVDataIterator::new(&file_path).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(datapoints, vec![(1, 0, 0), (2, 1, 0)]);
+    }
+
+    #[test]
+    fn shard_merge_order() {
+        let dir = temp_dir("shards");
+        write_shard(&dir, "a.csv.gz", &[
+            "2022-04-01 12:00:01 UTC,u1,#FF4500,\"0,0\"",
+            "2022-04-01 12:00:04 UTC,u2,#FF4500,\"1,0\"",
+            "2022-04-01 12:00:05 UTC,u1,#FF4500,\"2,0\"",
+        ]);
+        write_shard(&dir, "b.csv", &[
+            "2022-04-01 12:00:02 UTC,u3,#FFFFFF,\"0,1\"",
+            "2022-04-01 12:00:03 UTC,u2,#FFFFFF,\"1,1\"",
+            "2022-04-01 12:00:06 UTC,u3,#FFFFFF,\"2,1\"",
+        ]);
+        write_shard(&dir, "c.csv.gzip", &[
+            "2022-04-01 12:00:00 UTC,u4,#000000,\"3,3\"",
+        ]);
+        fs::write(dir.join("notes.txt"), "not a shard").unwrap();
+
+        let dir_path = dir.to_string_lossy().to_string();
+        let file_names: Vec<String> = RPlaceCSVShardIterator::shard_file_paths(&dir_path).unwrap().iter()
+            .map(|file_path| Path::new(file_path).file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        let single_file_path = dir.join("b.csv").to_string_lossy().to_string();
+        let single_file_paths = RPlaceCSVShardIterator::shard_file_paths(&single_file_path).unwrap();
+        let datapoints: Vec<RPlaceDatapoint> = RPlaceCSVShardIterator::from_path(&dir_path).unwrap().map(Result::unwrap).collect();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(file_names, vec!["a.csv.gz", "b.csv", "c.csv.gzip"]);
+        assert_eq!(single_file_paths, vec![single_file_path]);
+        assert_eq!(seconds_and_coordinates(datapoints.iter().copied().map(Ok)), vec![
+            (0, 3, 3), (1, 0, 0), (2, 0, 1), (3, 1, 1), (4, 1, 0), (5, 2, 0), (6, 2, 1),
+        ]);
+
+        // user ids are shared between shards
+        let user_ids: Vec<u32> = datapoints.iter().map(|datapoint| datapoint.user_id).collect();
+        assert_eq!(user_ids[1], user_ids[5]);
+        assert_eq!(user_ids[2], user_ids[6]);
+        assert_eq!(user_ids[3], user_ids[4]);
+        assert_ne!(user_ids[1], user_ids[2]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn rectangle_expansion() {
+        let dir = temp_dir("rectangle");
+        let file_path = write_shard(&dir, "a.csv", &[
+            "2022-04-01 12:00:01 UTC,u1,#FF4500,\"0,0\"",
+            "2022-04-01 12:00:02 UTC,mod,#FFFFFF,\"3,2,2,1\"",
+            "2022-04-01 12:00:03 UTC,u1,#FF4500,\"0,0\"",
+        ]);
+        let datapoints: Vec<RPlaceDatapoint> = RPlaceCSVDataIterator::new(&file_path).unwrap().map(Result::unwrap).collect();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // the corners are sorted and both are inclusive
+        assert_eq!(seconds_and_coordinates(datapoints.iter().copied().map(Ok)), vec![
+            (1, 0, 0), (2, 2, 1), (2, 3, 1), (2, 2, 2), (2, 3, 2), (3, 0, 0),
+        ]);
+        assert_eq!(datapoints.iter().map(|datapoint| datapoint.is_mod).collect::<Vec<bool>>(), vec![false, true, true, true, true, false]);
+        assert!(datapoints[1..5].iter().all(|datapoint| datapoint.color == PixelColor::White && datapoint.user_id == datapoints[1].user_id));
+    }
+
+    #[test]
+    fn nanosecond_timestamps() {
+        let dir = temp_dir("nanoseconds");
+        let file_path = write_shard(&dir, "a.csv", &[
+            "2022-04-01 12:00:01 UTC,u1,#FF4500,\"0,0\"",
+            "2022-04-01 12:00:01.000000001 UTC,u1,#FF4500,\"0,0\"",
+            "2022-04-01 12:00:01.25 UTC,u1,#FF4500,\"0,0\"",
+            "2022-04-01 12:00:01.577 UTC,u1,#FF4500,\"0,0\"",
+            "2022-04-01 12:00:02 UTC,u1,#FF4500,\"0,0\"",
+            "2022-04-01 12:00 UTC,u1,#FF4500,\"0,0\"",
+        ]);
+        let datapoints: Vec<Result<RPlaceDatapoint, RPlaceError>> = RPlaceCSVDataIterator::new(&file_path).unwrap().collect();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let start = (START_SECONDS + 1) * 1_000_000_000;
+        let timestamps: Vec<u64> = datapoints[..5].iter().map(|datapoint| datapoint.as_ref().unwrap().timestamp).collect();
+        assert_eq!(timestamps, vec![start, start + 1, start + 250_000_000, start + 577_000_000, start + 1_000_000_000]);
+        assert!(matches!(datapoints[5], Err(RPlaceError::Csv(_))));
+    }
+}
@@ -1,16 +1,517 @@
-use std::{time::Instant, fs::File, io::Write, mem::size_of};
+use std::{time::Instant, fs::File, io::Write, mem::{align_of, size_of}, fmt, ops::Range, str::FromStr};
 
 use memmap::Mmap;
-use min_max::{min, max};
 use serde::{Serialize, Deserialize};
 use strum::IntoEnumIterator;
 
-use crate::rplace::{dataset::{RPlaceDatasetDatapoint, RPlaceDataset}, data::RPlaceDataReader, pixel::PixelColor, canvas::CanvasPixel, search::least_greater};
+use super::compressed::CompressedHistory;
+use crate::rplace::{error::{BadRecordCounts, BadRecordPolicy, RPlaceError}, dataset::{DatasetRecord, EditLogEntry, RPlaceDatasetDatapoint}, data::CanvasDimensions, pixel::PixelColor, search::least_greater};
 
 //const SERIALIZED_DATAPOINT_SIZE: u8 = 14;
 //assert_eq!(SERIALIZED_DATAPOINT_SIZE, RPlaceDatasetDatapoint::start().to_bytes().len() as u8);
 
-#[derive(Serialize, Deserialize, Debug)]
+// first bytes of every custom dataset file. files without them are treated as the legacy
+// headerless format (bincode serialized PrecompressedDatasetMetadata followed by the data vec)
+pub const DATASET_MAGIC: [u8; 8] = *b"RPLACEDS";
+pub const DATASET_FORMAT_VERSION: u32 = 5;
+
+// datapoints are stored as DatasetRecords starting with version 3. older versions store 
+// bincode serialized RPlaceDatasetDatapoints
+pub const DATASET_RECORD_VERSION: u32 = 3;
+
+// the edit log section was added in version 5
+pub const DATASET_EDIT_LOG_VERSION: u32 = 5;
+
+// the data section starts at a multiple of this so records can be read in place
+pub const DATASET_DATA_ALIGNMENT: u64 = DatasetRecord::SIZE as u64;
+
+// bytes per datapoint in the columns layout: timestamp u64, user u32, color u8, is_mod u8
+pub const COLUMNS_DATAPOINT_SIZE: u32 = 14;
+
+/// How the datapoints are arranged in the data section. Added in version 4, older files use Records.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DatasetLayout {
+    // one record per datapoint, histories stored back to back
+    #[default]
+    Records,
+
+    // four arrays (timestamps, user ids, colors, mod flags) indexed by datapoint. binary searches 
+    // over a history only touch its timestamps
+    Columns,
+
+    // every pixel history is a variable length block of delta encoded timestamps, packed colors 
+    // and varint user ids (see reader::compressed). a table of block offsets comes first
+    Compressed,
+}
+
+impl FromStr for DatasetLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "records" => Ok(DatasetLayout::Records),
+            "columns" => Ok(DatasetLayout::Columns),
+            "compressed" => Ok(DatasetLayout::Compressed),
+            _ => Err(format!("unknown dataset layout '{}', expected records, columns or compressed", s)),
+        }
+    }
+}
+
+// opening a dataset only checks the header and metadata. color bytes and history blocks are
+// checked when they are read, or all at once by SerializedDataset::verify
+const CORRUPT_DATA: &str = "dataset contains corrupt datapoints, run `rplace verify` on it for details";
+
+// version reported for files written before the header existed
+pub const LEGACY_FORMAT_VERSION: u32 = 0;
+
+/// Errors of reading or writing a dataset in the custom format.
+#[derive(Debug)]
+pub enum DatasetFormatError {
+    Io(std::io::Error),
+    Decode(bincode::Error),
+    UnsupportedVersion(u32),
+    InvalidHeader(String),
+    Truncated { section: &'static str, expected: u64, actual: u64 },
+
+    // the datapoint source returned different data between writer passes
+    InconsistentSource(String),
+
+    // a datapoint or history block that can not be decoded
+    CorruptData(String),
+
+    // datapoints of the layout are not stored at fixed positions
+    UnsupportedLayout(DatasetLayout),
+}
+
+impl fmt::Display for DatasetFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatasetFormatError::Io(e) => write!(f, "failed to read dataset: {}", e),
+            DatasetFormatError::Decode(e) => write!(f, "failed to decode dataset: {}", e),
+            DatasetFormatError::UnsupportedVersion(version) => write!(f, "unsupported dataset format version {} (expected <= {})", version, DATASET_FORMAT_VERSION),
+            DatasetFormatError::InvalidHeader(reason) => write!(f, "invalid dataset header: {}", reason),
+            DatasetFormatError::Truncated { section, expected, actual } => write!(f, "dataset is truncated in {} section: expected {} bytes but file has {}", section, expected, actual),
+            DatasetFormatError::InconsistentSource(reason) => write!(f, "datapoint source changed between passes: {}", reason),
+            DatasetFormatError::CorruptData(reason) => write!(f, "dataset is corrupt: {}", reason),
+            DatasetFormatError::UnsupportedLayout(layout) => write!(f, "datapoints of the {:?} layout are not stored at fixed positions", layout),
+        }
+    }
+}
+
+impl std::error::Error for DatasetFormatError {}
+
+impl From<std::io::Error> for DatasetFormatError {
+    fn from(item: std::io::Error) -> Self {
+        DatasetFormatError::Io(item)
+    }
+}
+
+impl From<bincode::Error> for DatasetFormatError {
+    fn from(item: bincode::Error) -> Self {
+        DatasetFormatError::Decode(item)
+    }
+}
+
+/// Fixed size header at the start of the file. Offsets are absolute byte positions in the file.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct DatasetHeader {
+    pub magic: [u8; 8],
+    pub version: u32,
+    pub canvas_width: u32,
+    pub canvas_height: u32,
+
+    // r/place coordinate of canvas pixel (0, 0). added in version 2
+    pub origin_x: i32,
+    pub origin_y: i32,
+
+    pub datapoint_size: u32,
+    pub num_datapoints: u64,
+    pub metadata_offset: u64,
+    pub metadata_length: u64,
+    pub data_offset: u64,
+
+    // added in version 4
+    pub layout: DatasetLayout,
+
+    // all edits in timestamp order, see EditLogEntry. added in version 5, files without an edit 
+    // log have a length of 0
+    pub edit_log_offset: u64,
+    pub edit_log_length: u64,
+}
+
+// Version 4 header, which did not have the edit log. bincode writes nested structs field by field
+// so this has the same encoding as the flat struct
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct DatasetHeaderV4 {
+    header: DatasetHeaderV2,
+    layout: DatasetLayout,
+}
+
+// Version 2 and 3 header, which did not store the layout
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct DatasetHeaderV2 {
+    magic: [u8; 8],
+    version: u32,
+    canvas_width: u32,
+    canvas_height: u32,
+    origin_x: i32,
+    origin_y: i32,
+    datapoint_size: u32,
+    num_datapoints: u64,
+    metadata_offset: u64,
+    metadata_length: u64,
+    data_offset: u64,
+}
+
+// Version 1 header, which did not store the canvas origin
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct DatasetHeaderV1 {
+    magic: [u8; 8],
+    version: u32,
+    canvas_width: u32,
+    canvas_height: u32,
+    datapoint_size: u32,
+    num_datapoints: u64,
+    metadata_offset: u64,
+    metadata_length: u64,
+    data_offset: u64,
+}
+
+impl From<DatasetHeaderV1> for DatasetHeader {
+    fn from(item: DatasetHeaderV1) -> Self {
+        DatasetHeader { 
+            magic: item.magic, 
+            version: item.version, 
+            canvas_width: item.canvas_width, 
+            canvas_height: item.canvas_height, 
+            origin_x: 0, 
+            origin_y: 0, 
+            datapoint_size: item.datapoint_size, 
+            num_datapoints: item.num_datapoints, 
+            metadata_offset: item.metadata_offset, 
+            metadata_length: item.metadata_length, 
+            data_offset: item.data_offset,
+            layout: DatasetLayout::Records,
+            edit_log_offset: 0,
+            edit_log_length: 0,
+        }
+    }
+}
+
+impl From<DatasetHeaderV2> for DatasetHeader {
+    fn from(item: DatasetHeaderV2) -> Self {
+        DatasetHeader { 
+            magic: item.magic, 
+            version: item.version, 
+            canvas_width: item.canvas_width, 
+            canvas_height: item.canvas_height, 
+            origin_x: item.origin_x, 
+            origin_y: item.origin_y, 
+            datapoint_size: item.datapoint_size, 
+            num_datapoints: item.num_datapoints, 
+            metadata_offset: item.metadata_offset, 
+            metadata_length: item.metadata_length, 
+            data_offset: item.data_offset,
+            layout: DatasetLayout::Records,
+            edit_log_offset: 0,
+            edit_log_length: 0,
+        }
+    }
+}
+
+impl From<DatasetHeaderV4> for DatasetHeader {
+    fn from(item: DatasetHeaderV4) -> Self {
+        DatasetHeader {
+            layout: item.layout,
+            ..item.header.into()
+        }
+    }
+}
+
+impl DatasetHeader {
+    /// Header of a dataset in the current format version with the records layout.
+    pub fn new(dimensions: CanvasDimensions, num_datapoints: u64, metadata_length: u64) -> DatasetHeader {
+        DatasetHeader::new_with_layout(dimensions, num_datapoints, metadata_length, DatasetLayout::Records)
+    }
+
+    /// Header of a dataset in the current format version. The sections are laid out back to back
+    /// after the header, the edit log offset of the compressed layout is set once its data is written.
+    pub fn new_with_layout(dimensions: CanvasDimensions, num_datapoints: u64, metadata_length: u64, layout: DatasetLayout) -> DatasetHeader {
+        let metadata_offset = DatasetHeader::compressed_size();
+        let mut header = DatasetHeader {
+            magic: DATASET_MAGIC,
+            version: DATASET_FORMAT_VERSION,
+            canvas_width: dimensions.width,
+            canvas_height: dimensions.height,
+            origin_x: dimensions.origin_x,
+            origin_y: dimensions.origin_y,
+            datapoint_size: DatasetHeader::expected_datapoint_size(DATASET_FORMAT_VERSION, layout),
+            num_datapoints,
+            metadata_offset,
+            metadata_length,
+            data_offset: (metadata_offset + metadata_length).next_multiple_of(DATASET_DATA_ALIGNMENT),
+            layout,
+            edit_log_offset: 0,
+            edit_log_length: num_datapoints - dimensions.num_pixels() as u64,
+        };
+
+        // the length of the compressed data is only known once it is written
+        if layout != DatasetLayout::Compressed {
+            header.edit_log_offset = header.edit_log_position(header.data_length());
+        }
+        header
+    }
+
+    /// The edit log starts at the first aligned position after the data section.
+    pub fn edit_log_position(&self, data_length: u64) -> u64 {
+        (self.data_offset + data_length).next_multiple_of(DATASET_DATA_ALIGNMENT)
+    }
+
+    /// True if the file has an edit log section, which was added in version 5.
+    pub fn has_edit_log(&self) -> bool {
+        self.version >= DATASET_EDIT_LOG_VERSION && self.edit_log_length > 0
+    }
+
+    /// Total length of the file.
+    pub fn file_length(&self) -> u64 {
+        if self.has_edit_log() {
+            return self.edit_log_offset + self.edit_log_length * EditLogEntry::SIZE as u64;
+        }
+
+        self.data_offset + self.data_length()
+    }
+
+    /// Size in bytes of a datapoint for the version and layout, 0 if datapoints have a variable size.
+    pub fn expected_datapoint_size(version: u32, layout: DatasetLayout) -> u32 {
+        match layout {
+            DatasetLayout::Columns => COLUMNS_DATAPOINT_SIZE,
+
+            // datapoints have a variable size
+            DatasetLayout::Compressed => 0,
+            DatasetLayout::Records if version >= DATASET_RECORD_VERSION => DatasetRecord::SIZE as u32,
+            DatasetLayout::Records => RPlaceDatasetDatapoint::compressed_size() as u32,
+        }
+    }
+
+    /// True if the data section holds DatasetRecords.
+    pub fn has_records(&self) -> bool {
+        self.version >= DATASET_RECORD_VERSION && self.layout == DatasetLayout::Records
+    }
+
+    /// Distance in bytes between the timestamps of two consecutive datapoints. None for the
+    /// compressed layout, where timestamps are not at fixed positions.
+    pub fn timestamp_stride(&self) -> Option<u64> {
+        match self.layout {
+            DatasetLayout::Records => Some(self.datapoint_size as u64),
+            DatasetLayout::Columns => Some(size_of::<u64>() as u64),
+            DatasetLayout::Compressed => None,
+        }
+    }
+
+    /// Byte position in the file of the timestamp of datapoint `index`. None for the compressed layout.
+    pub fn timestamp_position(&self, index: u64) -> Option<usize> {
+        self.timestamp_stride().map(|stride| (self.data_offset + index * stride) as usize)
+    }
+
+    // start of the timestamp, user id, color and mod flag arrays in the columns layout
+    fn column_offsets(&self) -> [u64; 4] {
+        let timestamps = self.data_offset;
+        let user_ids = timestamps + self.num_datapoints * size_of::<u64>() as u64;
+        let colors = user_ids + self.num_datapoints * size_of::<u32>() as u64;
+        let mod_flags = colors + self.num_datapoints;
+        [timestamps, user_ids, colors, mod_flags]
+    }
+
+    /// Reads datapoint `index` from the whole file. Only supports the records and columns layouts. Records written before version 3 are bincode
+    /// serialized RPlaceDatasetDatapoints, which share the field offsets of DatasetRecord.
+    pub fn read_record(&self, file: &[u8], index: u64) -> Result<DatasetRecord, DatasetFormatError> {
+        match self.layout {
+            DatasetLayout::Records => {
+                let start = self.data_offset + index * self.datapoint_size as u64;
+                let bytes = SerializedDataset::section(file, "data", start, start + self.datapoint_size as u64)?;
+                Ok(DatasetRecord::from_bytes(bytes))
+            },
+            DatasetLayout::Columns => {
+                let [timestamps, user_ids, colors, mod_flags] = self.column_offsets();
+                let timestamp = SerializedDataset::section(file, "data", timestamps + index * 8, timestamps + index * 8 + 8)?;
+                let user_id = SerializedDataset::section(file, "data", user_ids + index * 4, user_ids + index * 4 + 4)?;
+                Ok(DatasetRecord {
+                    timestamp: u64::from_le_bytes(timestamp.try_into().unwrap()),
+                    user_id: u32::from_le_bytes(user_id.try_into().unwrap()),
+                    color: SerializedDataset::section(file, "data", colors + index, colors + index + 1)?[0],
+                    is_mod: SerializedDataset::section(file, "data", mod_flags + index, mod_flags + index + 1)?[0],
+                    padding: [0; 2],
+                })
+            },
+            DatasetLayout::Compressed => Err(DatasetFormatError::UnsupportedLayout(self.layout)),
+        }
+    }
+
+    /// Writes datapoint `index` into the whole file. Only supports version 3 and later with the
+    /// records or columns layout.
+    pub fn write_record(&self, file: &mut [u8], index: u64, record: &DatasetRecord) -> Result<(), DatasetFormatError> {
+        match self.layout {
+            DatasetLayout::Records => {
+                let start = self.data_offset + index * self.datapoint_size as u64;
+                SerializedDataset::section_mut(file, "data", start, start + DatasetRecord::SIZE as u64)?.copy_from_slice(&record.to_bytes());
+            },
+            DatasetLayout::Columns => {
+                let [timestamps, user_ids, colors, mod_flags] = self.column_offsets();
+                SerializedDataset::section_mut(file, "data", timestamps + index * 8, timestamps + index * 8 + 8)?.copy_from_slice(&record.timestamp.to_le_bytes());
+                SerializedDataset::section_mut(file, "data", user_ids + index * 4, user_ids + index * 4 + 4)?.copy_from_slice(&record.user_id.to_le_bytes());
+                SerializedDataset::section_mut(file, "data", colors + index, colors + index + 1)?[0] = record.color;
+                SerializedDataset::section_mut(file, "data", mod_flags + index, mod_flags + index + 1)?[0] = record.is_mod;
+            },
+            DatasetLayout::Compressed => return Err(DatasetFormatError::UnsupportedLayout(self.layout)),
+        }
+
+        Ok(())
+    }
+
+    /// The compressed layout starts with one u64 per pixel plus one with the offset of each history
+    /// block relative to the data section. The last entry is the length of the data section.
+    pub fn block_table_length(&self) -> u64 {
+        (self.canvas_width as u64 * self.canvas_height as u64 + 1) * size_of::<u64>() as u64
+    }
+
+    /// Parses any header version into the current header struct.
+    pub fn from_bytes(bytes: &[u8]) -> Result<DatasetHeader, DatasetFormatError> {
+        let version_bytes = SerializedDataset::section(bytes, "header", DATASET_MAGIC.len() as u64, DATASET_MAGIC.len() as u64 + 4)?;
+        let version: u32 = bincode::deserialize(version_bytes)?;
+        match version {
+            1 => {
+                let header: DatasetHeaderV1 = bincode::deserialize(bytes)?;
+                Ok(header.into())
+            },
+            2 | 3 => {
+                let header: DatasetHeaderV2 = bincode::deserialize(bytes)?;
+                Ok(header.into())
+            },
+            4 => {
+                let header: DatasetHeaderV4 = bincode::deserialize(bytes)?;
+                Ok(header.into())
+            },
+            _ => Ok(bincode::deserialize(bytes)?),
+        }
+    }
+
+    /// Bincode encoding of the header, always `DatasetHeader::compressed_size` bytes long.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    /// Every field is fixed size, so the size of any header will do. This can't go through
+    /// DatasetHeader::new since new uses it for the metadata offset.
+    pub fn compressed_size() -> u64 {
+        let header = DatasetHeader {
+            magic: DATASET_MAGIC,
+            version: DATASET_FORMAT_VERSION,
+            canvas_width: 0,
+            canvas_height: 0,
+            origin_x: 0,
+            origin_y: 0,
+            datapoint_size: 0,
+            num_datapoints: 0,
+            metadata_offset: 0,
+            metadata_length: 0,
+            data_offset: 0,
+            layout: DatasetLayout::Records,
+            edit_log_offset: 0,
+            edit_log_length: 0,
+        };
+        header.to_bytes().len() as u64
+    }
+
+    /// True if `bytes` start with the magic of the header. Files without it are legacy datasets.
+    pub fn is_present(bytes: &[u8]) -> bool {
+        bytes.len() >= DATASET_MAGIC.len() && bytes[0..DATASET_MAGIC.len()] == DATASET_MAGIC
+    }
+
+    /// Size and r/place origin of the canvas.
+    pub fn dimensions(&self) -> CanvasDimensions {
+        CanvasDimensions::new_with_origin(self.canvas_width, self.canvas_height, self.origin_x, self.origin_y)
+    }
+
+    /// For the compressed layout this is only the length of the block table, the length of the
+    /// blocks is stored in the table.
+    pub fn data_length(&self) -> u64 {
+        match self.layout {
+            DatasetLayout::Compressed => self.block_table_length(),
+            _ => self.num_datapoints * self.datapoint_size as u64,
+        }
+    }
+
+    /// Section ends computed with checked arithmetic, so that a crafted header reports an
+    /// InvalidHeader error instead of overflowing.
+    pub fn metadata_end(&self) -> Result<u64, DatasetFormatError> {
+        self.metadata_offset.checked_add(self.metadata_length).ok_or_else(|| DatasetHeader::overflow_error("metadata"))
+    }
+
+    /// End of the data section, see `metadata_end`.
+    pub fn data_end(&self) -> Result<u64, DatasetFormatError> {
+        let data_length = match self.layout {
+            DatasetLayout::Compressed => (self.canvas_width as u64 * self.canvas_height as u64).checked_add(1).and_then(|entries| entries.checked_mul(size_of::<u64>() as u64)),
+            _ => self.num_datapoints.checked_mul(self.datapoint_size as u64),
+        };
+        data_length.and_then(|data_length| self.data_offset.checked_add(data_length)).ok_or_else(|| DatasetHeader::overflow_error("data"))
+    }
+
+    /// End of the edit log section, see `metadata_end`.
+    pub fn edit_log_end(&self) -> Result<u64, DatasetFormatError> {
+        self.edit_log_length.checked_mul(EditLogEntry::SIZE as u64)
+            .and_then(|edit_log_length| self.edit_log_offset.checked_add(edit_log_length))
+            .ok_or_else(|| DatasetHeader::overflow_error("edit log"))
+    }
+
+    fn overflow_error(section: &str) -> DatasetFormatError {
+        DatasetFormatError::InvalidHeader(format!("{} section does not fit in a 64 bit file", section))
+    }
+
+    /// Checks the header against the file it was read from.
+    pub fn validate(&self, file_length: u64) -> Result<(), DatasetFormatError> {
+        if self.version > DATASET_FORMAT_VERSION {
+            return Err(DatasetFormatError::UnsupportedVersion(self.version));
+        }
+
+        if self.canvas_width == 0 || self.canvas_height == 0 {
+            return Err(DatasetFormatError::InvalidHeader(format!("canvas size must be non-zero: {}x{}", self.canvas_width, self.canvas_height)));
+        }
+
+        let expected_datapoint_size = DatasetHeader::expected_datapoint_size(self.version, self.layout);
+        if self.datapoint_size != expected_datapoint_size {
+            return Err(DatasetFormatError::InvalidHeader(format!("datapoint size {} does not match expected size {}", self.datapoint_size, expected_datapoint_size)));
+        }
+
+        if self.version >= DATASET_RECORD_VERSION && !self.data_offset.is_multiple_of(DATASET_DATA_ALIGNMENT) {
+            return Err(DatasetFormatError::InvalidHeader(format!("data section at {} is not aligned to {} bytes", self.data_offset, DATASET_DATA_ALIGNMENT)));
+        }
+
+        let metadata_end = self.metadata_end()?;
+        if metadata_end > self.data_offset {
+            return Err(DatasetFormatError::InvalidHeader(format!("metadata section {}..{} overlaps data section at {}", self.metadata_offset, metadata_end, self.data_offset)));
+        }
+
+        let expected_length = self.data_end()?;
+        if expected_length > file_length {
+            return Err(DatasetFormatError::Truncated { section: "data", expected: expected_length, actual: file_length });
+        }
+
+        if self.has_edit_log() {
+            if self.edit_log_offset < expected_length || !self.edit_log_offset.is_multiple_of(DATASET_DATA_ALIGNMENT) || self.edit_log_length > self.num_datapoints {
+                return Err(DatasetFormatError::InvalidHeader(format!("invalid edit log with {} entries at {}", self.edit_log_length, self.edit_log_offset)));
+            }
+
+            let edit_log_end = self.edit_log_end()?;
+            if edit_log_end > file_length {
+                return Err(DatasetFormatError::Truncated { section: "edit log", expected: edit_log_end, actual: file_length });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PrecompressedDatasetMetadata {
     pub canvas_width: u32,
     pub canvas_height: u32,
@@ -21,15 +522,37 @@ pub struct PrecompressedDatasetMetadata {
 }
 
 impl PrecompressedDatasetMetadata {
-    pub fn new(canvas_size: usize) -> PrecompressedDatasetMetadata {
+    pub fn new(canvas_width: usize, canvas_height: usize) -> PrecompressedDatasetMetadata {
         let default_metadata = PrecompressedDatapointHistoryMetadata::default();
         PrecompressedDatasetMetadata {
-            canvas_width: canvas_size as u32,
-            canvas_height: canvas_size as u32,
+            canvas_width: canvas_width as u32,
+            canvas_height: canvas_height as u32,
             min_timestamp: u64::MIN,
             max_timestamp: u64::MAX,
             datapoint_size: RPlaceDatasetDatapoint::start().to_bytes().len() as u8,
-            history_metadata: vec![default_metadata; canvas_size * canvas_size],
+            history_metadata: vec![default_metadata; canvas_width * canvas_height],
+        }
+    }
+
+    // builds the metadata for histories stored back to back in pixel order
+    pub fn new_with_history_lengths(dimensions: CanvasDimensions, min_timestamp: u64, max_timestamp: u64, datapoint_size: u32, history_lengths: &[u32]) -> PrecompressedDatasetMetadata {
+        let mut history_metadata = Vec::with_capacity(history_lengths.len());
+        let mut offset = 0u32;
+        for length in history_lengths.iter() {
+            history_metadata.push(PrecompressedDatapointHistoryMetadata { 
+                offset, 
+                length: *length, 
+            });
+            offset += length;
+        }
+
+        PrecompressedDatasetMetadata {
+            canvas_width: dimensions.width,
+            canvas_height: dimensions.height,
+            min_timestamp,
+            max_timestamp,
+            datapoint_size: datapoint_size as u8,
+            history_metadata,
         }
     }
 
@@ -37,9 +560,14 @@ impl PrecompressedDatasetMetadata {
         bincode::serialize(self).unwrap()
     }
 
-    pub fn compressed_size(canvas_size: usize) -> u64 {
-        let metadata = PrecompressedDatasetMetadata::new(canvas_size);
-        metadata.to_bytes().len() as u64
+    // bincode output length only depends on the number of histories, not their values. None if
+    // it does not fit in a u64
+    pub fn compressed_size(canvas_width: usize, canvas_height: usize) -> Option<u64> {
+        let empty_length = PrecompressedDatasetMetadata::new(0, 0).to_bytes().len() as u64;
+        let history_length = bincode::serialized_size(&PrecompressedDatapointHistoryMetadata::default()).unwrap();
+        (canvas_width as u64).checked_mul(canvas_height as u64)?
+            .checked_mul(history_length)?
+            .checked_add(empty_length)
     }
 
     pub fn get(&self, x: u32, y: u32) -> &PrecompressedDatapointHistoryMetadata {
@@ -54,69 +582,360 @@ pub struct PrecompressedDatapointHistoryMetadata {
     length: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct PrecompressedDatasetData {
-    data: Vec<RPlaceDatasetDatapoint>
-}
+impl PrecompressedDatapointHistoryMetadata {
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct PrecompressedDataset {
-    metadata: PrecompressedDatasetMetadata,
-    data: PrecompressedDatasetData,
+    pub fn length(&self) -> u32 {
+        self.length
+    }
 }
 
+/// A dataset in the custom format, memory mapped and read in place.
 #[derive(Debug)]
 pub struct SerializedDataset {
     mmap: Mmap,
+    pub header: DatasetHeader,
     pub metadata: PrecompressedDatasetMetadata,
-    data_start_idx: u64,
 }
 
 impl SerializedDataset {
-    pub fn new(file_path: &str) -> SerializedDataset {
-        let file = File::open(file_path).unwrap();
-        let mmap = unsafe { Mmap::map(&file).unwrap() };
-
-        let canvas_width: u32 = bincode::deserialize(mmap.get(0..4).unwrap()).unwrap();
-        let canvas_height: u32 = bincode::deserialize(mmap.get(0..4).unwrap()).unwrap();
-        assert_eq!(canvas_width, canvas_height);
+    /// Opens the dataset at `file_path`. Only the header and metadata are checked, see `verify`.
+    pub fn new(file_path: &str) -> Result<SerializedDataset, DatasetFormatError> {
+        let file = File::open(file_path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        let header = if DatasetHeader::is_present(&mmap) {
+            let header = DatasetHeader::from_bytes(&mmap)?;
+            header.validate(mmap.len() as u64)?;
+            header
+        } else {
+            SerializedDataset::legacy_header(&mmap)?
+        };
 
-        let data_start_idx = PrecompressedDatasetMetadata::compressed_size(canvas_width as usize);
-        let metadata_bytes = mmap.get(0..data_start_idx as usize).unwrap();
-        let metadata = bincode::deserialize(metadata_bytes).unwrap();
+        let metadata_end = header.metadata_end()?;
+        let metadata_bytes = SerializedDataset::section(&mmap, "metadata", header.metadata_offset, metadata_end)?;
+        let metadata: PrecompressedDatasetMetadata = bincode::deserialize(metadata_bytes)?;
+        SerializedDataset::validate_metadata(&header, &metadata)?;
 
-        let num_datapoints: u64 = bincode::deserialize(mmap.get(data_start_idx as usize..(data_start_idx+8) as usize).unwrap()).unwrap();
-        println!("num datapoints {}", num_datapoints);
-        SerializedDataset { 
+        println!("num datapoints {}", header.num_datapoints);
+        Ok(SerializedDataset { 
             mmap, 
+            header,
             metadata, 
-            data_start_idx,
+        })
+    }
+
+    // Legacy files are a bincode serialized (PrecompressedDatasetMetadata, Vec<RPlaceDatasetDatapoint>) pair, 
+    // so the layout has to be derived from the canvas size stored in the first 8 bytes
+    fn legacy_header(mmap: &[u8]) -> Result<DatasetHeader, DatasetFormatError> {
+        let file_length = mmap.len() as u64;
+        let size_bytes = SerializedDataset::section(mmap, "header", 0, 8)?;
+        let canvas_width: u32 = bincode::deserialize(&size_bytes[0..4])?;
+        let canvas_height: u32 = bincode::deserialize(&size_bytes[4..8])?;
+        if canvas_width == 0 || canvas_height == 0 {
+            return Err(DatasetFormatError::InvalidHeader(format!("canvas size must be non-zero: {}x{}", canvas_width, canvas_height)));
+        }
+
+        // the metadata size only depends on the number of pixels so it can be computed up front
+        let metadata_length = PrecompressedDatasetMetadata::compressed_size(canvas_width as usize, canvas_height as usize)
+            .ok_or_else(|| DatasetHeader::overflow_error("metadata"))?;
+        if metadata_length.saturating_add(8) > file_length {
+            return Err(DatasetFormatError::Truncated { section: "metadata", expected: metadata_length.saturating_add(8), actual: file_length });
         }
+
+        let length_bytes = SerializedDataset::section(mmap, "data", metadata_length, metadata_length + 8)?;
+        let num_datapoints: u64 = bincode::deserialize(length_bytes)?;
+
+        let header = DatasetHeader {
+            magic: DATASET_MAGIC,
+            version: LEGACY_FORMAT_VERSION,
+            canvas_width,
+            canvas_height,
+            origin_x: 0,
+            origin_y: 0,
+            datapoint_size: RPlaceDatasetDatapoint::compressed_size() as u32,
+            num_datapoints,
+            metadata_offset: 0,
+            metadata_length,
+
+            // skip the length prefix of the serialized data vec
+            data_offset: metadata_length + 8,
+            layout: DatasetLayout::Records,
+            edit_log_offset: 0,
+            edit_log_length: 0,
+        };
+        header.validate(file_length)?;
+        Ok(header)
     }
 
+    fn validate_metadata(header: &DatasetHeader, metadata: &PrecompressedDatasetMetadata) -> Result<(), DatasetFormatError> {
+        if (metadata.canvas_width, metadata.canvas_height) != (header.canvas_width, header.canvas_height) {
+            return Err(DatasetFormatError::InvalidHeader(format!("metadata canvas size {}x{} does not match header canvas size {}x{}", 
+                metadata.canvas_width, metadata.canvas_height, header.canvas_width, header.canvas_height)));
+        }
+
+        let num_pixels = header.canvas_width as usize * header.canvas_height as usize;
+        if metadata.history_metadata.len() != num_pixels {
+            return Err(DatasetFormatError::InvalidHeader(format!("expected {} pixel histories but found {}", num_pixels, metadata.history_metadata.len())));
+        }
+
+        for (idx, history) in metadata.history_metadata.iter().enumerate() {
+            if history.length == 0 || history.offset as u64 + history.length as u64 > header.num_datapoints {
+                return Err(DatasetFormatError::InvalidHeader(format!("pixel history {} has invalid range {}+{} for {} datapoints", 
+                    idx, history.offset, history.length, header.num_datapoints)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads every datapoint once. Datapoints with a color that is not in the palette are bad
+    /// records and handled by `policy`, history blocks that can't be decoded stop the check.
+    /// Opening a dataset does not do this, corrupt datapoints are only noticed when they are read.
+    pub fn verify(&self, policy: BadRecordPolicy) -> Result<BadRecordCounts, RPlaceError> {
+        let mut bad_records = BadRecordCounts::default();
+        let mut check_color = |index: u64, color: u8| match PixelColor::from_index(color) {
+            Some(_) => Ok(()),
+            None => policy.handle(RPlaceError::UnknownColor(format!("index {} of datapoint {}", color, index)), &mut bad_records),
+        };
+
+        if self.header.layout != DatasetLayout::Compressed {
+            for index in 0..self.header.num_datapoints {
+                check_color(index, self.header.read_record(&self.mmap, index)?.color)?;
+            }
+            return Ok(bad_records);
+        }
+
+        let table_length = self.header.block_table_length();
+        let table = SerializedDataset::section(&self.mmap, "data", self.header.data_offset, self.header.data_offset + table_length)?;
+        let block_offsets: Vec<u64> = table.chunks_exact(size_of::<u64>())
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+
+        let data_length = block_offsets[block_offsets.len() - 1];
+        for (pixel, history_metadata) in self.metadata.history_metadata.iter().enumerate() {
+            let (start, end) = (block_offsets[pixel], block_offsets[pixel + 1]);
+            if start < table_length || start > end || end > data_length {
+                return Err(DatasetFormatError::CorruptData(format!("history block {} has invalid range {}..{} in {} bytes of data", pixel, start, end, data_length)).into());
+            }
+
+            let block = SerializedDataset::section(&self.mmap, "data", self.header.data_offset + start, self.header.data_offset + end)?;
+            let history = CompressedHistory::new(block)?;
+            if history.len() != history_metadata.length as usize {
+                return Err(DatasetFormatError::CorruptData(format!("history block {} has {} datapoints but the metadata expects {}", pixel, history.len(), history_metadata.length)).into());
+            }
+
+            let mut previous_timestamp = 0;
+            for (idx, record) in history.iter().enumerate() {
+                let record = record?;
+                if record.timestamp < previous_timestamp {
+                    return Err(DatasetFormatError::CorruptData(format!("history block {} is not in timestamp order", pixel)).into());
+                }
+                previous_timestamp = record.timestamp;
+                check_color(history_metadata.offset as u64 + idx as u64, record.color)?;
+            }
+        }
+
+        Ok(bad_records)
+    }
+
+    fn section<'a>(mmap: &'a [u8], section: &'static str, start: u64, end: u64) -> Result<&'a [u8], DatasetFormatError> {
+        match mmap.get(start as usize..end as usize) {
+            Some(bytes) => Ok(bytes),
+            None => Err(DatasetFormatError::Truncated { section, expected: end, actual: mmap.len() as u64 }),
+        }
+    }
+
+    fn section_mut<'a>(mmap: &'a mut [u8], section: &'static str, start: u64, end: u64) -> Result<&'a mut [u8], DatasetFormatError> {
+        let actual = mmap.len() as u64;
+        match mmap.get_mut(start as usize..end as usize) {
+            Some(bytes) => Ok(bytes),
+            None => Err(DatasetFormatError::Truncated { section, expected: end, actual }),
+        }
+    }
+
+    /// Size and r/place origin of the canvas the dataset was built for.
+    pub fn dimensions(&self) -> CanvasDimensions {
+        self.header.dimensions()
+    }
+
+    /// Number of datapoints in the dataset, including one sentinel per pixel.
+    pub fn num_datapoints(&self) -> u64 {
+        self.header.num_datapoints
+    }
+
+    /// First and last timestamp in the dataset. The first timestamp is the one of the sentinels.
+    pub fn timestamp_range(&self) -> (u64, u64) {
+        (self.metadata.min_timestamp, self.metadata.max_timestamp)
+    }
+
+    /// All datapoints of the pixel at canvas coordinate (x, y) in timestamp order, starting with the sentinel.
+    pub fn pixel_history(&self, x: u32, y: u32) -> SerializedDatapointHistory<'_> {
+        SerializedDatapointHistory::new(self, x, y)
+    }
+
+    /// The records of the pixel at (x, y) read in place from the mmap. Only available for
+    /// datasets of version 3 and later on little endian targets.
+    pub fn pixel_records(&self, x: u32, y: u32) -> Option<&[DatasetRecord]> {
+        if !self.header.has_records() {
+            return None;
+        }
+
+        DatasetRecord::slice_from_bytes(self.datapoint_history_bytes(x, y))
+    }
+
+    /// The timestamps of the pixel at (x, y) read in place from the timestamp column. Only 
+    /// available for datasets with the columns layout on little endian targets.
+    pub fn pixel_timestamps(&self, x: u32, y: u32) -> Option<&[u64]> {
+        if self.header.layout != DatasetLayout::Columns || cfg!(target_endian = "big") {
+            return None;
+        }
+
+        let start = self.datapoint_history_xy_offset(x, y) as usize;
+        let end = start + self.datapoint_history_len(x, y) * size_of::<u64>();
+        let bytes = self.mmap.get(start..end)?;
+        if !(bytes.as_ptr() as usize).is_multiple_of(align_of::<u64>()) {
+            return None;
+        }
+
+        // safe because the bytes are aligned, in bounds and every bit pattern is a valid u64
+        Some(unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const u64, bytes.len() / size_of::<u64>()) })
+    }
+
+    /// The encoded history of the pixel at (x, y). Only available for datasets with the compressed layout.
+    pub fn compressed_history(&self, x: u32, y: u32) -> Option<CompressedHistory<'_>> {
+        if self.header.layout != DatasetLayout::Compressed {
+            return None;
+        }
+
+        Some(self.compressed_history_at(self.datapoint_history_xy_offset(x, y)))
+    }
+
+    /// Index into the history of (x, y) of the last datapoint placed at or before `timestamp`.
+    /// Timestamps before the first datapoint return the sentinel at index 0.
+    pub fn history_index_at(&self, x: u32, y: u32, timestamp: u64) -> usize {
+        if let Some(history) = self.compressed_history(x, y) {
+            return history.index_at(timestamp).expect(CORRUPT_DATA).unwrap_or(0);
+        }
+
+        let history_offset = self.datapoint_history_xy_offset(x, y);
+
+        // first index with a timestamp after the given one
+        let mut low = 1;
+        let mut high = self.datapoint_history_len(x, y);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.datapoint_timestamp_with_history_offset(history_offset, mid as u32) <= timestamp {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        low - 1
+    }
+
+    /// Number of datapoints of (x, y) placed after `start_timestamp` and at or before `end_timestamp`.
+    pub fn num_edits_between(&self, x: u32, y: u32, start_timestamp: u64, end_timestamp: u64) -> usize {
+        if end_timestamp <= start_timestamp {
+            return 0;
+        }
+
+        self.history_index_at(x, y, end_timestamp) - self.history_index_at(x, y, start_timestamp)
+    }
+
+    /// The datapoint that determines the color of (x, y) at `timestamp`.
+    pub fn datapoint_at(&self, x: u32, y: u32, timestamp: u64) -> RPlaceDatasetDatapoint {
+        let idx = self.history_index_at(x, y, timestamp);
+        self.datapoint_with_history_offset(self.datapoint_history_xy_offset(x, y), idx as u32)
+    }
+
+    /// Color of (x, y) at `timestamp`.
+    pub fn color_at(&self, x: u32, y: u32, timestamp: u64) -> PixelColor {
+        self.datapoint_at(x, y, timestamp).color
+    }
+
+    /// Number of entries in the edit log, 0 for datasets written before version 5.
+    pub fn edit_log_len(&self) -> usize {
+        if !self.header.has_edit_log() {
+            return 0;
+        }
+
+        self.header.edit_log_length as usize
+    }
+
+    /// The `index`th edit of the dataset in timestamp order, or `None` past the end of the edit log.
+    pub fn edit(&self, index: usize) -> Option<EditLogEntry> {
+        if index >= self.edit_log_len() {
+            return None;
+        }
+
+        Some(self.read_edit(index))
+    }
+
+    // index has to be below edit_log_len
+    fn read_edit(&self, index: usize) -> EditLogEntry {
+        let start = self.header.edit_log_offset as usize + index * EditLogEntry::SIZE;
+        EditLogEntry::from_bytes(&self.mmap[start..start + EditLogEntry::SIZE])
+    }
+
+    /// The edits in `range` read in place from the mmap. `None` for an empty, reversed or out of
+    /// bounds range, and on big endian targets.
+    pub fn edits(&self, range: Range<usize>) -> Option<&[EditLogEntry]> {
+        if range.start >= range.end || range.end > self.edit_log_len() {
+            return None;
+        }
+
+        let start = self.header.edit_log_offset as usize;
+        EditLogEntry::slice_from_bytes(&self.mmap[start + range.start * EditLogEntry::SIZE..start + range.end * EditLogEntry::SIZE])
+    }
+
+    /// Index of the first edit placed after `timestamp`, or the length of the edit log if there is none.
+    pub fn edit_index_after(&self, timestamp: u64) -> usize {
+        let mut low = 0;
+        let mut high = self.edit_log_len();
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.read_edit(mid).timestamp <= timestamp {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        low
+    }
+
+    // The byte views below cover whole datapoints and are only meaningful for the records layout.
+    // Use pixel_history or datapoint_with_history_offset to read datapoints in any layout
+
+    /// The bytes of every datapoint of (x, y).
     pub fn datapoint_history_bytes(&self, x: u32, y: u32) -> &[u8] {
         let metadata_idx = y * self.metadata.canvas_width + x;
         let datapoint_history_metadata = &self.metadata.history_metadata[metadata_idx as usize];
-        let start_idx = self.data_start_idx + 8 + (datapoint_history_metadata.offset as u64 * self.metadata.datapoint_size as u64);
-        let end_idx = self.data_start_idx + 8 + ((datapoint_history_metadata.offset + datapoint_history_metadata.length) as u64 * self.metadata.datapoint_size as u64);
+        let start_idx = self.header.data_offset + (datapoint_history_metadata.offset as u64 * self.metadata.datapoint_size as u64);
+        let end_idx = self.header.data_offset + ((datapoint_history_metadata.offset + datapoint_history_metadata.length) as u64 * self.metadata.datapoint_size as u64);
 
         //println!("fetching (x, y)=({} ,{}) from bytes {}..{} | metadata offset {} length {}", x, y, start_idx, end_idx, datapoint_history_metadata.offset, datapoint_history_metadata.length);
         self.mmap.get(start_idx as usize..end_idx as usize).unwrap()
     }
 
+    /// Number of datapoints of (x, y), including the sentinel.
     pub fn datapoint_history_len(&self, x: u32, y: u32) -> usize {
         let metadata_idx = y * self.metadata.canvas_width + x;
         let datapoint_history_metadata = &self.metadata.history_metadata[metadata_idx as usize];
         datapoint_history_metadata.length as usize
     }
 
-    // obtains a slice of the datapoint history
-    // this is equivalent of calling self.datapoint_history_bytes(x, y)[slice_start_idx..slice_end_idx]
+    /// Obtains a slice of the datapoint history,
+    /// this is equivalent of calling self.datapoint_history_bytes(x, y)[slice_start_idx..slice_end_idx].
     pub fn datapoint_history_bytes_sliced(&self, x: u32, y: u32, slice_start_idx: u32, slice_end_idx: u32) -> &[u8] {
         let metadata_idx = y * self.metadata.canvas_width + x;
         let datapoint_history_metadata = &self.metadata.history_metadata[metadata_idx as usize];
 
-        let first_datapoint_idx = self.data_start_idx + 8;
+        let first_datapoint_idx = self.header.data_offset;
         let mmap_start_idx = first_datapoint_idx + ((datapoint_history_metadata.offset + slice_start_idx) as u64 * self.metadata.datapoint_size as u64);
 
         let slice_length = slice_end_idx - slice_start_idx;
@@ -126,85 +945,151 @@ impl SerializedDataset {
         self.mmap.get(mmap_start_idx as usize..mmap_end_idx as usize).unwrap()
     }
 
+    /// Position of the timestamp of the first datapoint of (x, y). In the columns layout this points
+    /// into the timestamp column, in the compressed layout to the start of the history block.
     pub fn datapoint_history_xy_offset(&self, x: u32, y: u32) -> u64 {
         let metadata_idx = y * self.metadata.canvas_width + x;
         let datapoint_history_metadata = &self.metadata.history_metadata[metadata_idx as usize];
-
-        let first_datapoint_idx = self.data_start_idx + 8;
-        first_datapoint_idx + (datapoint_history_metadata.offset as u64 * self.metadata.datapoint_size as u64)
+        match self.header.timestamp_position(datapoint_history_metadata.offset as u64) {
+            Some(position) => position as u64,
+            None => {
+                let entry = (self.header.data_offset + metadata_idx as u64 * size_of::<u64>() as u64) as usize;
+                let block_offset = u64::from_le_bytes(self.mmap[entry..entry + 8].try_into().unwrap());
+                self.header.data_offset + block_offset
+            },
+        }
     }
 
+    /// The bytes of datapoint `idx` of the history at `history_offset`.
     pub fn datapoint_bytes_with_history_offset(&self, history_offset: u64, idx: u32) -> &[u8] {
         let mmap_start_idx = history_offset + (idx as u64 * self.metadata.datapoint_size as u64);
         let mmap_end_idx = mmap_start_idx + self.metadata.datapoint_size as u64;
         self.mmap.get(mmap_start_idx as usize..mmap_end_idx as usize).unwrap()
     }
 
+    /// Timestamp of datapoint `idx` of the history at `history_offset`, see `datapoint_history_xy_offset`.
     pub fn datapoint_timestamp_with_history_offset(&self, history_offset: u64, idx: u32) -> u64 {
-        let mmap_start_idx = history_offset + (idx as u64 * self.metadata.datapoint_size as u64);
+        let stride = match self.header.timestamp_stride() {
+            Some(stride) => stride,
+            None => return self.compressed_history_at(history_offset).timestamp(idx as usize).expect(CORRUPT_DATA),
+        };
+
+        let mmap_start_idx = history_offset + (idx as u64 * stride);
         let mmap_end_idx = mmap_start_idx as usize + size_of::<u64>();
         let bytes = self.mmap.get(mmap_start_idx as usize..mmap_end_idx).unwrap();
-        bincode::deserialize(bytes).unwrap()
+        DatasetRecord::timestamp_from_bytes(bytes)
     }
 
+    /// Timestamp of datapoint `idx` of (x, y).
     pub fn datapoint_timestamp_with_xy_and_idx(&self, x: u32, y: u32, idx: u32) -> u64 {
         let history_offset = self.datapoint_history_xy_offset(x, y);
         return self.datapoint_timestamp_with_history_offset(history_offset, idx);
     }
 
+    /// Datapoint `idx` of the history at `history_offset`, see `datapoint_history_xy_offset`.
     pub fn datapoint_with_history_offset(&self, history_offset: u64, idx: u32) -> RPlaceDatasetDatapoint {
-        let bytes = self.datapoint_bytes_with_history_offset(history_offset, idx);
-        bincode::deserialize(bytes).unwrap()
+        if self.header.version < DATASET_RECORD_VERSION {
+            let bytes = self.datapoint_bytes_with_history_offset(history_offset, idx);
+            return bincode::deserialize(bytes).unwrap();
+        }
+
+        let stride = match self.header.timestamp_stride() {
+            Some(stride) => stride,
+            None => return self.compressed_history_at(history_offset).get(idx as usize).expect(CORRUPT_DATA),
+        };
+
+        let index = (history_offset - self.header.data_offset) / stride + idx as u64;
+        self.header.read_record(&self.mmap, index).and_then(|record| record.datapoint()).expect(CORRUPT_DATA)
     }
 
-    pub fn search(&self, timestamp: u64, x: usize, y: usize, start_idx: usize, end_idx: usize, current_value: &CanvasPixel) -> usize {
+    fn compressed_history_at(&self, history_offset: u64) -> CompressedHistory<'_> {
+        let bytes = self.mmap.get(history_offset as usize..).expect(CORRUPT_DATA);
+        CompressedHistory::new(bytes).expect(CORRUPT_DATA)
+    }
+
+    /// Index of the last datapoint of (x, y) in start_idx..end_idx placed at or before `timestamp`.
+    /// The range has to start at such a datapoint, e.g. the sentinel at index 0.
+    pub fn search(&self, timestamp: u64, x: usize, y: usize, start_idx: usize, end_idx: usize) -> usize {
         //println!("Searching for timestamp {} at ({}, {}) in {}..{}", timestamp, x, y, start_idx, end_idx);
-        let history_offset = self.datapoint_history_xy_offset(x as u32, y as u32);
+        if let Some(history) = self.compressed_history(x as u32, y as u32) {
+            // the history is sorted so the first datapoint after the timestamp in start..end is the 
+            // first one in the whole history, clamped to the range
+            let first_after = history.index_at(timestamp).expect(CORRUPT_DATA).map_or(0, |index| index + 1);
+            return first_after.clamp(start_idx, end_idx).saturating_sub(1);
+        }
 
-        let result = least_greater(&timestamp, start_idx as i64, end_idx as i64 - 1, |i: usize| {
-            self.datapoint_timestamp_with_history_offset(history_offset, i as u32)
-        });
+        let result = if let Some(timestamps) = self.pixel_timestamps(x as u32, y as u32) {
+            least_greater(&timestamp, start_idx as i64, end_idx as i64 - 1, |i: usize| {
+                timestamps[i]
+            })
+        } else {
+            match self.pixel_records(x as u32, y as u32) {
+                Some(records) => least_greater(&timestamp, start_idx as i64, end_idx as i64 - 1, |i: usize| {
+                    records[i].timestamp
+                }),
+                None => {
+                    let history_offset = self.datapoint_history_xy_offset(x as u32, y as u32);
+                    least_greater(&timestamp, start_idx as i64, end_idx as i64 - 1, |i: usize| {
+                        self.datapoint_timestamp_with_history_offset(history_offset, i as u32)
+                    })
+                },
+            }
+        };
 
-        let index;
         match result {
-            Ok(value) => index = value as usize,
-            Err(value) => index = value as usize - 1,
+            Ok(value) => value as usize,
+            Err(value) => value as usize - 1,
         }
-
-        index
     }
 }
 
+/// One pixel history of a SerializedDataset. Reads datapoints on demand in any layout.
 pub struct SerializedDatapointHistory<'a> {
-    bytes: &'a [u8],
-    datapoint_size: u8,
+    dataset: &'a SerializedDataset,
+    history_offset: u64,
     length: usize,
+    compressed: Option<CompressedHistory<'a>>,
 }
 
 impl<'a> SerializedDatapointHistory<'a> {
-    pub fn new(bytes: &'a [u8]) -> SerializedDatapointHistory {
-        let datapoint_size = RPlaceDatasetDatapoint::compressed_size();
-        let length = bytes.len() / datapoint_size as usize;
-
-        // ensures that bytes is properly divisible by length w/ no remainder
-        //println!("datapoint_size {} / {} = {}", bytes.len(), datapoint_size, length);
-        assert_eq!(bytes.len(), datapoint_size as usize * length);
-
+    /// The history of (x, y) in `dataset`.
+    pub fn new(dataset: &'a SerializedDataset, x: u32, y: u32) -> SerializedDatapointHistory<'a> {
         SerializedDatapointHistory {
-            bytes,
-            datapoint_size,
-            length,
+            dataset,
+            history_offset: dataset.datapoint_history_xy_offset(x, y),
+            length: dataset.datapoint_history_len(x, y),
+            compressed: dataset.compressed_history(x, y),
         }
     }
 
+    /// Datapoint `index` of the history, the sentinel is at index 0.
     pub fn get(&self, index: usize) -> RPlaceDatasetDatapoint {
-        bincode::deserialize(self.get_bytes(index)).unwrap()
+        assert!(index < self.length, "index {} out of bounds for history of length {}", index, self.length);
+        self.dataset.datapoint_with_history_offset(self.history_offset, index as u32)
     }
 
-    pub fn get_bytes(&self, index: usize) -> &'a [u8] {
-        let start_idx = index * self.datapoint_size as usize;
-        let end_idx = start_idx + self.datapoint_size as usize;
-        &self.bytes[start_idx..end_idx]
+    /// Timestamp of datapoint `index`, without decoding the rest of it in the records and columns layouts.
+    pub fn timestamp(&self, index: usize) -> u64 {
+        assert!(index < self.length, "index {} out of bounds for history of length {}", index, self.length);
+        self.dataset.datapoint_timestamp_with_history_offset(self.history_offset, index as u32)
+    }
+
+    /// Number of datapoints, including the sentinel.
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    /// True if the history has no datapoints, which only happens in corrupt datasets.
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Compressed histories are decoded sequentially instead of looking up every index.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = RPlaceDatasetDatapoint> + '_> {
+        match &self.compressed {
+            Some(history) => Box::new(history.iter().map(|record| record.and_then(|record| record.datapoint()).expect(CORRUPT_DATA))),
+            None => Box::new((0..self.length).map(|index| self.get(index))),
+        }
     }
 }
 
@@ -213,112 +1098,29 @@ pub struct SerializedDatapoint<'a> {
 }
 
 impl<'a> SerializedDatapoint<'a> {
-    pub fn new(bytes: &'a [u8]) -> SerializedDatapoint {
+    pub fn new(bytes: &'a [u8]) -> SerializedDatapoint<'a> {
         SerializedDatapoint { 
             bytes,
         }
     }
     
     pub fn timestamp(&self) -> u64 {
-        bincode::deserialize(&self.bytes[0..size_of::<u64>()]).unwrap()
+        DatasetRecord::timestamp_from_bytes(self.bytes)
     }
 
     pub fn extract_timestamp(bytes: &[u8]) -> u64 {
-        bincode::deserialize(&bytes[0..size_of::<u64>()]).unwrap()
+        DatasetRecord::timestamp_from_bytes(bytes)
     }
 }
 
-pub fn write_data_to_file(parquet_dataset_file_path: &str, output_file_path: &str) {
-    // day 2 start: 28_201_610
-    // day 3 start: 71_784_347
-    // end: 160_808_191
-    let limit = 160_808_191;
-    let print_frequency = 1_000_000;
-    let reader = RPlaceDataReader::new(parquet_dataset_file_path).unwrap();
-    
-    // data to record
-    let canvas_size = 2000;
-    let mut dataset = RPlaceDataset::new_with_initial_datapoint(canvas_size);
-    let mut min_timestamp: u64 = u64::MIN;
-    let mut max_timestamp: u64 = u64::MAX;
-
-    let mut count: u64 = 0;
-    for (i, record) in reader.into_iter().take(limit).enumerate() {
-        if i == 0 {
-            println!("Reading datapoint {}: {:?}", i, record);
-            
-            // set min_timestamp to be one less than the smallest timestamp in the dataset
-            min_timestamp = record.timestamp - 1;
-            for j in 0..dataset.data.len() {
-                for k in 0..dataset.data[j].len() {
-                    dataset.data[j][k][0].timestamp = min_timestamp;
-                }
-            }
-        } 
-
-        if i % print_frequency == 0 {
-            println!("Reading datapoint {}: {:?}", i, record);
-        }
-        
-        let x = record.coordinate.x as usize;
-        let y = record.coordinate.y as usize;
-        dataset.add(record.into(), x, y);
-        max_timestamp = record.timestamp;
-        count += 1;
-    }
-
-    println!("Total datapoints: {}", count);
-
-    // create compressed dataset variables 
-    let mut compressed_dataset_metadata = PrecompressedDatasetMetadata {
-        canvas_width: canvas_size as u32,
-        canvas_height: canvas_size as u32,
-        min_timestamp: min_timestamp,
-        max_timestamp: max_timestamp,
-        datapoint_size: RPlaceDatasetDatapoint::start().to_bytes().len() as u8,
-        history_metadata: Vec::new(),
-    };
-
-    let mut compressed_dataset_data = PrecompressedDatasetData {
-        data: Vec::new(),
-    };
-
-    // populate compressed data values 
-    let mut idx = 0u32;
-    for (y, row) in dataset.data.iter_mut().enumerate() {
-        for (x, history) in row.iter_mut().enumerate() {
-            let history_length = history.len() as u32;
-            let metadata = PrecompressedDatapointHistoryMetadata {
-                offset: idx,
-                length: history_length,
-            };
-
-            compressed_dataset_metadata.history_metadata.push(metadata);
-            compressed_dataset_data.data.append(history);
-
-            idx += history_length;
-        }
-    }
-
-    // write data to file
-    let compressed_dataset = PrecompressedDataset {
-        metadata: compressed_dataset_metadata,
-        data: compressed_dataset_data,
-    };
-    let dataset_bytes = bincode::serialize(&compressed_dataset).unwrap();
-    
-    let mut file = File::create(output_file_path).unwrap();
-    file.write_all(dataset_bytes.as_slice()).unwrap();
-    file.flush().unwrap();
-
-    let metadata_bytes = bincode::serialize(&compressed_dataset.metadata).unwrap();
-    let data_bytes = bincode::serialize(&compressed_dataset.data).unwrap();
-    let array_bytes = bincode::serialize(&compressed_dataset.data.data).unwrap();
-    println!("Length metadata {} | metadata_calculated {} | data {} | vec {} | vec_len {} | dataset {}", metadata_bytes.len(), PrecompressedDatasetMetadata::compressed_size(canvas_size), data_bytes.len(), array_bytes.len(), compressed_dataset.data.data.len(), dataset_bytes.len());
-}
-
 pub fn read_data_from_compressed_file(file_path: &str) {
-    let compressed_dataset_mmap = SerializedDataset::new(file_path);
+    let compressed_dataset_mmap = match SerializedDataset::new(file_path) {
+        Ok(dataset) => dataset,
+        Err(e) => {
+            println!("Error: failed to open dataset {}: {}", file_path, e);
+            return;
+        },
+    };
     let metadata = &compressed_dataset_mmap.metadata;
 
     println!("canvas size {} {} | datapoint size {} | min/max timestamp {} {} | metadata size {} ", metadata.canvas_width, metadata.canvas_height, metadata.datapoint_size, metadata.min_timestamp, metadata.max_timestamp, metadata.history_metadata.len());
@@ -327,20 +1129,16 @@ pub fn read_data_from_compressed_file(file_path: &str) {
     for y in 0..metadata.canvas_height {
         let mut row: Vec<SerializedDatapointHistory> = Vec::new();
         for x in 0..metadata.canvas_width {
-            let bytes = compressed_dataset_mmap.datapoint_history_bytes(x, y);
-            let datapoint_history = SerializedDatapointHistory::new(bytes);
-            row.push(datapoint_history);
+            row.push(compressed_dataset_mmap.pixel_history(x, y));
 
         }
         dataset.push(row);
     }
 
     let start_time = Instant::now();
-    for (y, row) in dataset.iter().enumerate() {
-        for (x, datapoint_history) in row.iter().enumerate() {
-            let datapoint: RPlaceDatasetDatapoint = datapoint_history.get(0);
-            datapoint;
-            //println!("datapoint history (x, y)=({}, {}) | history {:?} | datapoint {:?}", x, y, datapoint_history.bytes, datapoint);
+    for row in dataset.iter() {
+        for datapoint_history in row.iter() {
+            std::hint::black_box(datapoint_history.get(0));
         }
     }
 
@@ -395,4 +1193,161 @@ pub fn test_mmap() {
     }
 
     println!("Done");
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2x2 canvas with one edit on (0, 0) and one on (1, 1)
+    fn header(layout: DatasetLayout) -> DatasetHeader {
+        DatasetHeader::new_with_layout(CanvasDimensions::new_with_origin(2, 2, -1, -1), 6, 100, layout)
+    }
+
+    fn temp_file_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("rplace-custom-{}-{}", std::process::id(), name)).to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn header_round_trip() {
+        for layout in [DatasetLayout::Records, DatasetLayout::Columns, DatasetLayout::Compressed] {
+            let header = header(layout);
+            let bytes = header.to_bytes();
+            assert_eq!(bytes.len() as u64, DatasetHeader::compressed_size());
+            assert!(DatasetHeader::is_present(&bytes));
+
+            let parsed = DatasetHeader::from_bytes(&bytes).unwrap();
+            assert_eq!(parsed.to_bytes(), bytes, "{:?} layout", layout);
+            assert_eq!(parsed.dimensions(), CanvasDimensions::new_with_origin(2, 2, -1, -1));
+        }
+    }
+
+    #[test]
+    fn older_header_versions() {
+        let v1 = DatasetHeaderV1 {
+            magic: DATASET_MAGIC,
+            version: 1,
+            canvas_width: 2,
+            canvas_height: 2,
+            datapoint_size: 14,
+            num_datapoints: 6,
+            metadata_offset: 64,
+            metadata_length: 100,
+            data_offset: 164,
+        };
+        let v2 = DatasetHeaderV2 {
+            magic: DATASET_MAGIC,
+            version: 3,
+            canvas_width: 2,
+            canvas_height: 2,
+            origin_x: -1,
+            origin_y: -1,
+            datapoint_size: 16,
+            num_datapoints: 6,
+            metadata_offset: 72,
+            metadata_length: 100,
+            data_offset: 176,
+        };
+        let v4 = DatasetHeaderV4 {
+            header: DatasetHeaderV2 { version: 4, datapoint_size: COLUMNS_DATAPOINT_SIZE, ..v2 },
+            layout: DatasetLayout::Columns,
+        };
+
+        let header = DatasetHeader::from_bytes(&bincode::serialize(&v1).unwrap()).unwrap();
+        assert_eq!((header.version, header.origin_x, header.origin_y, header.layout), (1, 0, 0, DatasetLayout::Records));
+        assert!(!header.has_edit_log());
+
+        let header = DatasetHeader::from_bytes(&bincode::serialize(&v2).unwrap()).unwrap();
+        assert_eq!((header.version, header.origin_x, header.origin_y, header.layout), (3, -1, -1, DatasetLayout::Records));
+        assert!(!header.has_edit_log());
+
+        let header = DatasetHeader::from_bytes(&bincode::serialize(&v4).unwrap()).unwrap();
+        assert_eq!((header.version, header.origin_x, header.origin_y, header.layout), (4, -1, -1, DatasetLayout::Columns));
+        assert!(!header.has_edit_log());
+    }
+
+    #[test]
+    fn header_parse_errors() {
+        assert!(matches!(DatasetHeader::from_bytes(&DATASET_MAGIC), Err(DatasetFormatError::Truncated { section: "header", .. })));
+
+        let bytes = header(DatasetLayout::Records).to_bytes();
+        assert!(matches!(DatasetHeader::from_bytes(&bytes[..20]), Err(DatasetFormatError::Decode(_))));
+        assert!(matches!(SerializedDataset::new(&temp_file_path("missing")), Err(DatasetFormatError::Io(_))));
+    }
+
+    #[test]
+    fn validate_accepts_written_headers() {
+        for layout in [DatasetLayout::Records, DatasetLayout::Columns] {
+            let header = header(layout);
+            assert!(header.has_edit_log());
+            header.validate(header.file_length()).unwrap();
+        }
+    }
+
+    #[test]
+    fn validate_errors() {
+        let valid = header(DatasetLayout::Records);
+        let file_length = valid.file_length();
+
+        let newer = DatasetHeader { version: DATASET_FORMAT_VERSION + 1, ..valid };
+        assert!(matches!(newer.validate(file_length), Err(DatasetFormatError::UnsupportedVersion(version)) if version == DATASET_FORMAT_VERSION + 1));
+
+        let invalid = [
+            DatasetHeader { canvas_width: 0, ..valid },
+            DatasetHeader { datapoint_size: 14, ..valid },
+            DatasetHeader { data_offset: valid.data_offset + 1, ..valid },
+            DatasetHeader { metadata_length: valid.data_offset, ..valid },
+            DatasetHeader { metadata_offset: u64::MAX, ..valid },
+            DatasetHeader { num_datapoints: u64::MAX, ..valid },
+            DatasetHeader { edit_log_offset: valid.data_offset, ..valid },
+            DatasetHeader { edit_log_length: valid.num_datapoints + 1, ..valid },
+        ];
+        for header in invalid {
+            assert!(matches!(header.validate(file_length), Err(DatasetFormatError::InvalidHeader(_))), "{:?}", header);
+        }
+
+        let data_end = valid.data_end().unwrap();
+        assert!(matches!(valid.validate(data_end - 1), Err(DatasetFormatError::Truncated { section: "data", .. })));
+        assert!(matches!(valid.validate(file_length - 1), Err(DatasetFormatError::Truncated { section: "edit log", .. })));
+    }
+
+    #[test]
+    fn compressed_records_are_not_addressable() {
+        let header = header(DatasetLayout::Compressed);
+        let mut file = vec![0; 1024];
+        assert!(matches!(header.read_record(&file, 0), Err(DatasetFormatError::UnsupportedLayout(DatasetLayout::Compressed))));
+        assert!(matches!(header.write_record(&mut file, 0, &DatasetRecord::default()), Err(DatasetFormatError::UnsupportedLayout(DatasetLayout::Compressed))));
+    }
+
+    #[test]
+    fn legacy_files_without_header() {
+        let edit = |timestamp: u64, color: PixelColor| RPlaceDatasetDatapoint { timestamp, user_id: 1, color, is_mod: false };
+        let datapoints = vec![
+            RPlaceDatasetDatapoint::start(), edit(10, PixelColor::Red),
+            RPlaceDatasetDatapoint::start(),
+            RPlaceDatasetDatapoint::start(),
+            RPlaceDatasetDatapoint::start(), edit(20, PixelColor::Blue),
+        ];
+        let metadata = PrecompressedDatasetMetadata::new_with_history_lengths(CanvasDimensions::new(2, 2), 0, 20, RPlaceDatasetDatapoint::compressed_size() as u32, &[2, 1, 1, 2]);
+        let bytes = bincode::serialize(&(metadata, datapoints)).unwrap();
+        assert!(!DatasetHeader::is_present(&bytes));
+
+        let file_path = temp_file_path("legacy");
+        let truncated_file_path = temp_file_path("legacy-truncated");
+        std::fs::write(&file_path, &bytes).unwrap();
+        std::fs::write(&truncated_file_path, &bytes[..bytes.len() - 1]).unwrap();
+        let dataset = SerializedDataset::new(&file_path);
+        let truncated = SerializedDataset::new(&truncated_file_path);
+        std::fs::remove_file(&file_path).unwrap();
+        std::fs::remove_file(&truncated_file_path).unwrap();
+
+        let dataset = dataset.unwrap();
+        assert_eq!(dataset.header.version, LEGACY_FORMAT_VERSION);
+        assert_eq!(dataset.num_datapoints(), 6);
+        assert_eq!(dataset.edit_log_len(), 0);
+        assert_eq!(dataset.color_at(0, 0, 9), PixelColor::White);
+        assert_eq!(dataset.color_at(0, 0, 10), PixelColor::Red);
+        assert_eq!(dataset.pixel_history(1, 0).len(), 1);
+        assert_eq!(dataset.color_at(1, 1, 20), PixelColor::Blue);
+        assert!(matches!(truncated, Err(DatasetFormatError::Truncated { .. })));
+    }
+}
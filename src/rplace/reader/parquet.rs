@@ -1,9 +1,22 @@
-use parquet::{file::reader::{SerializedFileReader}, record::{Field, Row, reader::RowIter}};
-use std::{fs::File, fmt};
+use parquet::column::reader::{ColumnReader, ColumnReaderImpl};
+use parquet::data_type::DataType;
+use parquet::errors::ParquetError;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver};
+use std::{fs::File, fmt, thread};
+use crate::rplace::error::RPlaceError;
 
 // obtained parquest file from the following article: https://medium.com/@deephavendatalabs/the-r-place-dataset-bf4b0d70ce72
 // Download URL: https://deephaven.io/wp-content/2022_place_deephaven.parquet
 
+// batches every row group reader thread decodes ahead of the batch that is being read
+const PARALLEL_BATCHES_AHEAD: usize = 2;
+
+// x2 and y2 hold this value when the edit is a single pixel rather than a moderator rectangle
+pub const NULL_COORDINATE: i16 = i16::MIN;
+
 #[derive(Copy, Clone, Debug, Default)]
 pub struct RPlaceParquetDatapoint {
     pub timestamp: i64,
@@ -15,88 +28,449 @@ pub struct RPlaceParquetDatapoint {
     pub y2: i16,
 }
 
+impl RPlaceParquetDatapoint {
+    // columns that are not read keep these values
+    fn empty() -> RPlaceParquetDatapoint {
+        RPlaceParquetDatapoint {
+            x2: NULL_COORDINATE,
+            y2: NULL_COORDINATE,
+            ..Default::default()
+        }
+    }
+}
+
+/// A column of the deephaven parquet dataset.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RPlaceParquetColumn {
+    Timestamp,
+    UserId,
+    Rgb,
+    X1,
+    Y1,
+    X2,
+    Y2,
+}
+
+impl RPlaceParquetColumn {
+    pub const ALL: [RPlaceParquetColumn; 7] = [
+        RPlaceParquetColumn::Timestamp,
+        RPlaceParquetColumn::UserId,
+        RPlaceParquetColumn::Rgb,
+        RPlaceParquetColumn::X1,
+        RPlaceParquetColumn::Y1,
+        RPlaceParquetColumn::X2,
+        RPlaceParquetColumn::Y2,
+    ];
+
+    /// Name of the column in the parquet schema.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RPlaceParquetColumn::Timestamp => "timestamp",
+            RPlaceParquetColumn::UserId => "user_id",
+            RPlaceParquetColumn::Rgb => "rgb",
+            RPlaceParquetColumn::X1 => "x1",
+            RPlaceParquetColumn::Y1 => "y1",
+            RPlaceParquetColumn::X2 => "x2",
+            RPlaceParquetColumn::Y2 => "y2",
+        }
+    }
+
+    // nulls are passed in as NULL_COORDINATE for the int32 columns and i64::MIN for the timestamp
+    fn set(&self, datapoint: &mut RPlaceParquetDatapoint, value: i64) {
+        match self {
+            RPlaceParquetColumn::Timestamp => datapoint.timestamp = value,
+            RPlaceParquetColumn::UserId => datapoint.user_id = value as i32,
+            RPlaceParquetColumn::Rgb => datapoint.rgb = value as u64,
+            RPlaceParquetColumn::X1 => datapoint.x1 = value as i16,
+            RPlaceParquetColumn::Y1 => datapoint.y1 = value as i16,
+            RPlaceParquetColumn::X2 => datapoint.x2 = value as i16,
+            RPlaceParquetColumn::Y2 => datapoint.y2 = value as i16,
+        }
+    }
+}
+
+/// Reads the dataset column by column. Every row group is decoded into typed arrays in batches of
+/// batch_size rows, so no per row allocations or column name lookups are needed.
 pub struct RPlaceParquetDataReader {
     file_path: String,
     reader: SerializedFileReader<File>,
+    columns: Vec<RPlaceParquetColumn>,
+    batch_size: usize,
+
+    // threads that decode row groups when iterating over the datapoints
+    num_threads: usize,
 }
 
 impl fmt::Debug for RPlaceParquetDataReader {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("RPlaceParquetDataIterator")
+        f.debug_struct("RPlaceParquetDataReader")
          .field("file_path", &self.file_path)
+         .field("columns", &self.columns)
+         .field("batch_size", &self.batch_size)
+         .field("num_threads", &self.num_threads)
          .finish()
     }
 }
 
 impl RPlaceParquetDataReader {
-    pub fn new(file_path: &str) -> Option<RPlaceParquetDataReader> {
-        match File::open(file_path) {
-            Ok(file) => {
-                if let Ok(reader) = SerializedFileReader::new(file) {
-                    return Some(RPlaceParquetDataReader { 
-                        file_path: file_path.to_string(), 
-                        reader 
-                    });
+    /// Opens the parquet file at `file_path`, decoding all columns on a single thread.
+    pub fn new(file_path: &str) -> Result<RPlaceParquetDataReader, RPlaceError> {
+        let file = File::open(file_path)?;
+        let reader = SerializedFileReader::new(file)?;
+        Ok(RPlaceParquetDataReader { 
+            file_path: file_path.to_string(), 
+            reader,
+            columns: RPlaceParquetColumn::ALL.to_vec(),
+            batch_size: 64 * 1024,
+            num_threads: 1,
+        })
+    }
+
+    /// Only the given columns are decoded, the others keep the values of RPlaceParquetDatapoint::empty.
+    pub fn with_columns(mut self, columns: &[RPlaceParquetColumn]) -> RPlaceParquetDataReader {
+        self.columns = columns.to_vec();
+        self
+    }
+
+    /// Number of rows decoded per batch.
+    pub fn with_batch_size(mut self, batch_size: usize) -> RPlaceParquetDataReader {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Number of threads used when the reader is iterated as datapoints, see `parallel_batches`.
+    pub fn with_threads(mut self, num_threads: usize) -> RPlaceParquetDataReader {
+        self.num_threads = num_threads.max(1);
+        self
+    }
+
+    /// Number of row groups in the file.
+    pub fn num_row_groups(&self) -> usize {
+        self.reader.num_row_groups()
+    }
+
+    /// Number of rows in the file.
+    pub fn num_rows(&self) -> u64 {
+        self.reader.metadata().file_metadata().num_rows() as u64
+    }
+
+    /// Opens the selected columns of a single row group.
+    pub fn open_row_group(&self, row_group_idx: usize) -> Result<RPlaceParquetRowGroupReader, RPlaceError> {
+        let row_group = self.reader.get_row_group(row_group_idx)?;
+        let schema = self.reader.metadata().file_metadata().schema_descr();
+
+        let mut columns = Vec::with_capacity(self.columns.len());
+        for column in self.columns.iter() {
+            let column_idx = match schema.columns().iter().position(|descr| descr.name() == column.name()) {
+                Some(column_idx) => column_idx,
+                None => return Err(ParquetError::General(format!("{} does not have a {} column", self.file_path, column.name())).into()),
+            };
+
+            let max_def_level = schema.column(column_idx).max_def_level();
+            columns.push((*column, max_def_level, row_group.get_column_reader(column_idx)?));
+        }
+
+        Ok(RPlaceParquetRowGroupReader {
+            columns,
+            remaining_rows: row_group.metadata().num_rows() as usize,
+            batch_size: self.batch_size,
+        })
+    }
+
+    /// The batches of every row group in order, decoded on the calling thread.
+    pub fn batches(self) -> RPlaceParquetBatchIterator {
+        let num_row_groups = self.num_row_groups();
+        self.row_group_batches(0..num_row_groups)
+    }
+
+    /// The batches of the row groups in `row_groups` in order, decoded on the calling thread.
+    pub fn row_group_batches(self, row_groups: Range<usize>) -> RPlaceParquetBatchIterator {
+        RPlaceParquetBatchIterator {
+            reader: self,
+            row_groups,
+            row_group: None,
+        }
+    }
+
+    /// Decodes the row groups on num_threads background threads. Every thread reads whole row groups
+    /// and hands their batches over through its own channel, so batches are still yielded in row
+    /// group order and the result is the same as for batches().
+    pub fn parallel_batches(self, num_threads: usize) -> RPlaceParquetParallelBatchIterator {
+        let num_row_groups = self.num_row_groups();
+        let num_threads = num_threads.clamp(1, num_row_groups.max(1));
+        let reader = Arc::new(self);
+
+        let receivers = (0..num_threads).map(|thread_idx| {
+            let (tx, rx) = mpsc::sync_channel(PARALLEL_BATCHES_AHEAD);
+            let reader = Arc::clone(&reader);
+            thread::spawn(move || {
+                for row_group_idx in (thread_idx..num_row_groups).step_by(num_threads) {
+                    let mut row_group = match reader.open_row_group(row_group_idx) {
+                        Ok(row_group) => row_group,
+                        Err(e) => {
+                            let _ = tx.send(Err(e));
+                            return;
+                        },
+                    };
+
+                    // Ok(None) marks the end of the row group. Sending fails once the iterator is dropped
+                    loop {
+                        let batch = row_group.next_batch();
+                        let is_batch = matches!(batch, Ok(Some(_)));
+                        let is_error = batch.is_err();
+                        if tx.send(batch).is_err() || is_error {
+                            return;
+                        }
+                        if !is_batch {
+                            break;
+                        }
+                    }
                 }
-            },
-            _ => return None,
+            });
+            rx
+        }).collect();
+
+        RPlaceParquetParallelBatchIterator {
+            receivers,
+            row_groups: 0..num_row_groups,
+            row_group: None,
+        }
+    }
+}
+
+pub struct RPlaceParquetRowGroupReader {
+    // column, max definition level and the reader of the column chunk
+    columns: Vec<(RPlaceParquetColumn, i16, ColumnReader)>,
+    remaining_rows: usize,
+    batch_size: usize,
+}
+
+impl fmt::Debug for RPlaceParquetRowGroupReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RPlaceParquetRowGroupReader")
+         .field("remaining_rows", &self.remaining_rows)
+         .finish()
+    }
+}
+
+impl RPlaceParquetRowGroupReader {
+    pub fn next_batch(&mut self) -> Result<Option<Vec<RPlaceParquetDatapoint>>, RPlaceError> {
+        if self.remaining_rows == 0 {
+            return Ok(None);
+        }
+
+        let num_rows = self.batch_size.min(self.remaining_rows);
+        let mut batch = vec![RPlaceParquetDatapoint::empty(); num_rows];
+        for (column, max_def_level, column_reader) in self.columns.iter_mut() {
+            match column_reader {
+                ColumnReader::Int64ColumnReader(reader) => {
+                    let values = read_column_values(reader, num_rows, *max_def_level, i64::MIN)?;
+                    for (datapoint, value) in batch.iter_mut().zip(values) {
+                        column.set(datapoint, value);
+                    }
+                },
+                ColumnReader::Int32ColumnReader(reader) => {
+                    let values = read_column_values(reader, num_rows, *max_def_level, NULL_COORDINATE as i32)?;
+                    for (datapoint, value) in batch.iter_mut().zip(values) {
+                        column.set(datapoint, value as i64);
+                    }
+                },
+                _ => return Err(ParquetError::General(format!("column {} is not an integer column", column.name())).into()),
+            }
+        }
+
+        self.remaining_rows -= num_rows;
+        Ok(Some(batch))
+    }
+}
+
+// Reads num_rows values of a column. Nulls (rows whose definition level is below the max) are 
+// replaced by null_value so that the result lines up with the rows of the other columns
+fn read_column_values<T: DataType>(reader: &mut ColumnReaderImpl<T>, num_rows: usize, max_def_level: i16, null_value: T::T) -> parquet::errors::Result<Vec<T::T>> {
+    let mut values = vec![T::T::default(); num_rows];
+    let mut def_levels = vec![0i16; if max_def_level > 0 { num_rows } else { 0 }];
+    let mut rows_read = 0;
+    let mut values_read = 0;
+
+    while rows_read < num_rows {
+        let (batch_values_read, batch_rows_read) = if max_def_level > 0 {
+            let (batch_values_read, levels_read) = reader.read_batch(num_rows - rows_read, Some(&mut def_levels[rows_read..]), None, &mut values[values_read..])?;
+            (batch_values_read, levels_read)
+        } else {
+            let (batch_values_read, _) = reader.read_batch(num_rows - rows_read, None, None, &mut values[values_read..])?;
+            (batch_values_read, batch_values_read)
         };
 
-        return None;
+        if batch_rows_read == 0 {
+            return Err(ParquetError::EOF(format!("column ended after {} of {} rows", rows_read, num_rows)));
+        }
+
+        rows_read += batch_rows_read;
+        values_read += batch_values_read;
     }
+
+    if values_read == num_rows {
+        return Ok(values);
+    }
+
+    // the values of the non null rows are packed at the front, spread them out
+    let mut values_iter = values.into_iter();
+    let spread = def_levels.iter().map(|def_level| {
+        if *def_level == max_def_level {
+            values_iter.next().unwrap_or(null_value.clone())
+        } else {
+            null_value.clone()
+        }
+    }).collect();
+    Ok(spread)
 }
 
-pub struct RPlaceParquetDataIterator<'a> {
-    iter: RowIter<'a>,
+// Yields the batches of a range of row groups in order. Iteration ends after the first error
+pub struct RPlaceParquetBatchIterator {
+    reader: RPlaceParquetDataReader,
+    row_groups: Range<usize>,
+    row_group: Option<RPlaceParquetRowGroupReader>,
 }
 
-impl<'a> fmt::Debug for RPlaceParquetDataIterator<'a> {
+impl fmt::Debug for RPlaceParquetBatchIterator {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("RPlaceParquetDataIterator")
+        f.debug_struct("RPlaceParquetBatchIterator")
+         .field("reader", &self.reader)
+         .field("row_groups", &self.row_groups)
          .finish()
     }
 }
 
-impl Iterator for RPlaceParquetDataIterator<'_> {
-    type Item = RPlaceParquetDatapoint;
+impl Iterator for RPlaceParquetBatchIterator {
+    type Item = Result<Vec<RPlaceParquetDatapoint>, RPlaceError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.iter.next() {
-            Some(row) => Some(self.convert_row(row)),
-            _ => None
+        loop {
+            if let Some(row_group) = self.row_group.as_mut() {
+                match row_group.next_batch() {
+                    Ok(Some(batch)) => return Some(Ok(batch)),
+                    Ok(None) => self.row_group = None,
+                    Err(e) => {
+                        self.row_group = None;
+                        self.row_groups = 0..0;
+                        return Some(Err(e));
+                    },
+                }
+            }
+
+            let row_group_idx = self.row_groups.next()?;
+            match self.reader.open_row_group(row_group_idx) {
+                Ok(row_group) => self.row_group = Some(row_group),
+                Err(e) => {
+                    self.row_groups = 0..0;
+                    return Some(Err(e));
+                },
+            }
         }
     }
 }
 
-impl IntoIterator for RPlaceParquetDataReader {
-    type Item = RPlaceParquetDatapoint;
+type RowGroupBatch = Result<Option<Vec<RPlaceParquetDatapoint>>, RPlaceError>;
 
-    type IntoIter = RPlaceParquetDataIterator<'static>;
+// Yields the batches decoded by the threads of RPlaceParquetDataReader::parallel_batches in row
+// group order. Row group i is read from the channel of thread i % threads. Iteration ends after
+// the first error
+pub struct RPlaceParquetParallelBatchIterator {
+    receivers: Vec<Receiver<RowGroupBatch>>,
+    row_groups: Range<usize>,
+    row_group: Option<usize>,
+}
 
-    fn into_iter(self) -> Self::IntoIter {
-        RPlaceParquetDataIterator {
-            iter: self.reader.into_iter()
+impl fmt::Debug for RPlaceParquetParallelBatchIterator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RPlaceParquetParallelBatchIterator")
+         .field("threads", &self.receivers.len())
+         .field("row_groups", &self.row_groups)
+         .field("row_group", &self.row_group)
+         .finish()
+    }
+}
+
+impl Iterator for RPlaceParquetParallelBatchIterator {
+    type Item = Result<Vec<RPlaceParquetDatapoint>, RPlaceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let row_group_idx = match self.row_group {
+                Some(row_group_idx) => row_group_idx,
+                None => {
+                    let row_group_idx = self.row_groups.next()?;
+                    self.row_group = Some(row_group_idx);
+                    row_group_idx
+                },
+            };
+
+            let error = match self.receivers[row_group_idx % self.receivers.len()].recv() {
+                Ok(Ok(Some(batch))) => return Some(Ok(batch)),
+                Ok(Ok(None)) => {
+                    self.row_group = None;
+                    continue;
+                },
+                Ok(Err(e)) => e,
+                Err(_) => ParquetError::General("row group reader thread panicked".to_string()).into(),
+            };
+
+            self.row_group = None;
+            self.row_groups = 0..0;
+            return Some(Err(error));
         }
     }
 }
 
-impl<'a> RPlaceParquetDataIterator<'a> {
-    fn convert_row(&self, row: Row) -> RPlaceParquetDatapoint {
-        let mut datapoint: RPlaceParquetDatapoint = RPlaceParquetDatapoint::default();
-        for (string, field) in row.get_column_iter() {
-            match (string.as_str(), field) {
-                ("timestamp", Field::Long(t)) => datapoint.timestamp = *t,
-                ("user_id", Field::Int(u)) => datapoint.user_id = *u,
-                ("rgb", Field::Int(rgb)) => datapoint.rgb = (*rgb) as u64,
-                ("x1", Field::Short(s)) => datapoint.x1 = *s,
-                ("y1", Field::Short(s)) => datapoint.y1 = *s,
-                ("x2", Field::Short(s)) => datapoint.x2 = *s,
-                ("y2", Field::Short(s)) => datapoint.y2 = *s,
-                _ => (),
+// Yields the datapoints of every batch one at a time
+pub struct RPlaceParquetDataIterator {
+    batches: Box<dyn Iterator<Item = Result<Vec<RPlaceParquetDatapoint>, RPlaceError>>>,
+    batch: std::vec::IntoIter<RPlaceParquetDatapoint>,
+}
+
+impl fmt::Debug for RPlaceParquetDataIterator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RPlaceParquetDataIterator")
+         .field("remaining_batch_rows", &self.batch.len())
+         .finish()
+    }
+}
+
+impl Iterator for RPlaceParquetDataIterator {
+    type Item = Result<RPlaceParquetDatapoint, RPlaceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(datapoint) = self.batch.next() {
+                return Some(Ok(datapoint));
+            }
+
+            match self.batches.next()? {
+                Ok(batch) => self.batch = batch.into_iter(),
+                Err(e) => return Some(Err(e)),
             }
         }
-    
-        return datapoint;
+    }
+}
+
+impl IntoIterator for RPlaceParquetDataReader {
+    type Item = Result<RPlaceParquetDatapoint, RPlaceError>;
+
+    type IntoIter = RPlaceParquetDataIterator;
+
+    // decodes on background threads when the reader was created with more than one thread
+    fn into_iter(self) -> Self::IntoIter {
+        let batches: Box<dyn Iterator<Item = Result<Vec<RPlaceParquetDatapoint>, RPlaceError>>> = if self.num_threads > 1 {
+            let num_threads = self.num_threads;
+            Box::new(self.parallel_batches(num_threads))
+        } else {
+            Box::new(self.batches())
+        };
+
+        RPlaceParquetDataIterator {
+            batches,
+            batch: Vec::new().into_iter(),
+        }
     }
 }
 
@@ -0,0 +1,119 @@
+use std::time::Instant;
+
+use image::imageops::{self, FilterType};
+use image::RgbImage;
+
+use super::canvas::Canvas;
+use super::error::RPlaceError;
+use super::framebuffer::{dimmed_rgb, FrameBuffer};
+use super::tiles::color_rgb;
+use super::user_index::UserEdit;
+
+// Canvas pixels x1..x2, y1..y2 that get rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CanvasRegion {
+    pub x1: u32,
+    pub y1: u32,
+    pub x2: u32,
+    pub y2: u32,
+}
+
+impl CanvasRegion {
+    pub fn new_with_canvas(canvas: &Canvas) -> CanvasRegion {
+        CanvasRegion {
+            x1: 0,
+            y1: 0,
+            x2: canvas.width(),
+            y2: canvas.height(),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.x2 - self.x1
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y2 - self.y1
+    }
+
+    pub fn is_within(&self, canvas: &Canvas) -> bool {
+        self.x1 < self.x2 && self.y1 < self.y2 && self.x2 <= canvas.width() && self.y2 <= canvas.height()
+    }
+}
+
+pub fn scale_image(image: RgbImage, scale: u32) -> RgbImage {
+    if scale <= 1 {
+        return image;
+    }
+
+    imageops::resize(&image, image.width() * scale, image.height() * scale, FilterType::Nearest)
+}
+
+// Renders the canvas without a window. The canvas and frame buffer are kept between renders so
+// consecutive timestamps only have to update the pixels that changed
+#[derive(Debug)]
+pub struct HeadlessRenderer {
+    pub canvas: Canvas,
+    frame_buffer: FrameBuffer,
+}
+
+impl HeadlessRenderer {
+    pub fn new_with_file_path(file_path: &str) -> Result<HeadlessRenderer, RPlaceError> {
+        let canvas = Canvas::new_with_file_path(file_path)?;
+        Ok(HeadlessRenderer::new_with_canvas(canvas))
+    }
+
+    pub fn new_with_canvas(canvas: Canvas) -> HeadlessRenderer {
+        let frame_buffer = FrameBuffer::new(&canvas);
+        HeadlessRenderer {
+            canvas,
+            frame_buffer,
+        }
+    }
+
+    // same bounds as the viewer, timestamps outside of the dataset show its first or last state
+    pub fn clamp_timestamp(&self, timestamp: u64) -> u64 {
+        timestamp.clamp(self.canvas.min_timestamp, self.canvas.max_timestamp)
+    }
+
+    // Moves the region to the timestamp and returns it with every canvas pixel drawn as a
+    // scale x scale square
+    pub fn render(&mut self, timestamp: u64, region: CanvasRegion, scale: u32) -> RgbImage {
+        let start_time = Instant::now();
+        let image = scale_image(self.render_region(timestamp, region), scale);
+        println!("Rendered x={}..{} y={}..{} at t={} | scale {} | duration {:?}",
+            region.x1, region.x2, region.y1, region.y2, timestamp, scale, start_time.elapsed());
+        image
+    }
+
+    // Dims the canvas at the timestamp and draws the edits placed at or before it on top, later
+    // edits over earlier ones. The edits have to be in timestamp order
+    pub fn render_edits_overlay(&mut self, edits: &[UserEdit], timestamp: u64, region: CanvasRegion, scale: u32) -> RgbImage {
+        let start_time = Instant::now();
+        let mut image = self.render_region(timestamp, region);
+        for pixel in image.pixels_mut() {
+            pixel.0 = dimmed_rgb(pixel.0);
+        }
+
+        let mut num_drawn = 0;
+        for edit in edits.iter().take_while(|edit| edit.timestamp <= timestamp) {
+            if edit.x < region.x1 || edit.x >= region.x2 || edit.y < region.y1 || edit.y >= region.y2 {
+                continue;
+            }
+
+            image.put_pixel(edit.x - region.x1, edit.y - region.y1, image::Rgb(color_rgb(edit.color)));
+            num_drawn += 1;
+        }
+
+        println!("Rendered overlay of {} edits over x={}..{} y={}..{} at t={} | duration {:?}",
+            num_drawn, region.x1, region.x2, region.y1, region.y2, timestamp, start_time.elapsed());
+        scale_image(image, scale)
+    }
+
+    fn render_region(&mut self, timestamp: u64, region: CanvasRegion) -> RgbImage {
+        let timestamp = self.clamp_timestamp(timestamp);
+        let (x1, x2, y1, y2) = (region.x1 as usize, region.x2 as usize, region.y1 as usize, region.y2 as usize);
+        self.canvas.adjust_timestamp(timestamp as i64, x1, x2, y1, y2);
+        self.frame_buffer.region_image(&mut self.canvas, x1, x2, y1, y2)
+    }
+}
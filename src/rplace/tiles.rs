@@ -0,0 +1,146 @@
+use super::canvas::CanvasPixel;
+use super::pixel::PixelColor;
+
+// levels stop once both sides are at most this many tiles
+const MIN_LEVEL_SIZE: u32 = 16;
+
+// Downsampled copies of the canvas state for drawing zoomed out views. Level k has one tile per
+// 2^k x 2^k block of canvas pixels, colored with the average of the block. Level 0 is the canvas
+// itself and is not stored here.
+#[derive(Debug)]
+pub struct TilePyramid {
+    width: u32,
+    height: u32,
+
+    // levels[0] is level 1 (2x2 blocks)
+    levels: Vec<TileLevel>,
+}
+
+#[derive(Debug)]
+pub struct TileLevel {
+    pub scale: u32,
+    pub width: u32,
+    pub height: u32,
+
+    // rgb bytes, row by row
+    rgb: Vec<u8>,
+}
+
+impl TileLevel {
+    fn new(scale: u32, width: u32, height: u32) -> TileLevel {
+        TileLevel {
+            scale,
+            width,
+            height,
+            rgb: vec![0; width as usize * height as usize * 3],
+        }
+    }
+
+    pub fn rgb(&self, x: u32, y: u32) -> [u8; 3] {
+        let idx = (y as usize * self.width as usize + x as usize) * 3;
+        [self.rgb[idx], self.rgb[idx + 1], self.rgb[idx + 2]]
+    }
+
+    fn set_rgb(&mut self, x: u32, y: u32, rgb: [u8; 3]) {
+        let idx = (y as usize * self.width as usize + x as usize) * 3;
+        self.rgb[idx..idx + 3].copy_from_slice(&rgb);
+    }
+
+    // range of tiles that cover the canvas pixels start..end
+    pub fn tile_range(&self, start: usize, end: usize) -> (u32, u32) {
+        let scale = self.scale as usize;
+        ((start / scale) as u32, end.div_ceil(scale) as u32)
+    }
+}
+
+pub fn color_rgb(color: PixelColor) -> [u8; 3] {
+    let color: u32 = color.into();
+    [(color >> 16 & 0xff) as u8, (color >> 8 & 0xff) as u8, (color & 0xff) as u8]
+}
+
+pub fn pixel_rgb(pixel: &CanvasPixel) -> [u8; 3] {
+    color_rgb(pixel.color)
+}
+
+// averages the children of a tile, children outside of the level below are left out
+fn average<F>(x: u32, y: u32, width: u32, height: u32, child: F) -> [u8; 3] where F: Fn(u32, u32) -> [u8; 3] {
+    let mut sum = [0u32; 3];
+    let mut count = 0;
+    for child_y in 2 * y..(2 * y + 2).min(height) {
+        for child_x in 2 * x..(2 * x + 2).min(width) {
+            let rgb = child(child_x, child_y);
+            for (total, value) in sum.iter_mut().zip(rgb) {
+                *total += value as u32;
+            }
+            count += 1;
+        }
+    }
+
+    sum.map(|total| (total / count) as u8)
+}
+
+impl TilePyramid {
+    pub fn new(width: u32, height: u32) -> TilePyramid {
+        let mut levels = Vec::new();
+        let (mut level_width, mut level_height) = (width, height);
+        let mut scale = 1;
+        while level_width > MIN_LEVEL_SIZE || level_height > MIN_LEVEL_SIZE {
+            level_width = level_width.div_ceil(2);
+            level_height = level_height.div_ceil(2);
+            scale *= 2;
+            levels.push(TileLevel::new(scale, level_width, level_height));
+        }
+
+        TilePyramid {
+            width,
+            height,
+            levels,
+        }
+    }
+
+    // number of levels including the canvas
+    pub fn num_levels(&self) -> usize {
+        self.levels.len() + 1
+    }
+
+    pub fn level(&self, level: usize) -> &TileLevel {
+        &self.levels[level - 1]
+    }
+
+    // Picks the coarsest level whose tiles are still at least one display pixel wide
+    pub fn level_for_pixel_size(&self, pixel_size: f32) -> usize {
+        if pixel_size >= 1.0 {
+            return 0;
+        }
+
+        let level = (1.0 / pixel_size).log2().floor() as usize;
+        level.min(self.num_levels() - 1)
+    }
+
+    // Recomputes the tiles that cover the canvas pixels x1..x2, y1..y2 on every level
+    pub fn update(&mut self, pixels: &[Vec<CanvasPixel>], x1: usize, x2: usize, y1: usize, y2: usize) {
+        if x1 >= x2 || y1 >= y2 || self.levels.is_empty() {
+            return;
+        }
+
+        let (mut x1, mut x2, mut y1, mut y2) = (x1 as u32, x2 as u32, y1 as u32, y2 as u32);
+        for level in 0..self.levels.len() {
+            x1 /= 2;
+            y1 /= 2;
+            x2 = x2.div_ceil(2);
+            y2 = y2.div_ceil(2);
+
+            let (below, rest) = self.levels.split_at_mut(level);
+            let tiles = &mut rest[0];
+            for y in y1..y2 {
+                for x in x1..x2 {
+                    let rgb = match below.last() {
+                        None => average(x, y, self.width, self.height, |child_x, child_y| pixel_rgb(&pixels[child_y as usize][child_x as usize])),
+                        Some(below) => average(x, y, below.width, below.height, |child_x, child_y| below.rgb(child_x, child_y)),
+                    };
+                    tiles.set_rgb(x, y, rgb);
+                }
+            }
+        }
+    }
+}
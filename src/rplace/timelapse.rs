@@ -0,0 +1,109 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, DynamicImage, Frame, RgbImage};
+
+use super::error::RPlaceError;
+use super::render::{CanvasRegion, HeadlessRenderer};
+
+// gif quantization speed, the r/place palette always fits in a single gif palette
+const GIF_ENCODER_SPEED: i32 = 10;
+
+// How far the canvas moves between two frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameStep {
+    // nanoseconds of data per frame
+    Interval(u64),
+
+    // edits inside the region per frame
+    Edits(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Timelapse {
+    pub start_timestamp: u64,
+    pub end_timestamp: u64,
+    pub step: FrameStep,
+    pub region: CanvasRegion,
+    pub scale: u32,
+}
+
+// Where the frames of a timelapse are written
+pub enum TimelapseOutput {
+    Gif(GifEncoder<BufWriter<File>>, Delay),
+
+    // frame_00000.png, frame_00001.png, ... in a directory
+    PngSequence(PathBuf),
+}
+
+impl TimelapseOutput {
+    // A path ending in .gif writes an animated gif, anything else is a directory for png frames
+    pub fn new_with_path(path: &str, frame_delay_ms: u32) -> Result<TimelapseOutput, RPlaceError> {
+        if path.to_lowercase().ends_with(".gif") {
+            let file = BufWriter::new(File::create(path)?);
+            let mut encoder = GifEncoder::new_with_speed(file, GIF_ENCODER_SPEED);
+            encoder.set_repeat(Repeat::Infinite)?;
+            return Ok(TimelapseOutput::Gif(encoder, Delay::from_numer_denom_ms(frame_delay_ms, 1)));
+        }
+
+        fs::create_dir_all(path)?;
+        Ok(TimelapseOutput::PngSequence(Path::new(path).to_path_buf()))
+    }
+
+    pub fn write_frame(&mut self, frame_idx: usize, image: RgbImage) -> Result<(), RPlaceError> {
+        match self {
+            TimelapseOutput::Gif(encoder, delay) => {
+                let frame = Frame::from_parts(DynamicImage::ImageRgb8(image).into_rgba8(), 0, 0, *delay);
+                encoder.encode_frame(frame)?;
+            },
+            TimelapseOutput::PngSequence(directory) => {
+                image.save(directory.join(format!("frame_{:05}.png", frame_idx)))?;
+            },
+        }
+
+        Ok(())
+    }
+}
+
+impl HeadlessRenderer {
+    // Renders a frame at the start of the timelapse and then one frame per step until the end
+    // timestamp, or until there are no edits left in the region. Returns the number of frames
+    pub fn render_timelapse(&mut self, timelapse: &Timelapse, output: &mut TimelapseOutput) -> Result<usize, RPlaceError> {
+        let start_time = Instant::now();
+        let region = timelapse.region;
+        let (x1, x2, y1, y2) = (region.x1 as usize, region.x2 as usize, region.y1 as usize, region.y2 as usize);
+        let end_timestamp = self.clamp_timestamp(timelapse.end_timestamp);
+        let mut timestamp = self.clamp_timestamp(timelapse.start_timestamp);
+        let mut num_frames = 0;
+
+        loop {
+            let image = self.render(timestamp, region, timelapse.scale);
+            output.write_frame(num_frames, image)?;
+            num_frames += 1;
+
+            if timestamp >= end_timestamp {
+                break;
+            }
+
+            let next_timestamp = match timelapse.step {
+                FrameStep::Interval(interval) => timestamp.saturating_add(interval),
+                FrameStep::Edits(n) => {
+                    self.canvas.next_nth_pixel_change_low_mem(n, x1, x2, y1, y2);
+                    if self.canvas.timestamp <= timestamp {
+                        println!("No edits left in the region after t={}", timestamp);
+                        break;
+                    }
+                    self.canvas.timestamp
+                },
+            };
+            timestamp = next_timestamp.min(end_timestamp);
+        }
+
+        println!("Rendered timelapse with {} frames | t={}..{} | step {:?} | duration {:?}",
+            num_frames, timelapse.start_timestamp, end_timestamp, timelapse.step, start_time.elapsed());
+        Ok(num_frames)
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::mem::size_of;
+use std::time::Instant;
+
+use memmap::{Mmap, MmapMut};
+use serde::{Serialize, Deserialize};
+
+use super::error::RPlaceError;
+use super::pixel::PixelColor;
+use super::reader::custom::{DatasetFormatError, SerializedDataset};
+use super::users::NO_USER_ID;
+
+pub const USER_INDEX_MAGIC: [u8; 8] = *b"RPLACEUI";
+pub const USER_INDEX_FORMAT_VERSION: u32 = 1;
+
+// Inverted index from user id to the edits of that user, stored next to a dataset. The edits of
+// a user are indices into the edit log of the dataset, which is in timestamp order, so every
+// list is in timestamp order as well.
+//
+// header (bincode, fixed size)
+// offsets at offsets_offset, u64 * (num_users + 1), little endian
+//     - the edits of user u are entries offsets[u]..offsets[u + 1]
+// edits at edits_offset, edit log index u32 * dataset edit log length, little endian
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserIndexHeader {
+    pub magic: [u8; 8],
+    pub version: u32,
+
+    // used to detect a sidecar that was written for a different dataset
+    pub dataset_num_datapoints: u64,
+    pub num_edits: u64,
+
+    // largest user id + 1
+    pub num_users: u64,
+    pub offsets_offset: u64,
+    pub edits_offset: u64,
+}
+
+impl UserIndexHeader {
+    pub fn new(dataset: &SerializedDataset, num_users: u64) -> UserIndexHeader {
+        let mut header = UserIndexHeader {
+            magic: USER_INDEX_MAGIC,
+            version: USER_INDEX_FORMAT_VERSION,
+            dataset_num_datapoints: dataset.num_datapoints(),
+            num_edits: dataset.edit_log_len() as u64,
+            num_users,
+            offsets_offset: 0,
+            edits_offset: 0,
+        };
+        header.offsets_offset = header.to_bytes().len() as u64;
+        header.edits_offset = header.offsets_offset + (num_users + 1) * size_of::<u64>() as u64;
+        header
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    pub fn file_length(&self) -> u64 {
+        self.edits_offset + self.num_edits * size_of::<u32>() as u64
+    }
+
+    // checks the header against the dataset it is used with
+    pub fn validate(&self, dataset: &SerializedDataset, file_length: u64) -> Result<(), DatasetFormatError> {
+        if self.magic != USER_INDEX_MAGIC {
+            return Err(DatasetFormatError::InvalidHeader("user index file is missing the magic bytes".to_string()));
+        }
+
+        if self.version > USER_INDEX_FORMAT_VERSION {
+            return Err(DatasetFormatError::UnsupportedVersion(self.version));
+        }
+
+        if self.dataset_num_datapoints != dataset.num_datapoints() || self.num_edits != dataset.edit_log_len() as u64 {
+            return Err(DatasetFormatError::InvalidHeader("user index was written for a different dataset".to_string()));
+        }
+
+        if self.file_length() > file_length {
+            return Err(DatasetFormatError::Truncated { section: "user index", expected: self.file_length(), actual: file_length });
+        }
+
+        Ok(())
+    }
+}
+
+// A single edit of a user
+#[derive(Debug, Clone, Copy)]
+pub struct UserEdit {
+    pub timestamp: u64,
+    pub x: u32,
+    pub y: u32,
+    pub color: PixelColor,
+    pub is_mod: bool,
+}
+
+pub struct UserIndex {
+    mmap: Mmap,
+    pub header: UserIndexHeader,
+}
+
+impl fmt::Debug for UserIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UserIndex")
+         .field("header", &self.header)
+         .finish()
+    }
+}
+
+impl UserIndex {
+    pub fn open(file_path: &str, dataset: &SerializedDataset) -> Result<UserIndex, DatasetFormatError> {
+        let file = File::open(file_path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        let header: UserIndexHeader = bincode::deserialize(&mmap)?;
+        header.validate(dataset, mmap.len() as u64)?;
+
+        Ok(UserIndex {
+            mmap,
+            header,
+        })
+    }
+
+    fn offset(&self, user_id: u32) -> u64 {
+        let start = (self.header.offsets_offset + user_id as u64 * size_of::<u64>() as u64) as usize;
+        u64::from_le_bytes(self.mmap[start..start + 8].try_into().unwrap())
+    }
+
+    // range of the user's entries, empty for unknown users
+    fn entries(&self, user_id: u32) -> (u64, u64) {
+        if user_id as u64 >= self.header.num_users {
+            return (0, 0);
+        }
+
+        (self.offset(user_id), self.offset(user_id + 1))
+    }
+
+    // Indices into the edit log of the dataset of every edit of the user, in timestamp order
+    pub fn edit_indices(&self, user_id: u32) -> impl Iterator<Item = usize> + '_ {
+        let (start, end) = self.entries(user_id);
+        (start..end).map(move |entry| {
+            let position = (self.header.edits_offset + entry * size_of::<u32>() as u64) as usize;
+            u32::from_le_bytes(self.mmap[position..position + 4].try_into().unwrap()) as usize
+        })
+    }
+
+    // Every edit of the user in timestamp order. Entries that point past the edit log are skipped
+    pub fn edits<'a>(&'a self, dataset: &'a SerializedDataset, user_id: u32) -> impl Iterator<Item = UserEdit> + 'a {
+        let canvas_width = dataset.header.canvas_width;
+        self.edit_indices(user_id).filter_map(move |edit_idx| {
+            let edit = dataset.edit(edit_idx)?;
+            let (x, y) = edit.coordinate(canvas_width);
+            let datapoint = dataset.pixel_history(x, y).get(edit.history_index as usize);
+            Some(UserEdit {
+                timestamp: edit.timestamp,
+                x,
+                y,
+                color: datapoint.color,
+                is_mod: datapoint.is_mod,
+            })
+        })
+    }
+}
+
+// side table that is written next to a dataset
+pub fn user_index_file_path(dataset_file_path: &str) -> String {
+    format!("{}.user_index", dataset_file_path)
+}
+
+// user of an edit, read from the history of the edited pixel. edit_idx has to be below the edit log length
+fn edit_user_id(dataset: &SerializedDataset, edit_idx: usize) -> u32 {
+    let edit = dataset.edit(edit_idx).expect("edit index is within the edit log");
+    let (x, y) = edit.coordinate(dataset.header.canvas_width);
+    dataset.pixel_history(x, y).get(edit.history_index as usize).user_id
+}
+
+// Walks the edit log twice, once to count the edits of every user and once to append every edit
+// to the list of its user. The user of an edit is looked up in the pixel history every time, so
+// only one u64 per user id is held in memory while writing.
+pub fn write_user_index(dataset: &SerializedDataset, file_path: &str) -> Result<UserIndexHeader, RPlaceError> {
+    let start_time = Instant::now();
+    if !dataset.header.has_edit_log() {
+        return Err(DatasetFormatError::InvalidHeader("the user index needs a dataset with an edit log, convert it again".to_string()).into());
+    }
+
+    if dataset.edit_log_len() > u32::MAX as usize {
+        return Err(DatasetFormatError::InvalidHeader(format!("{} edits do not fit in 32 bit edit indices", dataset.edit_log_len())).into());
+    }
+
+    let mut counts: Vec<u64> = Vec::new();
+    for edit_idx in 0..dataset.edit_log_len() {
+        let user_id = edit_user_id(dataset, edit_idx) as usize;
+        if user_id >= counts.len() {
+            counts.resize(user_id + 1, 0);
+        }
+        counts[user_id] += 1;
+    }
+
+    let header = UserIndexHeader::new(dataset, counts.len() as u64);
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(file_path)?;
+    file.set_len(header.file_length())?;
+    let mut mmap = unsafe { MmapMut::map_mut(&file)? };
+    let header_bytes = header.to_bytes();
+    mmap[0..header_bytes.len()].copy_from_slice(&header_bytes);
+
+    let num_users = counts.iter().enumerate().filter(|(user_id, count)| *user_id as u32 != NO_USER_ID && **count > 0).count();
+
+    // the counts become the position of the next edit of every user
+    let mut cursors = counts;
+    let mut offset = 0u64;
+    for (user_id, cursor) in cursors.iter_mut().enumerate() {
+        let position = (header.offsets_offset + user_id as u64 * 8) as usize;
+        mmap[position..position + 8].copy_from_slice(&offset.to_le_bytes());
+        let count = *cursor;
+        *cursor = offset;
+        offset += count;
+    }
+    let position = (header.offsets_offset + cursors.len() as u64 * 8) as usize;
+    mmap[position..position + 8].copy_from_slice(&offset.to_le_bytes());
+
+    for edit_idx in 0..dataset.edit_log_len() {
+        let user_id = edit_user_id(dataset, edit_idx) as usize;
+        let position = (header.edits_offset + cursors[user_id] * 4) as usize;
+        mmap[position..position + 4].copy_from_slice(&(edit_idx as u32).to_le_bytes());
+        cursors[user_id] += 1;
+    }
+    mmap.flush()?;
+
+    println!("Wrote user index for {} users to {} | {} bytes | duration {:?}",
+        num_users, file_path, header.file_length(), start_time.elapsed());
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use speedy2d::dimen::Vec2;
+
+    use super::*;
+    use crate::rplace::data::{CanvasDimensions, RPlaceDatapoint};
+    use crate::rplace::writer::DatasetWriter;
+
+    fn datapoint(timestamp: u64, x: u32, y: u32, user_id: u32, color: PixelColor) -> RPlaceDatapoint {
+        RPlaceDatapoint {
+            timestamp,
+            user_id,
+            color,
+            coordinate: Vec2::new(x as f32, y as f32),
+            is_mod: false,
+        }
+    }
+
+    fn temp_file_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("rplace-user-index-{}-{}", std::process::id(), name)).to_string_lossy().to_string()
+    }
+
+    fn write_dataset(name: &str, datapoints: &[RPlaceDatapoint]) -> SerializedDataset {
+        let file_path = temp_file_path(name);
+        DatasetWriter::new(CanvasDimensions::new(3, 2))
+            .write(&file_path, || Ok(datapoints.iter().copied().map(Ok)))
+            .unwrap();
+        let dataset = SerializedDataset::new(&file_path).unwrap();
+        std::fs::remove_file(&file_path).unwrap();
+        dataset
+    }
+
+    #[test]
+    fn user_index_contents() {
+        let dataset = write_dataset("contents", &[
+            datapoint(6, 2, 1, 1, PixelColor::White),
+            datapoint(1, 0, 0, 2, PixelColor::Red),
+            datapoint(2, 1, 0, 1, PixelColor::Blue),
+            datapoint(3, 0, 0, 2, PixelColor::Green),
+            datapoint(4, 2, 1, 3, PixelColor::Yellow),
+            datapoint(5, 1, 0, 2, PixelColor::Black),
+        ]);
+        let file_path = temp_file_path("contents.user_index");
+        let header = write_user_index(&dataset, &file_path).unwrap();
+        let index = UserIndex::open(&file_path, &dataset);
+        std::fs::remove_file(&file_path).unwrap();
+        let index = index.unwrap();
+
+        assert_eq!(header, index.header);
+        assert_eq!(index.header.num_users, 4);
+        assert_eq!(index.header.num_edits, 6);
+
+        // edit log indices in timestamp order, the edit log starts at timestamp 1
+        assert_eq!(index.edit_indices(1).collect::<Vec<usize>>(), vec![1, 5]);
+        assert_eq!(index.edit_indices(2).collect::<Vec<usize>>(), vec![0, 2, 4]);
+        assert_eq!(index.edit_indices(3).collect::<Vec<usize>>(), vec![3]);
+        assert_eq!(index.edit_indices(NO_USER_ID).count(), 0);
+        assert_eq!(index.edit_indices(99).count(), 0);
+
+        let edits: Vec<(u64, u32, u32, PixelColor)> = index.edits(&dataset, 2).map(|edit| (edit.timestamp, edit.x, edit.y, edit.color)).collect();
+        assert_eq!(edits, vec![(1, 0, 0, PixelColor::Red), (3, 0, 0, PixelColor::Green), (5, 1, 0, PixelColor::Black)]);
+    }
+
+    #[test]
+    fn user_index_of_other_dataset() {
+        let dataset = write_dataset("dataset", &[datapoint(1, 0, 0, 1, PixelColor::Red), datapoint(2, 1, 0, 2, PixelColor::Blue)]);
+        let other = write_dataset("other", &[datapoint(1, 0, 0, 1, PixelColor::Red)]);
+        let file_path = temp_file_path("other.user_index");
+        write_user_index(&dataset, &file_path).unwrap();
+        let bytes = std::fs::read(&file_path).unwrap();
+        let truncated_file_path = temp_file_path("truncated.user_index");
+        std::fs::write(&truncated_file_path, &bytes[..bytes.len() - 1]).unwrap();
+
+        let other_result = UserIndex::open(&file_path, &other);
+        let truncated_result = UserIndex::open(&truncated_file_path, &dataset);
+        std::fs::remove_file(&file_path).unwrap();
+        std::fs::remove_file(&truncated_file_path).unwrap();
+
+        assert!(matches!(other_result, Err(DatasetFormatError::InvalidHeader(_))));
+        assert!(matches!(truncated_result, Err(DatasetFormatError::Truncated { section: "user index", .. })));
+    }
+}
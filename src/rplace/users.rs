@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+
+// user id of the sentinel datapoint at the start of every pixel history
+pub const NO_USER_ID: u32 = 0;
+
+// Assigns dense sequential ids to the hashed user strings in the official csv dump. Ids are
+// handed out in the order users are first seen, so reading the same input twice produces the
+// same ids. Id 0 is reserved for the sentinel datapoints.
+#[derive(Debug, Default)]
+pub struct UserIdDictionary {
+    ids: HashMap<String, u32>,
+}
+
+impl UserIdDictionary {
+    pub fn new() -> UserIdDictionary {
+        UserIdDictionary {
+            ids: HashMap::new(),
+        }
+    }
+
+    pub fn get_or_insert(&mut self, user_hash: &str) -> u32 {
+        if let Some(id) = self.ids.get(user_hash) {
+            return *id;
+        }
+
+        let id = self.ids.len() as u32 + 1;
+        self.ids.insert(user_hash.to_string(), id);
+        id
+    }
+
+    pub fn get(&self, user_hash: &str) -> Option<u32> {
+        self.ids.get(user_hash).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    // writes the side table as "user_id,user_hash" lines, ordered by id
+    pub fn write_to_file(&self, file_path: &str) -> Result<(), Error> {
+        let mut entries: Vec<(&u32, &String)> = self.ids.iter().map(|(hash, id)| (id, hash)).collect();
+        entries.sort();
+
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        writeln!(writer, "user_id,user_hash")?;
+        for (id, hash) in entries {
+            writeln!(writer, "{},{}", id, hash)?;
+        }
+        writer.flush()
+    }
+}
+
+// Maps the ids stored in a dataset back to the original user hashes
+#[derive(Debug, Default)]
+pub struct UserHashTable {
+    hashes: Vec<Option<String>>,
+}
+
+impl UserHashTable {
+    pub fn read_from_file(file_path: &str) -> Result<UserHashTable, Error> {
+        let reader = BufReader::new(File::open(file_path)?);
+        let mut hashes = Vec::new();
+        for (line_idx, line) in reader.lines().enumerate().skip(1) {
+            let line = line?;
+            let (id, hash) = match line.split_once(',') {
+                Some((id, hash)) => (id.parse::<usize>(), hash),
+                None => return Err(Error::new(ErrorKind::InvalidData, format!("line {} of {} is not a user_id,user_hash pair", line_idx + 1, file_path))),
+            };
+
+            let id = match id {
+                Ok(id) => id,
+                Err(_) => return Err(Error::new(ErrorKind::InvalidData, format!("line {} of {} has an invalid user id", line_idx + 1, file_path))),
+            };
+
+            if id >= hashes.len() {
+                hashes.resize(id + 1, None);
+            }
+            hashes[id] = Some(hash.to_string());
+        }
+
+        Ok(UserHashTable {
+            hashes,
+        })
+    }
+
+    pub fn get(&self, user_id: u32) -> Option<&str> {
+        match self.hashes.get(user_id as usize) {
+            Some(Some(hash)) => Some(hash.as_str()),
+            _ => None,
+        }
+    }
+
+    // linear scan, only meant for looking up a single user
+    pub fn find(&self, user_hash: &str) -> Option<u32> {
+        self.hashes.iter().position(|hash| hash.as_deref() == Some(user_hash)).map(|id| id as u32)
+    }
+}
+
+// side table that is written next to a dataset
+pub fn user_table_file_path(dataset_file_path: &str) -> String {
+    format!("{}.users", dataset_file_path)
+}
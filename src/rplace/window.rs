@@ -1,20 +1,45 @@
-use image::RgbImage;
 use min_max::{min, max};
-use speedy2d::image::{ImageDataType, ImageSmoothingMode, ImageHandle};
+use speedy2d::image::{ImageDataType, ImageSmoothingMode};
 use time::OffsetDateTime;
+use std::collections::HashSet;
+use std::fmt;
 use std::time::Instant;
 
 use super::display::GraphicsHelper;
-use super::pixel::PixelColor;
+use super::inspector::PixelInspection;
 use std::process::exit;
 use speedy2d::color::Color;
 use speedy2d::dimen::{Vector2, Vec2, UVec2};
+use speedy2d::font::{Font, TextLayout, TextOptions};
 use speedy2d::shape::Rectangle;
 use speedy2d::Graphics2D;
 use speedy2d::window::{WindowHandler, WindowHelper, VirtualKeyCode, MouseScrollDistance};
 use super::canvas::Canvas;
-
-static mut IMAGE: [u8; 2000*2000*3] = [0; 2000*2000*3];
+use super::framebuffer::{FrameBuffer, RenderMode};
+use super::heatmap::{Heatmap, HeatmapScale, HeatmapStyle};
+use super::render::CanvasRegion;
+use super::reader::custom::DatasetFormatError;
+use super::tiles::color_rgb;
+
+// size of the inspector panel in display pixels
+const INSPECTOR_SWATCH_WIDTH: f32 = 16.0;
+const INSPECTOR_ROW_HEIGHT: f32 = 16.0;
+const INSPECTOR_FONT_SIZE: f32 = 12.0;
+const INSPECTOR_MARGIN: f32 = 16.0;
+const INSPECTOR_OUTLINE_THICKNESS: f32 = 2.0;
+
+// DejaVu Sans Mono, see assets/fonts/LICENSE-DejaVu.txt
+const INSPECTOR_FONT: &[u8] = include_bytes!("../../assets/fonts/DejaVuSansMono.ttf");
+
+// speedy2d fonts do not implement Debug
+#[derive(Clone)]
+struct InspectorFont(Font);
+
+impl fmt::Debug for InspectorFont {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "InspectorFont")
+    }
+}
 
 #[derive(Debug, Copy, Clone)]
 pub struct SelectionRegion {
@@ -35,20 +60,35 @@ pub struct RedditPlaceWindowHandler {
     scroll_direction: f64,
     realtime_redraw_rectangle_threshold: u32,
     selection_region: Option<SelectionRegion>,
+
+    frame_buffer: FrameBuffer,
+
+    // digits of a user id while it is being typed, see VirtualKeyCode::I
+    user_id_input: Option<String>,
+
+    // edits of the last clicked pixel, drawn as a panel until it is closed with VirtualKeyCode::X
+    inspected_pixel: Option<PixelInspection>,
+    inspector_font: InspectorFont,
+
+    // the heatmap counts the edits between this timestamp and the viewed one, see VirtualKeyCode::E
+    heatmap_start_timestamp: u64,
+    heatmap_style: HeatmapStyle,
 }
 
 impl RedditPlaceWindowHandler {
-    pub fn new(file_path: &str, size: usize) -> RedditPlaceWindowHandler {
+    pub fn new(file_path: &str) -> Result<RedditPlaceWindowHandler, DatasetFormatError> {
         let start_time = Instant::now();
 
-        let canvas = Canvas::new_with_file_path(file_path);
+        let canvas = Canvas::new_with_file_path(file_path)?;
 
         let duration = start_time.elapsed();
         println!("RedditPlaceWindowHandler init time {:?}", duration);
-        RedditPlaceWindowHandler::new_with_canvas(canvas)
+        Ok(RedditPlaceWindowHandler::new_with_canvas(canvas))
     }
 
     pub fn new_with_canvas(canvas: Canvas) -> RedditPlaceWindowHandler {
+        let frame_buffer = FrameBuffer::new(&canvas);
+        let heatmap_start_timestamp = canvas.min_timestamp;
         let graphics_helper = GraphicsHelper::new(canvas);
 
         RedditPlaceWindowHandler { 
@@ -64,6 +104,12 @@ impl RedditPlaceWindowHandler {
             scroll_direction: 1.0,
             realtime_redraw_rectangle_threshold: 320000,
             selection_region: None,
+            frame_buffer,
+            user_id_input: None,
+            inspected_pixel: None,
+            inspector_font: InspectorFont(Font::new(INSPECTOR_FONT).expect("bundled font can be loaded")),
+            heatmap_start_timestamp,
+            heatmap_style: HeatmapStyle::default(),
         }
     }
 }
@@ -80,9 +126,17 @@ impl WindowHandler for RedditPlaceWindowHandler
         self.graphics_helper.display_size = *info.viewport_size_pixels();
         self.graphics_helper.scale_factor = info.scale_factor() as f32;
 
-        let canvas_display_size = min!(self.graphics_helper.display_width(), self.graphics_helper.display_height()) as f32;
-        self.graphics_helper.canvas.top_left = Vector2::new_x((self.graphics_helper.display_width() as f32 - canvas_display_size) / 2.0);
-        self.graphics_helper.canvas.pixel_size = canvas_display_size / self.graphics_helper.canvas.pixels.len() as f32; // * 2.0; // NOTE: only multiplying by 2 because at the start we only need to display 1000x1000 
+        // fit the whole canvas on the display and center it
+        let display_width = self.graphics_helper.display_width() as f32;
+        let display_height = self.graphics_helper.display_height() as f32;
+        let canvas_width = self.graphics_helper.canvas.width() as f32;
+        let canvas_height = self.graphics_helper.canvas.height() as f32;
+        let pixel_size = f32::min(display_width / canvas_width, display_height / canvas_height);
+        self.graphics_helper.canvas.top_left = Vector2::new(
+            (display_width - canvas_width * pixel_size) / 2.0,
+            (display_height - canvas_height * pixel_size) / 2.0
+        );
+        self.graphics_helper.canvas.pixel_size = pixel_size;
 
         println!("display_size={:?}, scale_factor={:?}, top_left={:?}, pixel_size={:?}", self.graphics_helper.display_size, self.graphics_helper.scale_factor, self.graphics_helper.canvas.top_left, self.graphics_helper.canvas.pixel_size);
         println!("WindowHandler size {:?}", std::mem::size_of_val(self));
@@ -194,6 +248,32 @@ impl WindowHandler for RedditPlaceWindowHandler
         ) {
         println!("Detected keyup event {:?} {:?}", virtual_key_code, scancode);
 
+        // typing a user id takes over the keyboard until it is confirmed or cancelled
+        if let Some(user_id_input) = &self.user_id_input {
+            match virtual_key_code {
+                Some(VirtualKeyCode::Return) | Some(VirtualKeyCode::NumpadEnter) => {
+                    match user_id_input.parse::<u32>() {
+                        Ok(user_id) => self.toggle_highlighted_user(user_id),
+                        Err(_) => println!("Invalid user id '{}'", user_id_input),
+                    }
+                    self.user_id_input = None;
+                    helper.request_redraw();
+                },
+                Some(VirtualKeyCode::Escape) => {
+                    println!("Cancelled user id input");
+                    self.user_id_input = None;
+                },
+                Some(VirtualKeyCode::Backspace) => {
+                    if let Some(user_id_input) = &mut self.user_id_input {
+                        user_id_input.pop();
+                        println!("User id: {}", user_id_input);
+                    }
+                },
+                _ => (),
+            }
+            return;
+        }
+
         match virtual_key_code {
             Some(VirtualKeyCode::LControl) | Some(VirtualKeyCode::RControl) => {
                 self.is_ctrl_pressed = false;
@@ -206,7 +286,9 @@ impl WindowHandler for RedditPlaceWindowHandler
                 println!("Center coordinate = {:?}", self.graphics_helper.canvas.center_coordinate());
             },
             Some(VirtualKeyCode::M) => {
-                println!("Mouse position = {:?}", self.mouse_position);
+                let canvas_coordinates = self.graphics_helper.canvas.get_canvas_coordinates(self.mouse_position.x, self.mouse_position.y);
+                let place_coordinates = self.graphics_helper.canvas.place_coordinates(canvas_coordinates.x, canvas_coordinates.y);
+                println!("Mouse position = {:?} | canvas pixel = {:?} | r/place coordinate = {:?}", self.mouse_position, canvas_coordinates, place_coordinates);
             },
             Some(VirtualKeyCode::H) => {
                 println!("{:?}", self);
@@ -219,6 +301,42 @@ impl WindowHandler for RedditPlaceWindowHandler
                 self.scroll_direction *= -1.0;
                 println!("Setting scroll direction to {}", self.scroll_direction);
             },
+            Some(VirtualKeyCode::I) => {
+                println!("Type a user id and press enter to highlight or unhighlight it, escape to cancel");
+                self.user_id_input = Some(String::new());
+            },
+            Some(VirtualKeyCode::U) => {
+                println!("Clearing highlighted users");
+                self.frame_buffer.set_mode(RenderMode::Colors);
+                helper.request_redraw();
+            },
+            Some(VirtualKeyCode::E) => {
+                self.toggle_heatmap();
+                helper.request_redraw();
+            },
+            Some(VirtualKeyCode::B) => {
+                self.heatmap_start_timestamp = self.graphics_helper.canvas.timestamp;
+                println!("Heatmap starts at t={}, press E to show the edits until the viewed time", self.heatmap_start_timestamp);
+            },
+            Some(VirtualKeyCode::G) => {
+                self.heatmap_style.scale = match self.heatmap_style.scale {
+                    HeatmapScale::Linear => HeatmapScale::Log,
+                    HeatmapScale::Log => HeatmapScale::Linear,
+                };
+                println!("Heatmap scale: {:?}", self.heatmap_style.scale);
+                self.restyle_heatmap();
+                helper.request_redraw();
+            },
+            Some(VirtualKeyCode::R) => {
+                self.heatmap_style.ramp = self.heatmap_style.ramp.next_named();
+                println!("Heatmap color ramp: {}", self.heatmap_style.ramp.name);
+                self.restyle_heatmap();
+                helper.request_redraw();
+            },
+            Some(VirtualKeyCode::X) => {
+                self.inspected_pixel = None;
+                helper.request_redraw();
+            },
             Some(VirtualKeyCode::Key0) => {
                 self.graphics_helper.adjust_timestamp_to_day(0);
                 helper.request_redraw();
@@ -243,6 +361,15 @@ impl WindowHandler for RedditPlaceWindowHandler
         }
     }
 
+    fn on_keyboard_char(&mut self, _helper: &mut WindowHelper<()>, unicode_codepoint: char) {
+        if let Some(user_id_input) = &mut self.user_id_input {
+            if unicode_codepoint.is_ascii_digit() {
+                user_id_input.push(unicode_codepoint);
+                println!("User id: {}", user_id_input);
+            }
+        }
+    }
+
     fn on_mouse_wheel_scroll(
             &mut self,
             helper: &mut WindowHelper<()>,
@@ -346,10 +473,22 @@ impl WindowHandler for RedditPlaceWindowHandler
         ) {
         println!("on_mouse_button_down {:?}", button);
 
-        // clear the selected region if mouse is clicked in place
+        // clear the selected region if mouse is clicked in place, ctrl+click highlights the user
+        // that placed the clicked pixel and a plain click inspects the pixel
         if let Some(mouse_pressed_location) = self.is_mouse_pressed {
             if mouse_pressed_location == self.mouse_position {
                 self.selection_region = None;
+
+                // clicks outside of the canvas are ignored instead of picking the closest edge pixel
+                if let Some(canvas_coordinates) = self.graphics_helper.canvas.canvas_pixel_at(self.mouse_position.x, self.mouse_position.y) {
+                    if self.is_ctrl_pressed {
+                        let datapoint = self.graphics_helper.canvas.current_datapoint(canvas_coordinates.x, canvas_coordinates.y);
+                        println!("Pixel {:?} was placed by user {} at {}", canvas_coordinates, datapoint.user_id, datapoint.timestamp);
+                        self.toggle_highlighted_user(datapoint.user_id);
+                    } else if !self.is_shift_pressed {
+                        self.inspect_pixel(canvas_coordinates.x, canvas_coordinates.y);
+                    }
+                }
             }
         }
         self.is_mouse_pressed = None;
@@ -361,7 +500,8 @@ impl WindowHandler for RedditPlaceWindowHandler
     {
         let start_time = Instant::now();
         graphics.clear_screen(Color::from_rgb(0.0, 0.0, 0.0));
-        self.draw_pixels(graphics, None); //, Some(PixelColor::Black));
+        self.draw_pixels(graphics);
+        self.draw_inspector(graphics);
         let duration = start_time.elapsed();
         println!("on_draw duration: {}ms", duration.as_millis());
     }
@@ -372,46 +512,65 @@ impl RedditPlaceWindowHandler {
         return min!(self.graphics_helper.display_width(), self.graphics_helper.display_height()) as f32 / 20.0;
     }
 
-    fn zoom_into_center_of_display(&mut self, pixel_size_diff: f32) {
-        self.graphics_helper.canvas.zoom(pixel_size_diff, self.graphics_helper.display_center_coordinate());
+    // Adds the user to the highlighted users, or removes it if it is already highlighted
+    fn toggle_highlighted_user(&mut self, user_id: u32) {
+        let mut users = match self.frame_buffer.mode() {
+            RenderMode::UserOverlay(users) => users.clone(),
+            _ => HashSet::new(),
+        };
+
+        if !users.remove(&user_id) {
+            users.insert(user_id);
+        }
+        println!("Highlighted users: {:?}", users);
+
+        let mode = if users.is_empty() {
+            RenderMode::Colors
+        } else {
+            RenderMode::UserOverlay(users)
+        };
+        self.frame_buffer.set_mode(mode);
     }
 
-    fn zoom_into_mouse_location(&mut self, pixel_size_diff: f32) {
-        self.graphics_helper.canvas.zoom(pixel_size_diff, self.mouse_position);
+    // Shows the edits between the heatmap start and the viewed time, or goes back to the canvas
+    // colors if the heatmap is already shown
+    fn toggle_heatmap(&mut self) {
+        if let RenderMode::Heatmap(_) = self.frame_buffer.mode() {
+            println!("Hiding heatmap");
+            self.frame_buffer.set_mode(RenderMode::Colors);
+            return;
+        }
+
+        let canvas = &self.graphics_helper.canvas;
+        let start_timestamp = min!(self.heatmap_start_timestamp, canvas.timestamp);
+        let end_timestamp = max!(self.heatmap_start_timestamp, canvas.timestamp);
+        let heatmap = Heatmap::new(&canvas.dataset, CanvasRegion::new_with_canvas(canvas), start_timestamp, end_timestamp, self.heatmap_style.clone());
+        println!("Showing heatmap of t={}..{} | max {} edits per pixel", start_timestamp, end_timestamp, heatmap.max_count);
+        self.frame_buffer.set_mode(RenderMode::Heatmap(heatmap));
     }
 
-    fn get_image(&mut self, x1: usize, x2: usize, y1: usize, y2: usize) -> &mut [u8] {
-        let x_width = x2 - x1;
-        let y_height = y2 - y1;
+    // redraws a shown heatmap with the current style without counting again
+    fn restyle_heatmap(&mut self) {
+        self.frame_buffer.set_heatmap_style(&self.heatmap_style);
+    }
 
-        {
-            // Debug prints 
-            let total_canvas_pixels = self.graphics_helper.num_rectangles_to_redraw();
-            let total_display_pixels = total_canvas_pixels as f32 * self.graphics_helper.canvas.pixel_size;
-            println!("Drawing pixels between x={}..{}, y={}..{} | # canvas px: {} | # display px {} | px size {}", 
-                x1, x2, y1, y2, total_canvas_pixels, total_display_pixels, self.graphics_helper.canvas.pixel_size);
+    // Prints every edit of the pixel and keeps them for the inspector panel
+    fn inspect_pixel(&mut self, x: u32, y: u32) {
+        let canvas = &self.graphics_helper.canvas;
+        let inspection = PixelInspection::new(&canvas.dataset, x, y);
+        println!("r/place coordinate = {:?}", canvas.place_coordinates(x, y));
+        if let Err(e) = inspection.write_table(&mut std::io::stdout(), canvas.timestamp, None) {
+            println!("Error: {:?}", e);
         }
+        self.inspected_pixel = Some(inspection);
+    }
 
-        let image_bytes: &mut [u8] = unsafe {
-            IMAGE[0..(x_width * y_height * 3)].as_mut()
-        };
-        println!("Image bytes len: {}, size: ({},{})", image_bytes.len(), x_width, y_height);
-
-        for (image_y, canvas_y) in (y1..y2).into_iter().enumerate() {
-            for (image_x, canvas_x) in (x1..x2).into_iter().enumerate() {
-                let color: u32 = self.graphics_helper.canvas.pixels[canvas_y][canvas_x].color.into();
-                let r = (color >> 16 & 0xff) as u8;
-                let g = (color >> 8 & 0xff) as u8;
-                let b = (color & 0xff) as u8;
-
-                let idx = ((image_y * x_width) + image_x) * 3;
-                image_bytes[idx] = r;
-                image_bytes[idx + 1] = g;
-                image_bytes[idx + 2] = b;
-            }
-        }
+    fn zoom_into_center_of_display(&mut self, pixel_size_diff: f32) {
+        self.graphics_helper.canvas.zoom(pixel_size_diff, self.graphics_helper.display_center_coordinate());
+    }
 
-        return image_bytes;
+    fn zoom_into_mouse_location(&mut self, pixel_size_diff: f32) {
+        self.graphics_helper.canvas.zoom(pixel_size_diff, self.mouse_position);
     }
 
     fn screenshot(&mut self) {
@@ -429,43 +588,59 @@ impl RedditPlaceWindowHandler {
         } else {
             self.graphics_helper.pixel_index_bounds_2d()
         };
-        let x_width = x2 - x1;
-        let y_height = y2 - y1;
-
-        let image_bytes: &mut [u8] = self.get_image(x1, x2, y1, y2);
-        let mut image = RgbImage::new(x_width as u32, y_height as u32);
-        for y in 0..y_height {
-            for x in 0..x_width {
-                let idx = ((y * x_width) + x) * 3;
-                let r = image_bytes[idx]; 
-                let g = image_bytes[idx + 1];
-                let b = image_bytes[idx + 2];
-                let pixel = image::Rgb([r, g, b]);
-                image.put_pixel(x as u32, y as u32, pixel);
-            } 
-        }
 
+        let image = self.frame_buffer.region_image(&mut self.graphics_helper.canvas, x1, x2, y1, y2);
         match image.save(format!("screenshots/screenshot-{:?}.png", OffsetDateTime::now_utc())) {
             Err(e) => println!("Error: {:?}", e),
             _ => (),
         };
     }
 
-    fn draw_pixels(&mut self, graphics: &mut Graphics2D, ignore_color: Option<PixelColor>) {
+    fn draw_pixels(&mut self, graphics: &mut Graphics2D) {
         let (x1, x2, y1, y2) = self.graphics_helper.pixel_index_bounds_2d();
-        let x_width = x2 - x1;
-        let y_height = y2 - y1;
+        let level = if self.frame_buffer.supports_tiles() {
+            self.graphics_helper.tile_level()
+        } else {
+            0
+        };
+
+        // zoomed out views draw one tile per block of canvas pixels instead of every pixel
+        let (image_size, x1, x2, y1, y2, image_bytes) = if level == 0 {
+            {
+                // Debug prints 
+                let total_canvas_pixels = self.graphics_helper.num_rectangles_to_redraw();
+                let total_display_pixels = total_canvas_pixels as f32 * self.graphics_helper.canvas.pixel_size;
+                println!("Drawing pixels between x={}..{}, y={}..{} | # canvas px: {} | # display px {} | px size {}", 
+                    x1, x2, y1, y2, total_canvas_pixels, total_display_pixels, self.graphics_helper.canvas.pixel_size);
+            }
+
+            let image_size = UVec2::new((x2 - x1) as u32, (y2 - y1) as u32);
+            let image_bytes = self.frame_buffer.region(&mut self.graphics_helper.canvas, x1, x2, y1, y2);
+            (image_size, x1 as u32, x2 as u32, y1 as u32, y2 as u32, image_bytes)
+        } else {
+            let canvas_width = self.graphics_helper.canvas.width();
+            let canvas_height = self.graphics_helper.canvas.height();
+            let tiles = self.graphics_helper.canvas.tiles.level(level);
+            let scale = tiles.scale;
+            let (tx1, tx2) = tiles.tile_range(x1, x2);
+            let (ty1, ty2) = tiles.tile_range(y1, y2);
+
+            // the last row and column of tiles can stick out past the canvas
+            let image_size = UVec2::new(tx2 - tx1, ty2 - ty1);
+            let (x2, y2) = (min!(tx2 * scale, canvas_width), min!(ty2 * scale, canvas_height));
+            println!("Drawing tiles between x={}..{}, y={}..{} | level {} | scale {}", tx1, tx2, ty1, ty2, level, scale);
+            (image_size, tx1 * scale, x2, ty1 * scale, y2, self.frame_buffer.tile_region(tiles, tx1, tx2, ty1, ty2))
+        };
 
-        let image_bytes = self.get_image(x1, x2, y1, y2);
         let image = graphics.create_image_from_raw_pixels(
             ImageDataType::RGB, 
             ImageSmoothingMode::NearestNeighbor, 
-            UVec2::new(x_width as u32, y_height as u32), 
+            image_size, 
             image_bytes,
         );
 
-        let (top_left, _) = self.graphics_helper.canvas.get_rect_bounds(x1 as u32, y1 as u32);
-        let (bottom_right, _) = self.graphics_helper.canvas.get_rect_bounds(x2 as u32, y2 as u32);
+        let (top_left, _) = self.graphics_helper.canvas.get_rect_bounds(x1, y1);
+        let (bottom_right, _) = self.graphics_helper.canvas.get_rect_bounds(x2, y2);
         let rect = Rectangle::new(top_left, bottom_right);
         
         match image {
@@ -495,4 +670,99 @@ impl RedditPlaceWindowHandler {
             graphics.draw_rectangle(rect, color);
         }
     }
-}
\ No newline at end of file
+
+    // Outlines the inspected pixel and draws a panel with the color, time and user of its edits
+    // along the right edge of the display. The panel is centered on the edit that is current at
+    // the canvas timestamp, which is highlighted
+    fn draw_inspector(&self, graphics: &mut Graphics2D) {
+        let inspection = match &self.inspected_pixel {
+            Some(inspection) => inspection,
+            None => return,
+        };
+        let outline_color = Color::WHITE;
+        let text_color = Color::WHITE;
+
+        let (top_left, bottom_right) = self.graphics_helper.canvas.get_rect_bounds(inspection.x, inspection.y);
+        draw_outline(graphics, top_left, bottom_right, outline_color);
+
+        // the title takes up the first row
+        let display_width = self.graphics_helper.display_width() as f32;
+        let display_height = self.graphics_helper.display_height() as f32;
+        let num_rows = max!(1, (((display_height - 2.0 * INSPECTOR_MARGIN) / INSPECTOR_ROW_HEIGHT) as usize).saturating_sub(1));
+        let current_edit = inspection.current_edit(self.graphics_helper.canvas.timestamp);
+        let first_row = current_edit.unwrap_or(0).saturating_sub(num_rows / 2).min(inspection.edits.len().saturating_sub(num_rows));
+        let last_row = min!(first_row + num_rows, inspection.edits.len());
+
+        let title = match current_edit {
+            Some(current_edit) => format!("({}, {}) | edit {} of {}", inspection.x, inspection.y, current_edit + 1, inspection.edits.len()),
+            None => format!("({}, {}) | {} edits, none yet", inspection.x, inspection.y, inspection.edits.len()),
+        };
+        let font = &self.inspector_font.0;
+        let title = font.layout_text(&title, INSPECTOR_FONT_SIZE, TextOptions::new());
+        let rows: Vec<_> = inspection.edits[first_row..last_row].iter()
+            .map(|edit| {
+                let mod_label = if edit.is_mod { " | mod" } else { "" };
+                let label = format!("{} | user {}{}", format_timestamp(edit.timestamp), edit.user_id, mod_label);
+                font.layout_text(&label, INSPECTOR_FONT_SIZE, TextOptions::new())
+            })
+            .collect();
+
+        let text_width = rows.iter().map(|row| row.width()).fold(title.width(), f32::max);
+        let swatch_text_gap = INSPECTOR_MARGIN / 2.0;
+        let left = display_width - INSPECTOR_MARGIN - INSPECTOR_SWATCH_WIDTH - swatch_text_gap - text_width;
+        let panel_left = left - INSPECTOR_MARGIN / 2.0;
+        let panel_right = display_width - INSPECTOR_MARGIN / 2.0;
+        let panel = Rectangle::new(
+            Vector2::new(panel_left, INSPECTOR_MARGIN / 2.0),
+            Vector2::new(panel_right, INSPECTOR_MARGIN * 1.5 + (rows.len() + 1) as f32 * INSPECTOR_ROW_HEIGHT)
+        );
+        graphics.draw_rectangle(panel, Color::from_hex_argb(0xCC000000));
+        graphics.draw_text(Vector2::new(left, INSPECTOR_MARGIN + (INSPECTOR_ROW_HEIGHT - title.height()) / 2.0), text_color, &title);
+
+        let text_left = left + INSPECTOR_SWATCH_WIDTH + swatch_text_gap;
+        for (row, (edit_idx, text)) in (first_row..last_row).zip(rows.iter()).enumerate() {
+            let edit = &inspection.edits[edit_idx];
+            let top = INSPECTOR_MARGIN + (row + 1) as f32 * INSPECTOR_ROW_HEIGHT;
+            if Some(edit_idx) == current_edit {
+                let highlight = Rectangle::new(Vector2::new(panel_left, top), Vector2::new(panel_right, top + INSPECTOR_ROW_HEIGHT));
+                graphics.draw_rectangle(highlight, Color::from_hex_argb(0x66FFFFFF));
+            }
+
+            let [r, g, b] = color_rgb(edit.color);
+            let swatch_top_left = Vector2::new(left, top + 1.0);
+            let swatch_bottom_right = Vector2::new(left + INSPECTOR_SWATCH_WIDTH, top + INSPECTOR_ROW_HEIGHT - 1.0);
+            graphics.draw_rectangle(Rectangle::new(swatch_top_left, swatch_bottom_right), Color::from_int_rgb(r, g, b));
+
+            // mod edits get a red marker on the left of their row
+            if edit.is_mod {
+                let marker = Rectangle::new(
+                    Vector2::new(panel_left, top + 1.0),
+                    Vector2::new(left - 2.0, top + INSPECTOR_ROW_HEIGHT - 1.0)
+                );
+                graphics.draw_rectangle(marker, Color::RED);
+            }
+
+            if Some(edit_idx) == current_edit {
+                draw_outline(graphics, swatch_top_left, swatch_bottom_right, outline_color);
+            }
+            graphics.draw_text(Vector2::new(text_left, top + (INSPECTOR_ROW_HEIGHT - text.height()) / 2.0), text_color, text);
+        }
+    }
+}
+
+// month, day and UTC time of a timestamp in nanoseconds, e.g. 04-01 13:45:07
+fn format_timestamp(timestamp: u64) -> String {
+    match OffsetDateTime::from_unix_timestamp_nanos(timestamp as i128) {
+        Ok(datetime) => format!("{:02}-{:02} {:02}:{:02}:{:02}", datetime.month() as u8, datetime.day(), datetime.hour(), datetime.minute(), datetime.second()),
+        Err(_) => timestamp.to_string(),
+    }
+}
+
+fn draw_outline(graphics: &mut Graphics2D, top_left: Vec2, bottom_right: Vec2, color: Color) {
+    let top_right = Vector2::new(bottom_right.x, top_left.y);
+    let bottom_left = Vector2::new(top_left.x, bottom_right.y);
+    graphics.draw_line(top_left, top_right, INSPECTOR_OUTLINE_THICKNESS, color);
+    graphics.draw_line(top_right, bottom_right, INSPECTOR_OUTLINE_THICKNESS, color);
+    graphics.draw_line(bottom_right, bottom_left, INSPECTOR_OUTLINE_THICKNESS, color);
+    graphics.draw_line(bottom_left, top_left, INSPECTOR_OUTLINE_THICKNESS, color);
+}
@@ -0,0 +1,539 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::time::{Instant, Duration};
+
+use memmap::MmapMut;
+
+use super::data::{CanvasDimensions, RPlaceDatapoint, RPlaceDataReader};
+use super::dataset::{DatasetRecord, EditLogEntry, RPlaceDatasetDatapoint};
+use super::error::{BadRecordCounts, BadRecordPolicy, RPlaceError};
+use super::reader::compressed::encode_history;
+use super::reader::custom::{DatasetHeader, DatasetFormatError, DatasetLayout, PrecompressedDatasetMetadata, SerializedDataset, DATASET_FORMAT_VERSION};
+
+/// Writes datapoints into the custom dataset format without holding the dataset in memory.
+///
+/// The source is read twice. The first pass counts the datapoints of every pixel so that the
+/// history offsets are known up front, the second pass scatters each datapoint directly into
+/// its slot in a memory mapped output file. Memory use only depends on the canvas size.
+/// Sources should produce datapoints in timestamp order. Pixel histories that receive datapoints
+/// out of order (e.g. from unsorted csv shards) are sorted after the second pass.
+/// Bad records (including datapoints outside of the canvas) are handled by the bad record policy
+/// in the first pass and dropped in the second.
+/// The compressed layout is written in the records layout to a temporary file first, which is
+/// then encoded history by history.
+/// The edit log is built last by merging the sorted pixel histories.
+pub struct DatasetWriter {
+    dimensions: CanvasDimensions,
+    print_frequency: u64,
+    bad_record_policy: BadRecordPolicy,
+    layout: DatasetLayout,
+}
+
+impl DatasetWriter {
+    /// Writer for a canvas of `dimensions` with the default bad record policy and layout.
+    pub fn new(dimensions: CanvasDimensions) -> DatasetWriter {
+        DatasetWriter {
+            dimensions,
+            print_frequency: 1_000_000,
+            bad_record_policy: BadRecordPolicy::default(),
+            layout: DatasetLayout::default(),
+        }
+    }
+
+    /// Number of datapoints between progress messages.
+    pub fn with_print_frequency(mut self, print_frequency: u64) -> DatasetWriter {
+        self.print_frequency = print_frequency;
+        self
+    }
+
+    /// Policy for datapoints that can't be converted, see `BadRecordPolicy`.
+    pub fn with_bad_record_policy(mut self, bad_record_policy: BadRecordPolicy) -> DatasetWriter {
+        self.bad_record_policy = bad_record_policy;
+        self
+    }
+
+    /// Layout of the data section, see `DatasetLayout`.
+    pub fn with_layout(mut self, layout: DatasetLayout) -> DatasetWriter {
+        self.layout = layout;
+        self
+    }
+
+    /// Writes the datapoints of `source` to `output_file_path` and returns the header of the new
+    /// dataset. `source` opens the input once per pass, errors while opening it are returned as they are.
+    pub fn write<I, F>(&self, output_file_path: &str, source: F) -> Result<DatasetHeader, RPlaceError>
+    where F: Fn() -> Result<I, RPlaceError>, I: Iterator<Item = Result<RPlaceDatapoint, RPlaceError>> {
+        if self.layout == DatasetLayout::Compressed {
+            let records_file_path = format!("{}.records.tmp", output_file_path);
+            let writer = DatasetWriter {
+                layout: DatasetLayout::Records,
+                ..*self
+            };
+            writer.write(&records_file_path, source)?;
+
+            let result = SerializedDataset::new(&records_file_path)
+                .map_err(RPlaceError::from)
+                .and_then(|dataset| write_compressed(&dataset, output_file_path));
+            fs::remove_file(&records_file_path)?;
+            return result;
+        }
+
+        let start_time = Instant::now();
+        let (history_lengths, min_timestamp, max_timestamp) = self.count_pass(source()?)?;
+        println!("Finished counting pass (1/2) | duration {:?}", start_time.elapsed());
+
+        let datapoint_size = DatasetHeader::expected_datapoint_size(DATASET_FORMAT_VERSION, self.layout);
+        let metadata = PrecompressedDatasetMetadata::new_with_history_lengths(self.dimensions, min_timestamp, max_timestamp, datapoint_size, &history_lengths);
+        let metadata_bytes = metadata.to_bytes();
+        let num_datapoints = history_lengths.iter().map(|length| *length as u64).sum();
+        let header = DatasetHeader::new_with_layout(self.dimensions, num_datapoints, metadata_bytes.len() as u64, self.layout);
+        drop(history_lengths);
+
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(output_file_path)?;
+        file.set_len(header.file_length())?;
+        let mut mmap = unsafe { MmapMut::map_mut(&file)? };
+
+        let header_bytes = header.to_bytes();
+        mmap[0..header_bytes.len()].copy_from_slice(&header_bytes);
+        let metadata_start = header.metadata_offset as usize;
+        mmap[metadata_start..metadata_start + metadata_bytes.len()].copy_from_slice(&metadata_bytes);
+        drop(metadata_bytes);
+
+        self.scatter_pass(source()?, &header, &metadata, &mut mmap)?;
+
+        let edit_log_start = Instant::now();
+        let (data, edit_log) = mmap.split_at_mut(header.edit_log_offset as usize);
+        let mut position = 0;
+        write_edit_log(
+            &header,
+            |pixel| metadata.get(pixel % self.dimensions.width, pixel / self.dimensions.width).length(),
+            |pixel, idx| {
+                let offset = metadata.get(pixel % self.dimensions.width, pixel / self.dimensions.width).offset();
+                let position = header.timestamp_position((offset + idx) as u64).ok_or(DatasetFormatError::UnsupportedLayout(header.layout))?;
+                Ok(DatasetRecord::timestamp_from_bytes(&data[position..]))
+            },
+            |entry| {
+                edit_log[position..position + EditLogEntry::SIZE].copy_from_slice(&entry.to_bytes());
+                position += EditLogEntry::SIZE;
+                Ok(())
+            },
+        )?;
+        println!("Wrote edit log | duration {:?}", edit_log_start.elapsed());
+        mmap.flush()?;
+
+        println!("Wrote {} datapoints to {} | duration {:?}", header.num_datapoints, output_file_path, start_time.elapsed());
+        Ok(header)
+    }
+
+    // returns the length of every pixel history (including the sentinel) and the timestamp range
+    fn count_pass<I>(&self, source: I) -> Result<(Vec<u32>, u64, u64), RPlaceError> where I: Iterator<Item = Result<RPlaceDatapoint, RPlaceError>> {
+        let mut history_lengths = vec![1u32; self.dimensions.num_pixels()];
+        let mut min_timestamp = u64::MAX;
+        let mut max_timestamp = u64::MIN;
+        let mut total: u64 = 0;
+        let mut bad_records = BadRecordCounts::default();
+        let start_time = Instant::now();
+
+        for (i, result) in source.enumerate() {
+            if (i as u64).is_multiple_of(self.print_frequency) {
+                println!("Pass 1/2 | counted {} datapoints | elapsed {:?}", i, start_time.elapsed());
+            }
+
+            let record = match result {
+                Ok(record) => record,
+                Err(e) => {
+                    self.bad_record_policy.handle(e, &mut bad_records)?;
+                    continue;
+                },
+            };
+
+            let index = match self.dimensions.canvas_index(&record.coordinate) {
+                Some(index) => index,
+                None => {
+                    let error = RPlaceError::OutOfBounds { x: record.coordinate.x as i32, y: record.coordinate.y as i32 };
+                    self.bad_record_policy.handle(error, &mut bad_records)?;
+                    continue;
+                },
+            };
+
+            history_lengths[index.y * self.dimensions.width as usize + index.x] += 1;
+            min_timestamp = min_timestamp.min(record.timestamp);
+            max_timestamp = max_timestamp.max(record.timestamp);
+            total += 1;
+        }
+
+        if total == 0 {
+            return Err(DatasetFormatError::InconsistentSource("source did not produce any datapoints".to_string()).into());
+        }
+
+        let num_datapoints = total + history_lengths.len() as u64;
+        if num_datapoints > u32::MAX as u64 {
+            return Err(DatasetFormatError::InvalidHeader(format!("{} datapoints do not fit in 32 bit history offsets", num_datapoints)).into());
+        }
+
+        println!("Total datapoints: {} | timestamps {}..{}", total, min_timestamp, max_timestamp);
+        if self.bad_record_policy == BadRecordPolicy::Count {
+            println!("Dropped {}", bad_records);
+        }
+
+        // the sentinel is placed right before the first datapoint in the dataset, or at the same
+        // timestamp if the source starts at 0
+        Ok((history_lengths, min_timestamp.saturating_sub(1), max_timestamp))
+    }
+
+    fn scatter_pass<I>(&self, source: I, header: &DatasetHeader, metadata: &PrecompressedDatasetMetadata, mmap: &mut MmapMut) -> Result<(), RPlaceError>
+    where I: Iterator<Item = Result<RPlaceDatapoint, RPlaceError>> {
+        let width = self.dimensions.width as usize;
+
+        // next free datapoint slot for every pixel, starting after the sentinel
+        let mut cursors = Vec::with_capacity(self.dimensions.num_pixels());
+        let mut last_timestamps = vec![metadata.min_timestamp; self.dimensions.num_pixels()];
+        let mut unsorted_histories = vec![false; self.dimensions.num_pixels()];
+        let mut sentinel = RPlaceDatasetDatapoint::start();
+        sentinel.timestamp = metadata.min_timestamp;
+        let sentinel = DatasetRecord::from(&sentinel);
+        for y in 0..self.dimensions.height {
+            for x in 0..self.dimensions.width {
+                let offset = metadata.get(x, y).offset();
+                header.write_record(mmap, offset as u64, &sentinel)?;
+                cursors.push(offset + 1);
+            }
+        }
+
+        // sentinels are not part of the source
+        let total = header.num_datapoints - self.dimensions.num_pixels() as u64;
+        let start_time = Instant::now();
+        for (i, result) in source.enumerate() {
+            if (i as u64).is_multiple_of(self.print_frequency) {
+                let progress = i as f64 / total as f64;
+                let elapsed = start_time.elapsed();
+                let remaining = if progress > 0.0 { elapsed.mul_f64((1.0 - progress) / progress) } else { Duration::ZERO };
+                println!("Pass 2/2 | wrote {} / {} datapoints ({:.1}%) | elapsed {:?} | remaining {:?}", i, total, progress * 100.0, elapsed, remaining);
+            }
+
+            // bad records were already handled by the policy in the first pass
+            let record = match result {
+                Ok(record) => record,
+                Err(e) if e.is_bad_record() => continue,
+                Err(e) => return Err(e),
+            };
+
+            let index = match self.dimensions.canvas_index(&record.coordinate) {
+                Some(index) => index,
+                None => continue,
+            };
+
+            let pixel_idx = index.y * width + index.x;
+            if record.timestamp < last_timestamps[pixel_idx] {
+                unsorted_histories[pixel_idx] = true;
+            }
+            last_timestamps[pixel_idx] = record.timestamp;
+
+            let history = metadata.get(index.x as u32, index.y as u32);
+            let cursor = &mut cursors[pixel_idx];
+            if *cursor >= history.offset() + history.length() {
+                return Err(DatasetFormatError::InconsistentSource(format!("pixel ({}, {}) received more than the {} datapoints counted", index.x, index.y, history.length())).into());
+            }
+
+            header.write_record(mmap, *cursor as u64, &DatasetRecord::from(record))?;
+            *cursor += 1;
+        }
+
+        for y in 0..self.dimensions.height {
+            for x in 0..self.dimensions.width {
+                let history = metadata.get(x, y);
+                if cursors[y as usize * width + x as usize] != history.offset() + history.length() {
+                    return Err(DatasetFormatError::InconsistentSource(format!("pixel ({}, {}) received fewer than the {} datapoints counted", x, y, history.length())).into());
+                }
+            }
+        }
+
+        self.sort_histories(&unsorted_histories, header, metadata, mmap)
+    }
+
+    // sorts the histories that were written out of order by timestamp. the sort is stable so 
+    // datapoints with the same timestamp stay in the order the source produced them
+    fn sort_histories(&self, unsorted_histories: &[bool], header: &DatasetHeader, metadata: &PrecompressedDatasetMetadata, mmap: &mut MmapMut) -> Result<(), RPlaceError> {
+        let width = self.dimensions.width as usize;
+        let mut sorted_count = 0;
+
+        for (pixel_idx, _) in unsorted_histories.iter().enumerate().filter(|(_, unsorted)| **unsorted) {
+            let history = metadata.get((pixel_idx % width) as u32, (pixel_idx / width) as u32);
+            let indices = history.offset() as u64..(history.offset() + history.length()) as u64;
+
+            let mut datapoints: Vec<DatasetRecord> = indices.clone()
+                .map(|index| header.read_record(mmap, index))
+                .collect::<Result<_, _>>()?;
+            datapoints.sort_by_key(|datapoint| datapoint.timestamp);
+
+            for (index, datapoint) in indices.zip(datapoints.iter()) {
+                header.write_record(mmap, index, datapoint)?;
+            }
+            sorted_count += 1;
+        }
+
+        if sorted_count > 0 {
+            println!("Sorted {} pixel histories that were not in timestamp order", sorted_count);
+        }
+
+        Ok(())
+    }
+}
+
+// day 2 start: 28_201_610
+// day 3 start: 71_784_347
+// end: 160_808_191
+pub fn write_data_to_file(parquet_dataset_file_path: &str, output_file_path: &str, dimensions: CanvasDimensions, limit: Option<usize>) -> Result<DatasetHeader, RPlaceError> {
+    let limit = limit.unwrap_or(usize::MAX);
+    let writer = DatasetWriter::new(dimensions);
+    writer.write(output_file_path, || {
+        Ok(RPlaceDataReader::new(parquet_dataset_file_path)?.into_iter().take(limit))
+    })
+}
+
+// Re-encodes a dataset of any layout into the compressed layout
+pub fn write_compressed(dataset: &SerializedDataset, output_file_path: &str) -> Result<DatasetHeader, RPlaceError> {
+    let start_time = Instant::now();
+    let dimensions = dataset.dimensions();
+    let mut metadata = dataset.metadata.clone();
+    metadata.datapoint_size = DatasetHeader::expected_datapoint_size(DATASET_FORMAT_VERSION, DatasetLayout::Compressed) as u8;
+    let metadata_bytes = metadata.to_bytes();
+    let mut header = DatasetHeader::new_with_layout(dimensions, dataset.num_datapoints(), metadata_bytes.len() as u64, DatasetLayout::Compressed);
+
+    let mut file = BufWriter::new(File::create(output_file_path)?);
+    file.write_all(&header.to_bytes())?;
+    file.write_all(&metadata_bytes)?;
+
+    // zero padding up to the aligned data section, then a placeholder for the block table
+    let padding = header.data_offset - header.metadata_offset - header.metadata_length;
+    file.write_all(&vec![0u8; (padding + header.block_table_length()) as usize])?;
+
+    let mut block_offsets = Vec::with_capacity(dimensions.num_pixels() + 1);
+    let mut data_length = header.block_table_length();
+    for y in 0..dimensions.height {
+        for x in 0..dimensions.width {
+            let records: Vec<DatasetRecord> = dataset.pixel_history(x, y).iter().map(|datapoint| DatasetRecord::from(&datapoint)).collect();
+            let block = encode_history(&records);
+            file.write_all(&block)?;
+            block_offsets.push(data_length);
+            data_length += block.len() as u64;
+        }
+
+        if y % 100 == 0 {
+            println!("Compressing | row {} / {} | {} bytes | elapsed {:?}", y, dimensions.height, data_length, start_time.elapsed());
+        }
+    }
+    block_offsets.push(data_length);
+
+    header.edit_log_offset = header.edit_log_position(data_length);
+    file.write_all(&vec![0u8; (header.edit_log_offset - header.data_offset - data_length) as usize])?;
+    write_edit_log(
+        &header,
+        |pixel| dataset.datapoint_history_len(pixel % dimensions.width, pixel / dimensions.width) as u32,
+        |pixel, idx| Ok(dataset.datapoint_timestamp_with_xy_and_idx(pixel % dimensions.width, pixel / dimensions.width, idx)),
+        |entry| Ok(file.write_all(&entry.to_bytes())?),
+    )?;
+
+    // the edit log offset is only known now
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header.to_bytes())?;
+    file.seek(SeekFrom::Start(header.data_offset))?;
+    for offset in block_offsets {
+        file.write_all(&offset.to_le_bytes())?;
+    }
+    file.flush()?;
+
+    let uncompressed_length = dataset.num_datapoints() * dataset.header.datapoint_size as u64;
+    println!("Wrote compressed dataset to {} | data {} bytes (uncompressed {} bytes) | duration {:?}", output_file_path, data_length, uncompressed_length, start_time.elapsed());
+    Ok(header)
+}
+
+// Merges the pixel histories, which are each sorted by timestamp, into one list of all edits in 
+// timestamp order and passes every entry to `write`. Sentinels are left out.
+fn write_edit_log<L, T, W>(header: &DatasetHeader, history_length: L, timestamp: T, mut write: W) -> Result<(), RPlaceError>
+where L: Fn(u32) -> u32, T: Fn(u32, u32) -> Result<u64, RPlaceError>, W: FnMut(EditLogEntry) -> Result<(), RPlaceError> {
+    let num_pixels = header.canvas_width * header.canvas_height;
+
+    // the next edit of every pixel that has one left
+    let mut next_edits = BinaryHeap::with_capacity(num_pixels as usize);
+    for pixel in 0..num_pixels {
+        if history_length(pixel) > 1 {
+            next_edits.push(Reverse((timestamp(pixel, 1)?, pixel, 1)));
+        }
+    }
+
+    let mut count = 0;
+    while let Some(Reverse((edit_timestamp, pixel, history_index))) = next_edits.pop() {
+        write(EditLogEntry {
+            timestamp: edit_timestamp,
+            pixel,
+            history_index,
+        })?;
+        count += 1;
+
+        if history_index + 1 < history_length(pixel) {
+            next_edits.push(Reverse((timestamp(pixel, history_index + 1)?, pixel, history_index + 1)));
+        }
+    }
+
+    if count != header.edit_log_length {
+        return Err(DatasetFormatError::InconsistentSource(format!("edit log has {} entries but the header expects {}", count, header.edit_log_length)).into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use speedy2d::dimen::Vec2;
+
+    use super::*;
+    use crate::rplace::pixel::PixelColor;
+    use crate::rplace::reader::custom::COLUMNS_DATAPOINT_SIZE;
+
+    // 3x2 canvas at r/place coordinates (10, 20)..(12, 21)
+    fn dimensions() -> CanvasDimensions {
+        CanvasDimensions::new_with_origin(3, 2, 10, 20)
+    }
+
+    fn datapoint(timestamp: u64, x: i32, y: i32, color: PixelColor) -> RPlaceDatapoint {
+        RPlaceDatapoint {
+            timestamp,
+            user_id: timestamp as u32,
+            color,
+            coordinate: Vec2::new(x as f32, y as f32),
+            is_mod: false,
+        }
+    }
+
+    // out of order both across pixels and within the history of (10, 20), plus one datapoint
+    // outside of the canvas
+    fn datapoints() -> Vec<RPlaceDatapoint> {
+        vec![
+            datapoint(5, 10, 20, PixelColor::Red),
+            datapoint(3, 12, 21, PixelColor::Blue),
+            datapoint(7, 10, 20, PixelColor::Green),
+            datapoint(6, 10, 20, PixelColor::Yellow),
+            datapoint(8, 50, 50, PixelColor::Black),
+        ]
+    }
+
+    fn temp_file_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("rplace-writer-{}-{}", std::process::id(), name)).to_string_lossy().to_string()
+    }
+
+    fn write(writer: DatasetWriter, name: &str, datapoints: &[RPlaceDatapoint]) -> Result<SerializedDataset, RPlaceError> {
+        let file_path = temp_file_path(name);
+        let result = writer.write(&file_path, || Ok(datapoints.iter().copied().map(Ok)));
+        let dataset = result.and_then(|_| Ok(SerializedDataset::new(&file_path)?));
+        let _ = fs::remove_file(&file_path);
+        dataset
+    }
+
+    fn history(dataset: &SerializedDataset, x: u32, y: u32) -> Vec<(u64, PixelColor)> {
+        dataset.pixel_history(x, y).iter().map(|datapoint| (datapoint.timestamp, datapoint.color)).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        for layout in [DatasetLayout::Records, DatasetLayout::Columns, DatasetLayout::Compressed] {
+            let writer = DatasetWriter::new(dimensions()).with_layout(layout);
+            let dataset = write(writer, &format!("{:?}", layout), &datapoints()).unwrap();
+            let sentinel = (2, PixelColor::White);
+
+            assert_eq!(dataset.header.layout, layout);
+            assert_eq!(dataset.dimensions(), dimensions());
+            assert_eq!(dataset.num_datapoints(), 6 + 4);
+            assert_eq!(dataset.timestamp_range(), (2, 7));
+            assert_eq!(history(&dataset, 0, 0), vec![sentinel, (5, PixelColor::Red), (6, PixelColor::Yellow), (7, PixelColor::Green)], "{:?} layout", layout);
+            assert_eq!(history(&dataset, 2, 1), vec![sentinel, (3, PixelColor::Blue)], "{:?} layout", layout);
+            for (x, y) in [(1, 0), (2, 0), (0, 1), (1, 1)] {
+                assert_eq!(history(&dataset, x, y), vec![sentinel], "{:?} layout", layout);
+            }
+
+            assert_eq!(dataset.color_at(0, 0, 6), PixelColor::Yellow);
+            assert_eq!(dataset.pixel_history(0, 0).get(3).user_id, 7);
+            assert_eq!(dataset.verify(BadRecordPolicy::Fail).unwrap().total(), 0);
+        }
+    }
+
+    #[test]
+    fn columns_layout() {
+        let dataset = write(DatasetWriter::new(dimensions()).with_layout(DatasetLayout::Columns), "columns", &datapoints()).unwrap();
+        assert_eq!(dataset.header.datapoint_size, COLUMNS_DATAPOINT_SIZE);
+        assert!(dataset.pixel_records(0, 0).is_none());
+        if cfg!(target_endian = "little") {
+            assert_eq!(dataset.pixel_timestamps(0, 0).unwrap(), &[2, 5, 6, 7]);
+            assert_eq!(dataset.pixel_timestamps(2, 1).unwrap(), &[2, 3]);
+        }
+
+        // the columns hold the same datapoints as the records layout
+        let records = write(DatasetWriter::new(dimensions()), "columns-records", &datapoints()).unwrap();
+        for (x, y) in [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)] {
+            let columns_history: Vec<DatasetRecord> = dataset.pixel_history(x, y).iter().map(|datapoint| DatasetRecord::from(&datapoint)).collect();
+            let records_history: Vec<DatasetRecord> = records.pixel_history(x, y).iter().map(|datapoint| DatasetRecord::from(&datapoint)).collect();
+            assert_eq!(columns_history, records_history, "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn edit_log_order() {
+        // edits at the same timestamp are ordered by pixel
+        let datapoints = [
+            datapoint(5, 10, 20, PixelColor::Red),
+            datapoint(3, 12, 21, PixelColor::Blue),
+            datapoint(7, 10, 20, PixelColor::Green),
+            datapoint(6, 10, 20, PixelColor::Yellow),
+            datapoint(6, 12, 21, PixelColor::Red),
+            datapoint(6, 11, 20, PixelColor::Black),
+        ];
+        let expected: Vec<(u64, u32, u32)> = vec![(3, 5, 1), (5, 0, 1), (6, 0, 2), (6, 1, 1), (6, 5, 2), (7, 0, 3)];
+
+        for layout in [DatasetLayout::Records, DatasetLayout::Columns, DatasetLayout::Compressed] {
+            let writer = DatasetWriter::new(dimensions()).with_layout(layout);
+            let dataset = write(writer, &format!("edit-log-{:?}", layout), &datapoints).unwrap();
+
+            assert_eq!(dataset.edit_log_len(), expected.len(), "{:?} layout", layout);
+            let edits: Vec<(u64, u32, u32)> = (0..dataset.edit_log_len())
+                .map(|index| dataset.edit(index).unwrap())
+                .map(|edit| (edit.timestamp, edit.pixel, edit.history_index))
+                .collect();
+            assert_eq!(edits, expected, "{:?} layout", layout);
+            assert_eq!(dataset.edit(expected.len()), None);
+
+            for index in 0..dataset.edit_log_len() {
+                let edit = dataset.edit(index).unwrap();
+                let (x, y) = edit.coordinate(dataset.header.canvas_width);
+                assert_eq!(dataset.pixel_history(x, y).timestamp(edit.history_index as usize), edit.timestamp);
+            }
+
+            if cfg!(target_endian = "little") {
+                assert_eq!(dataset.edits(1..3).unwrap(), &[dataset.edit(1).unwrap(), dataset.edit(2).unwrap()]);
+            }
+            assert!(dataset.edits(3..3).is_none());
+            assert!(dataset.edits(2..expected.len() + 1).is_none());
+
+            let indices_after: Vec<usize> = [0, 3, 5, 6, 7].iter().map(|timestamp| dataset.edit_index_after(*timestamp)).collect();
+            assert_eq!(indices_after, vec![0, 1, 2, 5, 6], "{:?} layout", layout);
+        }
+    }
+
+    #[test]
+    fn bad_records() {
+        let writer = DatasetWriter::new(dimensions()).with_bad_record_policy(BadRecordPolicy::Fail);
+        assert!(matches!(write(writer, "fail", &datapoints()), Err(RPlaceError::OutOfBounds { x: 50, y: 50 })));
+
+        let writer = DatasetWriter::new(dimensions()).with_bad_record_policy(BadRecordPolicy::Skip);
+        assert_eq!(write(writer, "skip", &datapoints()).unwrap().num_datapoints(), 6 + 4);
+
+        let writer = DatasetWriter::new(dimensions());
+        assert!(matches!(write(writer, "empty", &datapoints()[4..]), Err(RPlaceError::Format(DatasetFormatError::InconsistentSource(_)))));
+    }
+
+    #[test]
+    fn sentinel_at_timestamp_0() {
+        let dataset = write(DatasetWriter::new(dimensions()), "zero", &[datapoint(0, 11, 20, PixelColor::Red)]).unwrap();
+        assert_eq!(history(&dataset, 1, 0), vec![(0, PixelColor::White), (0, PixelColor::Red)]);
+    }
+}